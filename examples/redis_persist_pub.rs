@@ -41,11 +41,19 @@
 
 use std::{env, process};
 
-use paho_mqtt as mqtt;
-use paho_mqtt_redis::RedisPersistence;
+use paho_mqtt_redis::{mqtt, RedisPersistence};
 
 // --------------------------------------------------------------------------
 
+/// Determines if the error is the one the client gives for a failure of
+/// the persistence store, which differs between the Paho versions.
+fn is_persistence_error(err: &mqtt::Error) -> bool {
+    #[cfg(feature = "paho-0_13")]
+    return matches!(err, mqtt::Error::PersistenceError);
+    #[cfg(not(feature = "paho-0_13"))]
+    matches!(err, mqtt::Error::Paho(-2))
+}
+
 fn main() {
     // Use the environment logger for this example.
     env_logger::init();
@@ -66,12 +74,11 @@ fn main() {
         .finalize();
 
     let cli = mqtt::AsyncClient::new(create_opts).unwrap_or_else(|err| {
-        match err {
-            mqtt::Error::Paho(-2 /*mqtt::PERSISTENCE_ERROR*/) => {
-                eprintln!("Error connecting to the local Redis server. Is it running?")
-            }
-            _ => eprintln!("Error creating the client: {:?}", err),
-        };
+        if is_persistence_error(&err) {
+            eprintln!("Error connecting to the local Redis server. Is it running?")
+        } else {
+            eprintln!("Error creating the client: {:?}", err)
+        }
         process::exit(2);
     });
 
@@ -0,0 +1,1286 @@
+// mqtt.rust.redis/src/adapter.rs
+//
+// Internal adapter for the redis-rs client calls.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Internal adapter for the redis-rs client calls.
+//!
+//! The store only needs a handful of Redis commands, and they are all
+//! issued through the functions in this module. That keeps any
+//! differences between versions of the `redis` crate in one place, so the
+//! crate can accept a range of versions and unify with whichever one the
+//! application already uses, rather than forcing a second copy into the
+//! build.
+
+use crate::latency::{LatencyEvent, SlowlogEntry};
+#[cfg(feature = "tokio")]
+use redis::aio::ConnectionManager;
+#[cfg(all(feature = "async", not(feature = "tokio")))]
+use redis::aio::MultiplexedConnection;
+#[cfg(feature = "cluster")]
+use redis::cluster::{ClusterClient, ClusterClientBuilder, ClusterConnection};
+use redis::{
+    Client, Cmd, Connection, ConnectionAddr, ConnectionInfo, ConnectionLike, ErrorKind,
+    IntoConnectionInfo, RedisConnectionInfo, RedisError, RedisResult, ToRedisArgs, Value,
+};
+use std::{collections::HashSet, path::Path, time::Duration};
+
+/// Creates a Redis client for the server at the URL.
+pub fn open_client(url: &str) -> RedisResult<Client> {
+    Client::open(url)
+}
+
+/// Gets the connection info for the server at the URL, with the database
+/// number, ACL username, and password, if given, taking the place of any
+/// in the URL.
+pub fn connection_info(
+    url: &str,
+    db: Option<i64>,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> RedisResult<ConnectionInfo> {
+    let mut info = url.into_connection_info()?;
+    if let Some(db) = db {
+        info.redis.db = db;
+    }
+    if let Some(username) = username {
+        info.redis.username = Some(username.to_string());
+    }
+    if let Some(password) = password {
+        info.redis.password = Some(password.to_string());
+    }
+    Ok(info)
+}
+
+/// Creates a Redis client with the connection info.
+pub fn open_client_info(info: ConnectionInfo) -> RedisResult<Client> {
+    Client::open(info)
+}
+
+/// Creates a Redis client for a `rediss://` server, with the PEM-encoded
+/// CA certificate, and client certificate and key, if given.
+/// If `insecure`, the server's hostname isn't verified.
+#[cfg(feature = "tls")]
+pub fn open_tls_client(
+    mut info: ConnectionInfo,
+    root_cert: Option<Vec<u8>>,
+    client_cert: Option<(Vec<u8>, Vec<u8>)>,
+    insecure: bool,
+) -> RedisResult<Client> {
+    if let ConnectionAddr::TcpTls { insecure: flag, .. } = &mut info.addr {
+        *flag = insecure;
+    }
+    let client_tls = client_cert.map(|(client_cert, client_key)| redis::ClientTlsConfig {
+        client_cert,
+        client_key,
+    });
+    Client::build_with_tls(
+        info,
+        redis::TlsCertificates {
+            client_tls,
+            root_cert,
+        },
+    )
+}
+
+/// Creates a Redis client for the server listening on the Unix socket.
+pub fn unix_client(path: &Path) -> RedisResult<Client> {
+    Client::open(ConnectionInfo {
+        addr: ConnectionAddr::Unix(path.to_path_buf()),
+        redis: RedisConnectionInfo::default(),
+    })
+}
+
+/// Gets a URL for the server of the client, without its credentials, for
+/// logging and for reconnecting to the same address.
+pub fn client_url(client: &Client) -> String {
+    let info = client.get_connection_info();
+    match &info.addr {
+        ConnectionAddr::Unix(path) => {
+            format!("redis+unix://{}?db={}", path.display(), info.redis.db)
+        }
+        addr @ ConnectionAddr::TcpTls { .. } => format!("rediss://{}/{}", addr, info.redis.db),
+        addr => format!("redis://{}/{}", addr, info.redis.db),
+    }
+}
+
+/// Gets the database number and password that the client connects with.
+pub fn client_auth(client: &Client) -> (i64, Option<String>, Option<String>) {
+    let info = client.get_connection_info();
+    (
+        info.redis.db,
+        info.redis.username.clone(),
+        info.redis.password.clone(),
+    )
+}
+
+/// Gets the ACL user that the client authenticates as, which is the
+/// `default` user if none is given.
+pub fn client_user(client: &Client) -> String {
+    client
+        .get_connection_info()
+        .redis
+        .username
+        .clone()
+        .unwrap_or_else(|| "default".to_string())
+}
+
+/// Determines if the error is the server refusing the credentials, or
+/// asking for some, rather than a failure of the connection or command.
+pub fn is_auth_error(e: &RedisError) -> bool {
+    e.kind() == ErrorKind::AuthenticationFailed
+        || matches!(e.code(), Some("NOAUTH") | Some("WRONGPASS"))
+}
+
+/// Opens a new connection to the server.
+pub fn connect(client: &Client) -> RedisResult<Connection> {
+    client.get_connection()
+}
+
+/// Sets the `key` in hash `name` to the value, and stamps the `meta` hash
+/// with the `writer`, all in a single transaction. If given, the `info`
+/// side hash gets its record for the key in the same transaction.
+/// Returns the previous writer stamp, if any, and the number of keys that
+/// were added to the hash: one for a new key, or zero for a replacement.
+pub fn stamped_hset(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    key: &str,
+    value: &[u8],
+    meta: &str,
+    (writer_field, writer): (&str, &str),
+    info: Option<(&str, &str)>,
+) -> RedisResult<(Option<String>, usize)> {
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .hget(meta, writer_field)
+        .hset(name, key, value)
+        .hset(meta, writer_field, writer)
+        .ignore();
+    if let Some((info, record)) = info {
+        pipe.hset(info, key, record).ignore();
+    }
+    pipe.query(conn)
+}
+
+/// Script to put a value, with a writer stamp, only if the key isn't in
+/// the hash already. The stamp is left alone if the value wasn't put.
+pub(crate) const STAMPED_HSETNX: &str = r"
+local added = redis.call('HSETNX', KEYS[1], ARGV[1], ARGV[2])
+if added == 0 then
+    return {false, 0}
+end
+local prev = redis.call('HGET', KEYS[2], ARGV[3])
+redis.call('HSET', KEYS[2], ARGV[3], ARGV[4])
+return {prev, added}
+";
+
+/// Like [`stamped_hset()`], but only sets the `key` if it isn't in the
+/// hash already, with a single script, so that two clients sharing the
+/// store can't overwrite each other's value.
+/// Returns the previous writer stamp, if any, and the number of keys that
+/// were added to the hash: one if the value was put, or zero if the key
+/// was already there.
+pub fn stamped_hsetnx(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    key: &str,
+    value: &[u8],
+    meta: &str,
+    writer_field: &str,
+    writer: &str,
+) -> RedisResult<(Option<String>, usize)> {
+    redis::Script::new(STAMPED_HSETNX)
+        .key(name)
+        .key(meta)
+        .arg(key)
+        .arg(value)
+        .arg(writer_field)
+        .arg(writer)
+        .invoke(conn)
+}
+
+/// Script to put a value in a string key of its own, with a writer
+/// stamp, and an expiry, in milliseconds, if ARGV[4] isn't zero. With
+/// ARGV[5] set, a key that's there already is left alone, as by SETNX.
+/// The key is added to the index set, KEYS[3].
+pub(crate) const STAMPED_SET: &str = r"
+local added = 1 - redis.call('EXISTS', KEYS[1])
+if added == 0 and ARGV[5] == '1' then
+    return {false, 0}
+end
+if ARGV[4] ~= '0' then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[4])
+else
+    redis.call('SET', KEYS[1], ARGV[1])
+end
+redis.call('SADD', KEYS[3], KEYS[1])
+local prev = redis.call('HGET', KEYS[2], ARGV[2])
+redis.call('HSET', KEYS[2], ARGV[2], ARGV[3])
+return {prev, added}
+";
+
+/// Sets the string key `entry` to the value, expiring after the `ttl`, if
+/// any, adds it to the `index` set, and stamps the `meta` hash with the
+/// `writer`, in a single script.
+/// If `nx` is set, the key is only set if it doesn't exist.
+/// Returns the previous writer stamp, if any, and the number of entries
+/// that were added: one for a new key, or zero for a replacement, or a
+/// key that was left alone.
+pub fn stamped_set(
+    conn: &mut dyn ConnectionLike,
+    (entry, index): (&str, &str),
+    value: &[u8],
+    ttl: Option<Duration>,
+    nx: bool,
+    meta: &str,
+    (writer_field, writer): (&str, &str),
+) -> RedisResult<(Option<String>, usize)> {
+    let ms = ttl.map_or(0, |ttl| {
+        u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX).max(1)
+    });
+    redis::Script::new(STAMPED_SET)
+        .key(entry)
+        .key(meta)
+        .key(index)
+        .arg(value)
+        .arg(writer_field)
+        .arg(writer)
+        .arg(ms)
+        .arg(u8::from(nx))
+        .invoke(conn)
+}
+
+/// Script to put a value, with a writer stamp, only if its sequence
+/// number is newer than the last one applied to the store.
+pub(crate) const SEQUENCED_HSET: &str = r"
+local last = tonumber(redis.call('HGET', KEYS[2], ARGV[4]) or '0')
+if last >= tonumber(ARGV[5]) then
+    return false
+end
+local prev = redis.call('HGET', KEYS[2], ARGV[2])
+local added = redis.call('HSET', KEYS[1], ARGV[1], ARGV[3])
+redis.call('HSET', KEYS[2], ARGV[2], ARGV[6])
+redis.call('HSET', KEYS[2], ARGV[4], ARGV[5])
+return {prev, added}
+";
+
+/// Script to put a value as separate segments, with a writer stamp. The
+/// key's field gets the header with the number of segments, and any left
+/// over from a longer value before are deleted.
+pub(crate) const SEGMENTED_HSET: &str = r"
+local old = redis.call('HGET', KEYS[1], ARGV[1])
+local n = #ARGV - 3
+if old and string.sub(old, 1, 5) == '\0seg:' then
+    for i = n, (tonumber(string.sub(old, 6)) or 0) - 1 do
+        redis.call('HDEL', KEYS[1], ARGV[1] .. ':' .. i)
+    end
+end
+local prev = redis.call('HGET', KEYS[2], ARGV[2])
+local added = redis.call('HSET', KEYS[1], ARGV[1], '\0seg:' .. n)
+for i = 1, n do
+    redis.call('HSET', KEYS[1], ARGV[1] .. ':' .. (i - 1), ARGV[3 + i])
+end
+redis.call('HSET', KEYS[2], ARGV[2], ARGV[3])
+return {prev, added}
+";
+
+/// Script to finish a chunked put, after the chunks were written to their
+/// own fields. The key's field gets the header with the number of chunks,
+/// the writer is stamped, and any chunks left over from a longer value
+/// before are deleted.
+pub(crate) const CHUNKED_COMMIT: &str = r"
+local old = redis.call('HGET', KEYS[1], ARGV[1])
+local n = tonumber(ARGV[2])
+if old and string.sub(old, 1, 5) == '\0seg:' then
+    for i = n, (tonumber(string.sub(old, 6)) or 0) - 1 do
+        redis.call('HDEL', KEYS[1], ARGV[1] .. ':' .. i)
+    end
+end
+local prev = redis.call('HGET', KEYS[2], ARGV[3])
+local added = redis.call('HSET', KEYS[1], ARGV[1], '\0seg:' .. n)
+redis.call('HSET', KEYS[2], ARGV[3], ARGV[4])
+return {prev, added}
+";
+
+/// Script to delete a key from a hash, along with its segments, if its
+/// value was put as segments.
+pub(crate) const SEGMENTED_HDEL: &str = r"
+local old = redis.call('HGET', KEYS[1], ARGV[1])
+if old and string.sub(old, 1, 5) == '\0seg:' then
+    for i = 0, (tonumber(string.sub(old, 6)) or 0) - 1 do
+        redis.call('HDEL', KEYS[1], ARGV[1] .. ':' .. i)
+    end
+end
+return redis.call('HDEL', KEYS[1], ARGV[1])
+";
+
+/// Script to delete keys from a hash only if the sequence number is newer
+/// than the last one applied to the store. The keys are deleted in slices,
+/// since Lua can only unpack several thousand values onto its stack, and a
+/// batch of removes can be larger than that.
+pub(crate) const SEQUENCED_HDEL: &str = r"
+local last = tonumber(redis.call('HGET', KEYS[2], ARGV[1]) or '0')
+if last >= tonumber(ARGV[2]) then
+    return false
+end
+redis.call('HSET', KEYS[2], ARGV[1], ARGV[2])
+local n = 0
+for i = 3, #ARGV, 1000 do
+    n = n + redis.call('HDEL', KEYS[1], unpack(ARGV, i, math.min(i + 999, #ARGV)))
+end
+return n
+";
+
+/// Script to move the entries of one hash into another, keeping the
+/// values already in the destination. The destination is renamed into
+/// place if it doesn't exist yet.
+pub(crate) const MERGE_HASH: &str = r"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return 0
+end
+if redis.call('EXISTS', KEYS[2]) == 0 then
+    local n = redis.call('HLEN', KEYS[1])
+    redis.call('RENAME', KEYS[1], KEYS[2])
+    return n
+end
+local entries = redis.call('HGETALL', KEYS[1])
+for i = 1, #entries, 2 do
+    redis.call('HSETNX', KEYS[2], entries[i], entries[i + 1])
+end
+redis.call('DEL', KEYS[1])
+return #entries / 2
+";
+
+/// Moves all the entries of the hash `from` into the hash `to`, and
+/// deletes `from`, in a single script. Any keys already in `to` keep
+/// their values.
+/// Returns the number of entries that were in `from`.
+pub fn merge_hash(conn: &mut dyn ConnectionLike, from: &str, to: &str) -> RedisResult<usize> {
+    redis::Script::new(MERGE_HASH)
+        .key(from)
+        .key(to)
+        .invoke(conn)
+}
+
+/// Like [`stamped_hset()`], but only if the sequence number is newer than
+/// the one in its field of the `meta` hash, which is then updated. The
+/// `writer` and `seq` are each given as the field name and the value.
+/// Returns `None` if the write was skipped, as one that already landed.
+pub fn sequenced_hset(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    key: &str,
+    value: &[u8],
+    meta: &str,
+    (writer_field, writer): (&str, &str),
+    (seq_field, seq): (&str, u64),
+) -> RedisResult<Option<(Option<String>, usize)>> {
+    redis::Script::new(SEQUENCED_HSET)
+        .key(name)
+        .key(meta)
+        .arg(key)
+        .arg(writer_field)
+        .arg(value)
+        .arg(seq_field)
+        .arg(seq)
+        .arg(writer)
+        .invoke(conn)
+}
+
+/// Removes all the `keys` from hash `name` with a single, variadic, HDEL,
+/// but only if `seq` is newer than the sequence number in the `seq_field`
+/// of the `meta` hash, which is then updated.
+/// Returns the number of fields removed, or `None` if the delete was
+/// skipped, as one that already landed.
+pub fn sequenced_hdel(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    keys: &[String],
+    meta: &str,
+    seq_field: &str,
+    seq: u64,
+) -> RedisResult<Option<usize>> {
+    redis::Script::new(SEQUENCED_HDEL)
+        .key(name)
+        .key(meta)
+        .arg(seq_field)
+        .arg(seq)
+        .arg(keys)
+        .invoke(conn)
+}
+
+/// Puts the value for `key` in hash `name` as separate segments, one
+/// field for each, and stamps the `meta` hash with the `writer`, with a
+/// single script, so the buffers are sent as they are, without being
+/// copied into one.
+/// Returns the previous writer stamp, if any, and the number of keys that
+/// were added to the hash: one for a new key, or zero for a replacement.
+pub fn segmented_hset(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    key: &str,
+    segments: &[&[u8]],
+    meta: &str,
+    writer_field: &str,
+    writer: &str,
+) -> RedisResult<(Option<String>, usize)> {
+    let script = redis::Script::new(SEGMENTED_HSET);
+    let mut invocation = script.prepare_invoke();
+    invocation
+        .key(name)
+        .key(meta)
+        .arg(key)
+        .arg(writer_field)
+        .arg(writer);
+    for segment in segments {
+        invocation.arg(*segment);
+    }
+    invocation.invoke(conn)
+}
+
+/// Puts a large value for `key` in hash `name` as chunks, each in a field
+/// of its own, as for segments, and stamps the `meta` hash with the
+/// `writer`. The chunks are written with a pipeline of separate HSETs,
+/// so the server can serve other clients in between, and then a script
+/// sets the header in the key's field.
+///
+/// This isn't atomic: if it stops part way, the key keeps its old value,
+/// but that's a mix of the old and new chunks if the old value was
+/// chunked too.
+/// Returns the previous writer stamp, if any, and the number of keys that
+/// were added to the hash: one for a new key, or zero for a replacement.
+pub fn chunked_hset(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    key: &str,
+    chunks: &[&[u8]],
+    meta: &str,
+    writer_field: &str,
+    writer: &str,
+) -> RedisResult<(Option<String>, usize)> {
+    let mut pipe = redis::pipe();
+    for (i, chunk) in chunks.iter().enumerate() {
+        pipe.hset(name, format!("{}:{}", key, i), *chunk).ignore();
+    }
+    pipe.cmd("EVAL")
+        .arg(CHUNKED_COMMIT)
+        .arg(2)
+        .arg(name)
+        .arg(meta)
+        .arg(key)
+        .arg(chunks.len())
+        .arg(writer_field)
+        .arg(writer);
+    let (res,): ((Option<String>, usize),) = pipe.query(conn)?;
+    Ok(res)
+}
+
+/// Deletes `key` from hash `name`, along with any segments of its value.
+pub fn segmented_hdel(conn: &mut dyn ConnectionLike, name: &str, key: &str) -> RedisResult<usize> {
+    redis::Script::new(SEGMENTED_HDEL)
+        .key(name)
+        .arg(key)
+        .invoke(conn)
+}
+
+/// Gets the sequence number in the `seq_field` of the `meta` hash, or
+/// zero if there isn't one.
+pub fn get_seq(conn: &mut dyn ConnectionLike, meta: &str, seq_field: &str) -> RedisResult<u64> {
+    let seq: Option<u64> = Cmd::hget(meta, seq_field).query(conn)?;
+    Ok(seq.unwrap_or_default())
+}
+
+/// Gets the value of `key` in hash `name` along with the current writer
+/// stamp in the `meta` hash, in a single round trip.
+pub fn stamped_hget(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    key: &str,
+    meta: &str,
+    writer_field: &str,
+) -> RedisResult<(Option<Vec<u8>>, Option<String>)> {
+    redis::pipe()
+        .hget(name, key)
+        .hget(meta, writer_field)
+        .query(conn)
+}
+
+/// Gets the value of the string key `entry` along with the current writer
+/// stamp in the `meta` hash, in a single round trip.
+pub fn stamped_get(
+    conn: &mut dyn ConnectionLike,
+    entry: &str,
+    meta: &str,
+    writer_field: &str,
+) -> RedisResult<(Option<Vec<u8>>, Option<String>)> {
+    redis::pipe()
+        .get(entry)
+        .hget(meta, writer_field)
+        .query(conn)
+}
+
+/// Gets all the keys in hash `name` along with the current writer stamp
+/// in the `meta` hash, in a single round trip.
+pub fn stamped_hkeys(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    meta: &str,
+    writer_field: &str,
+) -> RedisResult<(Vec<String>, Option<String>)> {
+    redis::pipe()
+        .hkeys(name)
+        .hget(meta, writer_field)
+        .query(conn)
+}
+
+/// Gets all the keys in hash `name` with an incremental HSCAN, asking
+/// the server for about `count` at a time, so that a large hash doesn't
+/// block the server the way HKEYS would, along with the current writer
+/// stamp in the `meta` hash.
+/// A scan can turn up a key more than once, so the duplicates are dropped.
+pub fn stamped_hscan_keys(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    meta: &str,
+    writer_field: &str,
+    count: usize,
+) -> RedisResult<(Vec<String>, Option<String>)> {
+    let writer = Cmd::hget(meta, writer_field).query(conn)?;
+    let mut cmd = redis::cmd("HSCAN");
+    cmd.arg(name).cursor_arg(0).arg("COUNT").arg(count.max(1));
+    let mut seen = HashSet::new();
+    let keys = cmd
+        .iter::<(String, redis::Value)>(conn)?
+        .map(|(key, _)| key)
+        .filter(|key| seen.insert(key.clone()))
+        .collect();
+    Ok((keys, writer))
+}
+
+/// Gets the keys in hash `name` that match the glob `pattern`, with an
+/// incremental HSCAN, asking the server for about `count` at a time.
+pub fn hscan_match(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    pattern: &str,
+    count: usize,
+) -> RedisResult<Vec<String>> {
+    let mut cmd = redis::cmd("HSCAN");
+    cmd.arg(name)
+        .cursor_arg(0)
+        .arg("MATCH")
+        .arg(pattern)
+        .arg("COUNT")
+        .arg(count.max(1));
+    let mut seen = HashSet::new();
+    let keys = cmd
+        .iter::<(String, redis::Value)>(conn)?
+        .map(|(key, _)| key)
+        .filter(|key| seen.insert(key.clone()))
+        .collect();
+    Ok(keys)
+}
+
+/// Gets all the keys in hash `name`, without checking the stamp.
+pub fn hkeys(conn: &mut dyn ConnectionLike, name: &str) -> RedisResult<Vec<String>> {
+    Cmd::hkeys(name).query(conn)
+}
+
+/// Removes the `key` from hash `name`.
+/// Returns the number of fields removed.
+pub fn hdel(conn: &mut dyn ConnectionLike, name: &str, key: &str) -> RedisResult<usize> {
+    Cmd::hdel(name, key).query(conn)
+}
+
+/// Removes all the `keys` from hash `name` with a single, variadic, HDEL.
+/// Returns the number of fields removed.
+pub fn hdel_many<K: ToRedisArgs>(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    keys: &[K],
+) -> RedisResult<usize> {
+    Cmd::hdel(name, keys).query(conn)
+}
+
+/// Like [`hdel_many()`], but also removes the records of the `keys` from
+/// the `info` side hash, in the same transaction.
+/// Returns the number of fields removed from hash `name`.
+pub fn hdel_with_info<K: ToRedisArgs>(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    info: &str,
+    keys: &[K],
+) -> RedisResult<usize> {
+    let (n,): (usize,) = redis::pipe()
+        .atomic()
+        .hdel(name, keys)
+        .hdel(info, keys)
+        .ignore()
+        .query(conn)?;
+    Ok(n)
+}
+
+/// Sets the `key` in hash `name` to the string value.
+pub fn hset_str(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    key: &str,
+    value: &str,
+) -> RedisResult<()> {
+    Cmd::hset(name, key, value).query(conn)
+}
+
+/// Sets each of the `fields` in hash `name` to its string value.
+pub fn hset_many(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    fields: &[(&str, &str)],
+) -> RedisResult<()> {
+    Cmd::hset_multiple(name, fields).query(conn)
+}
+
+/// Determines if hash `name` contains the `key`.
+pub fn hexists(conn: &mut dyn ConnectionLike, name: &str, key: &str) -> RedisResult<bool> {
+    Cmd::hexists(name, key).query(conn)
+}
+
+/// Deletes the Redis keys.
+/// Returns the number of keys that were deleted.
+pub fn del(conn: &mut dyn ConnectionLike, keys: &[&str]) -> RedisResult<usize> {
+    Cmd::del(keys).query(conn)
+}
+
+/// Deletes the string keys of the `entries`, and removes them from the
+/// `index` set, in a single transaction.
+/// Returns the number of keys that were deleted.
+pub fn del_indexed(
+    conn: &mut dyn ConnectionLike,
+    entries: &[&str],
+    index: &str,
+) -> RedisResult<usize> {
+    let (n,): (usize,) = redis::pipe()
+        .atomic()
+        .del(entries)
+        .srem(index, entries)
+        .ignore()
+        .query(conn)?;
+    Ok(n)
+}
+
+/// Gets the number of members of the set `name`.
+pub fn scard(conn: &mut dyn ConnectionLike, name: &str) -> RedisResult<usize> {
+    Cmd::scard(name).query(conn)
+}
+
+/// Removes the keys that no longer exist, like those that expired, from
+/// the `index` set of keys.
+/// Returns the number of keys left in the set.
+pub fn prune_index(conn: &mut dyn ConnectionLike, index: &str) -> RedisResult<usize> {
+    let members: Vec<String> = Cmd::smembers(index).query(conn)?;
+    if members.is_empty() {
+        return Ok(0);
+    }
+    let mut pipe = redis::pipe();
+    for member in &members {
+        pipe.exists(member);
+    }
+    let exists: Vec<bool> = pipe.query(conn)?;
+    let gone: Vec<&str> = members
+        .iter()
+        .zip(&exists)
+        .filter(|(_, exists)| !**exists)
+        .map(|(member, _)| member.as_str())
+        .collect();
+    if !gone.is_empty() {
+        Cmd::srem(index, &gone).query::<()>(conn)?;
+    }
+    Ok(members.len() - gone.len())
+}
+
+/// Finds all the keys on the server that match the glob `pattern`.
+pub fn scan_match(conn: &mut dyn ConnectionLike, pattern: &str) -> RedisResult<Vec<String>> {
+    let mut cmd = redis::cmd("SCAN");
+    cmd.cursor_arg(0).arg("MATCH").arg(pattern);
+    let keys = cmd.iter(conn)?.collect();
+    Ok(keys)
+}
+
+/// Gets the value of the string `key`, if it exists.
+pub fn get(conn: &mut dyn ConnectionLike, key: &str) -> RedisResult<Option<Vec<u8>>> {
+    Cmd::get(key).query(conn)
+}
+
+/// Gets the values of the string `keys`, with `None` for the missing ones.
+pub fn mget(conn: &mut dyn ConnectionLike, keys: &[String]) -> RedisResult<Vec<Option<Vec<u8>>>> {
+    redis::cmd("MGET").arg(keys).query(conn)
+}
+
+/// Gets the length of the string `key`, or zero if it doesn't exist.
+pub fn strlen(conn: &mut dyn ConnectionLike, key: &str) -> RedisResult<usize> {
+    Cmd::strlen(key).query(conn)
+}
+
+/// Determines if the `key` exists on the server.
+pub fn exists(conn: &mut dyn ConnectionLike, key: &str) -> RedisResult<bool> {
+    Cmd::exists(key).query(conn)
+}
+
+/// Gets the type of the value stored at `key`, like "hash" or "string".
+pub fn key_type(conn: &mut dyn ConnectionLike, key: &str) -> RedisResult<String> {
+    redis::cmd("TYPE").arg(key).query(conn)
+}
+
+/// Gets all the fields and values in hash `name`.
+pub fn hgetall(conn: &mut dyn ConnectionLike, name: &str) -> RedisResult<Vec<(String, Vec<u8>)>> {
+    Cmd::hgetall(name).query(conn)
+}
+
+/// Serializes the value at `key` with the DUMP command.
+/// Returns `None` if the key doesn't exist.
+pub fn dump(conn: &mut dyn ConnectionLike, key: &str) -> RedisResult<Option<Vec<u8>>> {
+    redis::cmd("DUMP").arg(key).query(conn)
+}
+
+/// Replaces the value at `key` with the serialized `data`, using the
+/// RESTORE command, with no expiry.
+pub fn restore(conn: &mut dyn ConnectionLike, key: &str, data: &[u8]) -> RedisResult<()> {
+    redis::cmd("RESTORE")
+        .arg(key)
+        .arg(0)
+        .arg(data)
+        .arg("REPLACE")
+        .query(conn)
+}
+
+/// Gets the values for the `keys` in hash `name` with HMGET.
+/// The value is `None` for any key that isn't in the hash.
+pub fn hmget(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    keys: &[&str],
+) -> RedisResult<Vec<Option<Vec<u8>>>> {
+    redis::cmd("HMGET").arg(name).arg(keys).query(conn)
+}
+
+/// Sets each of the `keys` in hash `name` to its value, or removes it if
+/// the value is `None`, in a single transaction.
+pub fn hset_or_hdel(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    keys: &[&str],
+    values: &[Option<Vec<u8>>],
+) -> RedisResult<()> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for (key, value) in keys.iter().zip(values) {
+        match value {
+            Some(v) => pipe.hset(name, *key, v.as_slice()).ignore(),
+            None => pipe.hdel(name, *key).ignore(),
+        };
+    }
+    pipe.query(conn)
+}
+
+/// Rewrites hash `name` from scratch with the `entries`, and sets the
+/// `fields` in the `meta` hash, all in a single transaction.
+pub fn rewrite_hash(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    entries: &[(String, Vec<u8>)],
+    meta: &str,
+    fields: &[(&str, String)],
+) -> RedisResult<()> {
+    let mut pipe = redis::pipe();
+    pipe.atomic().del(name).ignore();
+    if !entries.is_empty() {
+        pipe.hset_multiple(name, entries).ignore();
+    }
+    if !fields.is_empty() {
+        pipe.hset_multiple(meta, fields).ignore();
+    }
+    pipe.query(conn)
+}
+
+/// Gets the number of fields in hash `name`.
+pub fn hlen(conn: &mut dyn ConnectionLike, name: &str) -> RedisResult<usize> {
+    Cmd::hlen(name).query(conn)
+}
+
+/// Sets the fields of the hash `name`, replacing the whole hash, in a
+/// single transaction.
+pub fn replace_hash(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    fields: &[(&str, String)],
+) -> RedisResult<()> {
+    redis::pipe()
+        .atomic()
+        .del(name)
+        .ignore()
+        .hset_multiple(name, fields)
+        .ignore()
+        .query(conn)
+}
+
+/// Gets all the keys in each of the hashes, in a single round trip.
+pub fn hkeys_many(
+    conn: &mut dyn ConnectionLike,
+    names: &[String],
+) -> RedisResult<Vec<Vec<String>>> {
+    let mut pipe = redis::pipe();
+    for name in names {
+        pipe.hkeys(name);
+    }
+    pipe.query(conn)
+}
+
+/// The entries of a hash, as read by HGETALL.
+pub type Entries = Vec<(String, Vec<u8>)>;
+
+/// Gets all the entries in each of the hashes, in a single round trip.
+pub fn hgetall_many(conn: &mut dyn ConnectionLike, names: &[String]) -> RedisResult<Vec<Entries>> {
+    let mut pipe = redis::pipe();
+    for name in names {
+        pipe.hgetall(name);
+    }
+    pipe.query(conn)
+}
+
+/// Appends an entry with the `fields` to the `stream`, trimming it to
+/// about `max_len` entries, with an XADD.
+pub fn xadd_capped(
+    conn: &mut dyn ConnectionLike,
+    stream: &str,
+    max_len: usize,
+    fields: &[(&str, String)],
+) -> RedisResult<()> {
+    redis::cmd("XADD")
+        .arg(stream)
+        .arg("MAXLEN")
+        .arg("~")
+        .arg(max_len)
+        .arg("*")
+        .arg(fields)
+        .query(conn)
+}
+
+/// An entry of a stream, with its ID and fields.
+pub type StreamEntry = (String, Vec<(String, String)>);
+
+/// Gets the latest `count` entries of the `stream`, oldest first.
+pub fn xrange_latest(
+    conn: &mut dyn ConnectionLike,
+    stream: &str,
+    count: usize,
+) -> RedisResult<Vec<StreamEntry>> {
+    let entries: Vec<Value> = redis::cmd("XREVRANGE")
+        .arg(stream)
+        .arg("+")
+        .arg("-")
+        .arg("COUNT")
+        .arg(count)
+        .query(conn)?;
+    // Each entry is a pair, which a list of pairs would take as a flat list
+    let mut entries = entries
+        .iter()
+        .map(redis::from_redis_value)
+        .collect::<RedisResult<Vec<_>>>()?;
+    entries.reverse();
+    Ok(entries)
+}
+
+/// Publishes the message on the sharded pub/sub channel.
+pub fn spublish(conn: &mut dyn ConnectionLike, channel: &str, msg: &str) -> RedisResult<usize> {
+    redis::cmd("SPUBLISH").arg(channel).arg(msg).query(conn)
+}
+
+/// Subscribes the connection to the sharded pub/sub channel.
+/// From here on, the connection can only be used to receive messages.
+pub fn ssubscribe(conn: &mut dyn ConnectionLike, channel: &str) -> RedisResult<()> {
+    redis::cmd("SSUBSCRIBE")
+        .arg(channel)
+        .query::<Value>(conn)
+        .map(|_| ())
+}
+
+/// The channel that the server sends the client tracking invalidations
+/// on, to a RESP2 connection that they're redirected to.
+pub const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+/// Gets the ID the server knows the connection by.
+pub fn client_id(conn: &mut dyn ConnectionLike) -> RedisResult<u64> {
+    redis::cmd("CLIENT").arg("ID").query(conn)
+}
+
+/// Subscribes the connection to the pub/sub channel.
+/// From here on, the connection can only be used to receive messages.
+pub fn subscribe(conn: &mut dyn ConnectionLike, channel: &str) -> RedisResult<()> {
+    redis::cmd("SUBSCRIBE")
+        .arg(channel)
+        .query::<Value>(conn)
+        .map(|_| ())
+}
+
+/// Turns on client tracking for the connection, in broadcast mode, for
+/// the keys that start with `prefix`, redirecting the invalidations to
+/// the client with the ID `redirect`. The connection's own writes aren't
+/// reported.
+pub fn client_tracking(
+    conn: &mut dyn ConnectionLike,
+    redirect: u64,
+    prefix: &str,
+) -> RedisResult<()> {
+    redis::cmd("CLIENT")
+        .arg("TRACKING")
+        .arg("ON")
+        .arg("REDIRECT")
+        .arg(redirect)
+        .arg("BCAST")
+        .arg("PREFIX")
+        .arg(prefix)
+        .arg("NOLOOP")
+        .query(conn)
+}
+
+/// Waits for the next reply on a connection subscribed to the channel
+/// for the client tracking invalidations, and gets the keys that were
+/// invalidated, if it's one of those. The keys are empty for a flush of
+/// the whole database.
+pub fn recv_invalidation(conn: &mut Connection) -> RedisResult<Option<Vec<String>>> {
+    let v = conn.recv_response()?;
+    match redis::from_redis_value::<(String, String, Option<Vec<String>>)>(&v) {
+        Ok((kind, channel, keys)) if kind == "message" && channel == INVALIDATE_CHANNEL => {
+            Ok(Some(keys.unwrap_or_default()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Waits for the next reply on a subscribed connection, and gets the
+/// payload if it's a sharded pub/sub message.
+pub fn recv_smessage(conn: &mut Connection) -> RedisResult<Option<String>> {
+    let v = conn.recv_response()?;
+    match redis::from_redis_value::<(String, String, String)>(&v) {
+        Ok((kind, _, payload)) if kind == "smessage" => Ok(Some(payload)),
+        _ => Ok(None),
+    }
+}
+
+/// Opens a new connection to the server, waiting no longer than the
+/// `timeout` to connect.
+pub fn connect_timeout(client: &Client, timeout: Duration) -> RedisResult<Connection> {
+    client.get_connection_with_timeout(timeout)
+}
+
+/// Creates a client for a Redis Cluster, with the URLs of some of its
+/// nodes to find the rest, and the ACL username and password, if given,
+/// taking the place of any in the URLs.
+#[cfg(feature = "cluster")]
+pub fn open_cluster_client(
+    nodes: &[String],
+    username: Option<&str>,
+    password: Option<&str>,
+) -> RedisResult<ClusterClient> {
+    let mut builder = ClusterClientBuilder::new(nodes.to_vec());
+    if let Some(username) = username {
+        builder = builder.username(username.to_string());
+    }
+    if let Some(password) = password {
+        builder = builder.password(password.to_string());
+    }
+    builder.build()
+}
+
+/// Opens the connections to the nodes of a Redis Cluster.
+#[cfg(feature = "cluster")]
+pub fn cluster_connect(client: &ClusterClient) -> RedisResult<ClusterConnection> {
+    client.get_connection()
+}
+
+/// Sets the read and write timeouts on the sockets to all the nodes of a
+/// cluster, or clears them if `None`.
+#[cfg(feature = "cluster")]
+pub fn set_cluster_timeouts(
+    conn: &ClusterConnection,
+    timeout: Option<Duration>,
+) -> RedisResult<()> {
+    conn.set_read_timeout(timeout)?;
+    conn.set_write_timeout(timeout)
+}
+
+/// The connection for the async store.
+/// With tokio, this is a multiplexed connection that makes itself again,
+/// in the background, after it fails.
+#[cfg(feature = "tokio")]
+pub type AsyncConnection = ConnectionManager;
+
+/// The connection for the async store.
+#[cfg(all(feature = "async", not(feature = "tokio")))]
+pub type AsyncConnection = MultiplexedConnection;
+
+/// Opens a new connection to the server, for the async store.
+#[cfg(feature = "async")]
+pub async fn async_connect(client: &Client) -> RedisResult<AsyncConnection> {
+    #[cfg(feature = "tokio")]
+    return ConnectionManager::new(client.clone()).await;
+    #[cfg(not(feature = "tokio"))]
+    client.get_multiplexed_async_connection().await
+}
+
+/// Sets the `key` in hash `name` to the value, over an async connection.
+#[cfg(feature = "async")]
+pub async fn async_hset(
+    conn: &mut AsyncConnection,
+    name: &str,
+    key: &str,
+    value: &[u8],
+) -> RedisResult<usize> {
+    Cmd::hset(name, key, value).query_async(conn).await
+}
+
+/// Gets the value of `key` in hash `name`, over an async connection.
+#[cfg(feature = "async")]
+pub async fn async_hget(
+    conn: &mut AsyncConnection,
+    name: &str,
+    key: &str,
+) -> RedisResult<Option<Vec<u8>>> {
+    Cmd::hget(name, key).query_async(conn).await
+}
+
+/// Gets the values of the `keys` in hash `name`, over an async connection.
+#[cfg(feature = "async")]
+pub async fn async_hmget(
+    conn: &mut AsyncConnection,
+    name: &str,
+    keys: &[&str],
+) -> RedisResult<Vec<Option<Vec<u8>>>> {
+    redis::cmd("HMGET")
+        .arg(name)
+        .arg(keys)
+        .query_async(conn)
+        .await
+}
+
+/// Deletes `key` from hash `name`, along with any segments of its value,
+/// over an async connection.
+#[cfg(feature = "async")]
+pub async fn async_segmented_hdel(
+    conn: &mut AsyncConnection,
+    name: &str,
+    key: &str,
+) -> RedisResult<usize> {
+    redis::Script::new(SEGMENTED_HDEL)
+        .key(name)
+        .arg(key)
+        .invoke_async(conn)
+        .await
+}
+
+/// Deletes `key` from hash `name`, over an async connection.
+#[cfg(feature = "async")]
+pub async fn async_hdel(conn: &mut AsyncConnection, name: &str, key: &str) -> RedisResult<usize> {
+    Cmd::hdel(name, key).query_async(conn).await
+}
+
+/// Gets all the keys in hash `name`, over an async connection.
+#[cfg(feature = "async")]
+pub async fn async_hkeys(conn: &mut AsyncConnection, name: &str) -> RedisResult<Vec<String>> {
+    Cmd::hkeys(name).query_async(conn).await
+}
+
+/// Determines if `key` is in hash `name`, over an async connection.
+#[cfg(feature = "async")]
+pub async fn async_hexists(conn: &mut AsyncConnection, name: &str, key: &str) -> RedisResult<bool> {
+    Cmd::hexists(name, key).query_async(conn).await
+}
+
+/// Deletes the Redis key, over an async connection.
+#[cfg(feature = "async")]
+pub async fn async_del(conn: &mut AsyncConnection, key: &str) -> RedisResult<usize> {
+    Cmd::del(key).query_async(conn).await
+}
+
+/// Ends the session with the server, which then closes the connection.
+pub fn quit(conn: &mut dyn ConnectionLike) -> RedisResult<()> {
+    redis::cmd("QUIT").query(conn)
+}
+
+/// Checks that the connection is still alive with a PING.
+pub fn ping(conn: &mut dyn ConnectionLike) -> RedisResult<()> {
+    redis::cmd("PING").query(conn)
+}
+
+/// Names the connection, as shown by `CLIENT LIST` on the server.
+pub fn client_setname(conn: &mut dyn ConnectionLike, name: &str) -> RedisResult<()> {
+    redis::cmd("CLIENT").arg("SETNAME").arg(name).query(conn)
+}
+
+/// Sets the read and write timeouts on the connection's socket, or clears
+/// them if `None`.
+pub fn set_timeouts(conn: &mut Connection, timeout: Option<Duration>) -> RedisResult<()> {
+    conn.set_read_timeout(timeout)?;
+    conn.set_write_timeout(timeout)
+}
+
+/// Gets the length of the value of `key` in hash `name` with HSTRLEN,
+/// without fetching the value itself. This is zero for a missing key.
+pub fn hstrlen(conn: &mut dyn ConnectionLike, name: &str, key: &str) -> RedisResult<usize> {
+    redis::cmd("HSTRLEN").arg(name).arg(key).query(conn)
+}
+
+/// Gets the value of `key` in hash `name`, without checking the stamp.
+pub fn hget(conn: &mut dyn ConnectionLike, name: &str, key: &str) -> RedisResult<Option<Vec<u8>>> {
+    Cmd::hget(name, key).query(conn)
+}
+
+/// Gets the length of the value of `key` in hash `name` by fetching it,
+/// for servers that don't support HSTRLEN.
+pub fn hget_len(
+    conn: &mut dyn ConnectionLike,
+    name: &str,
+    key: &str,
+) -> RedisResult<Option<usize>> {
+    Ok(hget(conn, name, key)?.map(|v| v.len()))
+}
+
+/// Determines if the error is the server rejecting a command that it
+/// doesn't know, as from an older server version.
+pub fn is_unknown_command(err: &RedisError) -> bool {
+    err.kind() == ErrorKind::ResponseError
+        && err.to_string().to_lowercase().contains("unknown command")
+}
+
+/// Gets the replication role of the server, like "master" or "slave".
+pub fn role(conn: &mut dyn ConnectionLike) -> RedisResult<String> {
+    let reply: Vec<Value> = redis::cmd("ROLE").query(conn)?;
+    match reply.first() {
+        Some(v) => redis::from_redis_value(v),
+        None => Ok(String::new()),
+    }
+}
+
+/// Asks a sentinel for the address of the primary it knows by the name,
+/// or `None` if it doesn't monitor one by that name.
+#[cfg(feature = "sentinel")]
+pub fn sentinel_master_addr(
+    conn: &mut dyn ConnectionLike,
+    master: &str,
+) -> RedisResult<Option<(String, u16)>> {
+    redis::cmd("SENTINEL")
+        .arg("get-master-addr-by-name")
+        .arg(master)
+        .query(conn)
+}
+
+/// Waits for at least `replicas` of the server's replicas to have all the
+/// writes made so far on the connection, for up to the `timeout`.
+/// Returns the number of replicas that have them.
+pub fn wait_replicas(
+    conn: &mut dyn ConnectionLike,
+    replicas: usize,
+    timeout: Duration,
+) -> RedisResult<usize> {
+    redis::cmd("WAIT")
+        .arg(replicas)
+        .arg(timeout.as_millis() as u64)
+        .query(conn)
+}
+
+/// Runs a command just to see if the server allows it, ignoring the reply.
+pub fn probe(conn: &mut dyn ConnectionLike, cmd: &str, args: &[&str]) -> RedisResult<()> {
+    redis::cmd(cmd).arg(args).query::<Value>(conn).map(|_| ())
+}
+
+/// Runs a trivial transaction, with MULTI and EXEC, just to see if the
+/// server allows it.
+pub fn probe_transaction(conn: &mut dyn ConnectionLike, name: &str) -> RedisResult<()> {
+    redis::pipe()
+        .atomic()
+        .hlen(name)
+        .query::<Value>(conn)
+        .map(|_| ())
+}
+
+/// Determines if the error is the server refusing a command that the
+/// user isn't permitted to run, by its ACL.
+pub fn is_no_permission(err: &RedisError) -> bool {
+    err.code() == Some("NOPERM")
+}
+
+/// Sets all the `keys` to expire after the `ttl`, in a single round trip.
+pub fn pexpire(conn: &mut dyn ConnectionLike, keys: &[&str], ttl: Duration) -> RedisResult<()> {
+    // The argument type of the `pexpire()` helper varies between versions
+    let ms = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX).max(1);
+    let mut pipe = redis::pipe();
+    for key in keys {
+        pipe.cmd("PEXPIRE").arg(*key).arg(ms).ignore();
+    }
+    pipe.query(conn)
+}
+
+/// Gets the latest events from the server's latency monitor.
+pub fn latency_latest(conn: &mut dyn ConnectionLike) -> RedisResult<Vec<LatencyEvent>> {
+    let events: Vec<(String, i64, i64, i64)> = redis::cmd("LATENCY").arg("LATEST").query(conn)?;
+    Ok(events
+        .into_iter()
+        .map(|(event, timestamp, latest_ms, max_ms)| LatencyEvent {
+            event,
+            timestamp,
+            latest_ms,
+            max_ms,
+        })
+        .collect())
+}
+
+/// Gets the most recent `count` entries from the server's slow log.
+///
+/// The entries have grown extra fields over the server versions, so only
+/// the leading ones are read.
+pub fn slowlog_get(conn: &mut dyn ConnectionLike, count: usize) -> RedisResult<Vec<SlowlogEntry>> {
+    let entries: Vec<Vec<Value>> = redis::cmd("SLOWLOG").arg("GET").arg(count).query(conn)?;
+    entries
+        .iter()
+        .filter(|entry| entry.len() >= 4)
+        .map(|entry| {
+            Ok(SlowlogEntry {
+                id: redis::from_redis_value(&entry[0])?,
+                timestamp: redis::from_redis_value(&entry[1])?,
+                micros: redis::from_redis_value(&entry[2])?,
+                args: redis::from_redis_value(&entry[3])?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,593 @@
+// mqtt.rust.redis/src/admin.rs
+//
+// Maintenance operations across many persistence stores.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Maintenance operations across many persistence stores.
+//!
+//! These work directly on a Redis connection, rather than on a single
+//! open store, and are meant for tooling, like the `mqtt-redis` CLI, that
+//! cleans up after test runs that leave thousands of ephemeral client
+//! stores behind.
+
+use crate::{
+    adapter,
+    audit::AuditEvent,
+    entry_info::{self, EntryInfo},
+    key_kind::KindCounts,
+    name,
+    session::SessionSummary,
+    stamp::WriteStamp,
+    stats::QosBreakdown,
+    store::Format,
+    Error, Result, Snapshot,
+};
+use redis::Connection;
+use std::{cmp::Ordering, collections::HashSet, time::Duration};
+
+/// The number of keys to delete in a single command.
+const DEL_BATCH_SIZE: usize = 500;
+
+/// The time to keep the temporary copy of a snapshot restored for a diff,
+/// in case we don't get to delete it.
+const SNAPSHOT_TTL: Duration = Duration::from_secs(60);
+
+/// Matches a string against a Redis-style glob pattern.
+///
+/// This supports `*`, `?`, character classes like `[a-z]` or `[^abc]`, and
+/// backslash escapes, the same as the Redis `SCAN MATCH` option.
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    glob_match_chars(&p, &s)
+}
+
+fn glob_match_chars(p: &[char], s: &[char]) -> bool {
+    match p.first() {
+        None => s.is_empty(),
+        Some('*') => (0..=s.len()).any(|i| glob_match_chars(&p[1..], &s[i..])),
+        Some('?') => !s.is_empty() && glob_match_chars(&p[1..], &s[1..]),
+        Some('[') => {
+            let c = match s.first() {
+                Some(c) => *c,
+                None => return false,
+            };
+            let mut i = 1;
+            let negate = p.get(i) == Some(&'^');
+            if negate {
+                i += 1;
+            }
+            let mut matched = false;
+            while i < p.len() && p[i] != ']' {
+                if p[i] == '\\' && i + 1 < p.len() {
+                    i += 1;
+                    matched |= p[i] == c;
+                    i += 1;
+                } else if i + 2 < p.len() && p[i + 1] == '-' && p[i + 2] != ']' {
+                    matched |= p[i] <= c && c <= p[i + 2];
+                    i += 3;
+                } else {
+                    matched |= p[i] == c;
+                    i += 1;
+                }
+            }
+            // An unterminated class matches to the end of the pattern.
+            let rest = if i < p.len() { &p[i + 1..] } else { &p[i..] };
+            matched != negate && glob_match_chars(rest, &s[1..])
+        }
+        Some('\\') if p.len() > 1 => s.first() == Some(&p[1]) && glob_match_chars(&p[2..], &s[1..]),
+        Some(c) => s.first() == Some(c) && glob_match_chars(&p[1..], &s[1..]),
+    }
+}
+
+/// Escapes the glob characters in a string, so that it matches only
+/// itself in a pattern.
+pub(crate) fn glob_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The Redis keys of a store, and its auxiliary keys, found on the server.
+#[derive(Debug, Default)]
+struct StoreKeys {
+    /// The names of the stores.
+    stores: Vec<String>,
+    /// All the keys of the stores, including the stores themselves.
+    keys: Vec<String>,
+}
+
+/// Finds the Redis keys for the stores, and their metadata, under the key
+/// `prefix`, whose client ID matches the glob `pattern`.
+///
+/// The pattern is matched against the client ID as it appears in the
+/// store name, with any escaping applied, but the pattern itself is
+/// escaped the same way, so it can be written in terms of the original
+/// client ID's.
+///
+/// A store is only recognized by its metadata hash, which the store
+/// stamps with each write, so that an unrelated key that happens to
+/// match, like `<client>:<anything>`, is never taken for one. The other
+/// keys of a store are then only included if they have the type that
+/// the store gives them.
+fn matching_keys(conn: &mut Connection, prefix: &str, pattern: &str) -> Result<StoreKeys> {
+    let pattern = name::escape_client_id(pattern);
+    let scan_pattern = format!("{}{}{}*", glob_escape(prefix), pattern, name::SEPARATOR);
+    let found: HashSet<String> = adapter::scan_match(conn, &scan_pattern)?
+        .into_iter()
+        .collect();
+    let meta_suffix = name::meta_name("");
+
+    let mut candidates: Vec<&str> = found
+        .iter()
+        .filter_map(|key| key.strip_suffix(&meta_suffix))
+        .filter(|store| {
+            // SCAN might let a '*' run past the separator, so check the
+            // client ID portion of the name on its own.
+            let rest = store.strip_prefix(prefix).unwrap_or_default();
+            let client_id = rest.split(name::SEPARATOR).next().unwrap_or_default();
+            glob_match(&pattern, client_id)
+        })
+        .collect();
+    candidates.sort_unstable();
+
+    let mut found_keys = StoreKeys::default();
+    for store in candidates {
+        let meta = name::meta_name(store);
+        if adapter::key_type(conn, &meta)? != "hash" {
+            continue;
+        }
+        let aux = [
+            (store.to_string(), "hash"),
+            (name::received_name(store), "hash"),
+            (name::last_session_name(store), "hash"),
+            (name::info_name(store), "hash"),
+            (name::audit_name(store), "stream"),
+            (name::index_name(store), "set"),
+        ];
+        found_keys.stores.push(store.to_string());
+        found_keys.keys.push(meta);
+        for (key, key_type) in aux {
+            if found.contains(&key) && adapter::key_type(conn, &key)? == key_type {
+                found_keys.keys.push(key);
+            }
+        }
+        // The entries kept in string keys of their own
+        let entry_prefix = name::entry_prefix(store);
+        for key in found.iter().filter(|key| key.starts_with(&entry_prefix)) {
+            if adapter::key_type(conn, key)? == "string" {
+                found_keys.keys.push(key.clone());
+            }
+        }
+    }
+    found_keys.keys.sort();
+    found_keys.keys.dedup();
+    Ok(found_keys)
+}
+
+/// Gets the names of the stores whose client ID matches the glob
+/// `pattern`.
+pub fn list_matching(conn: &mut Connection, pattern: &str) -> Result<Vec<String>> {
+    list_matching_in(conn, "", pattern)
+}
+
+/// Gets the names of the stores under the key `prefix`, as set for the
+/// stores with the builder, whose client ID matches the glob `pattern`.
+pub fn list_matching_in(conn: &mut Connection, prefix: &str, pattern: &str) -> Result<Vec<String>> {
+    let stores = matching_keys(conn, prefix, pattern)?
+        .stores
+        .into_iter()
+        .filter(|store| !name::is_aux_name(store))
+        .collect();
+    Ok(stores)
+}
+
+/// Deletes all the stores, and their metadata, whose client ID matches
+/// the glob `pattern`.
+///
+/// Returns the names of the stores that were deleted. Note that a
+/// pattern of "*" will delete every store on the server.
+pub fn clear_matching(conn: &mut Connection, pattern: &str) -> Result<Vec<String>> {
+    clear_matching_in(conn, "", pattern)
+}
+
+/// Deletes all the stores, and their metadata, under the key `prefix`,
+/// whose client ID matches the glob `pattern`.
+///
+/// Returns the names of the stores that were deleted.
+pub fn clear_matching_in(
+    conn: &mut Connection,
+    prefix: &str,
+    pattern: &str,
+) -> Result<Vec<String>> {
+    let StoreKeys { stores, keys } = matching_keys(conn, prefix, pattern)?;
+
+    for batch in keys.chunks(DEL_BATCH_SIZE) {
+        let batch: Vec<&str> = batch.iter().map(String::as_str).collect();
+        adapter::del(conn, &batch)?;
+    }
+
+    let stores: Vec<String> = stores
+        .into_iter()
+        .filter(|store| !name::is_aux_name(store))
+        .collect();
+    info!("Cleared {} stores matching '{}'", stores.len(), pattern);
+    Ok(stores)
+}
+
+/// Counts the keys in the store, by the kind of Paho key.
+pub fn count_kinds(conn: &mut Connection, store: &str) -> Result<KindCounts> {
+    let keys = adapter::hkeys(conn, store)?;
+    Ok(KindCounts::from_keys(keys))
+}
+
+/// Reads the summary of the last session of the store, if there is one.
+pub fn last_session(conn: &mut Connection, store: &str) -> Result<Option<SessionSummary>> {
+    let fields = adapter::hgetall(conn, &name::last_session_name(store))?;
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(SessionSummary::from_fields(fields)))
+}
+
+/// Gets the metadata of the entries in the store, sorted by key, from its
+/// side hash. This is empty if the store doesn't keep the metadata.
+pub fn entries_info(conn: &mut Connection, store: &str) -> Result<Vec<(String, EntryInfo)>> {
+    let fields = adapter::hgetall(conn, &name::info_name(store))?;
+    let mut entries = entry_info::from_fields(fields);
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(entries)
+}
+
+/// Gets the latest `count` events in the audit stream of the store, oldest
+/// first. This is empty if the store doesn't keep an audit trail.
+pub fn audit_trail(conn: &mut Connection, store: &str, count: usize) -> Result<Vec<AuditEvent>> {
+    let entries = adapter::xrange_latest(conn, &name::audit_name(store), count)?;
+    Ok(entries
+        .into_iter()
+        .map(|(id, fields)| AuditEvent::from_entry(id, fields))
+        .collect())
+}
+
+/// Gets all the entries in the store, sorted by key.
+pub fn entries(conn: &mut Connection, store: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    let format = Format::read(conn, &name::meta_name(store))?;
+    entries_as(conn, store, format)
+}
+
+/// Gets all the entries in the hash, sorted by key, decoded as the format
+/// calls for.
+fn entries_as(conn: &mut Connection, hash: &str, format: Format) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut entries = format
+        .join(adapter::hgetall(conn, hash)?)?
+        .into_iter()
+        .map(|(k, v)| Ok((k, format.decode(v)?)))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+/// Reads the entries of a snapshot, sorted by key, decoded like those of
+/// the store, which is normally the one that it was taken of.
+///
+/// The snapshot is in the server's own `DUMP` format, so it's restored to
+/// a temporary key on the server to read it back. The key is deleted when
+/// done, and expires in any case.
+pub fn snapshot_entries(
+    conn: &mut Connection,
+    snapshot: &Snapshot,
+    store: &str,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    if snapshot.is_empty() {
+        return Ok(Vec::new());
+    }
+    let format = Format::read(conn, &name::meta_name(store))?;
+    let tmp = format!(
+        "mqtt-redis-diff{}{}",
+        name::SEPARATOR,
+        WriteStamp::new().id()
+    );
+    adapter::restore(conn, &tmp, snapshot.as_bytes())?;
+    let res = adapter::pexpire(conn, &[&tmp], SNAPSHOT_TTL)
+        .map_err(Error::from)
+        .and_then(|_| entries_as(conn, &tmp, format));
+    adapter::del(conn, &[&tmp])?;
+    res
+}
+
+/// A difference between the entries of two stores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryDiff {
+    /// The key is only in the first store.
+    OnlyInFirst(String),
+    /// The key is only in the second store.
+    OnlyInSecond(String),
+    /// The key has different values in the two stores, with a checksum
+    /// of each value.
+    Differs(String, u32, u32),
+}
+
+/// Compares the entries of two stores, each sorted by key, as read by
+/// [`entries()`] or [`snapshot_entries()`].
+pub fn diff_entries(a: &[(String, Vec<u8>)], b: &[(String, Vec<u8>)]) -> Vec<EntryDiff> {
+    let mut diffs = Vec::new();
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    loop {
+        let order = match (a.peek(), b.peek()) {
+            (Some((ka, _)), Some((kb, _))) => ka.cmp(kb),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+        match order {
+            Ordering::Less => {
+                if let Some((key, _)) = a.next() {
+                    diffs.push(EntryDiff::OnlyInFirst(key.clone()));
+                }
+            }
+            Ordering::Greater => {
+                if let Some((key, _)) = b.next() {
+                    diffs.push(EntryDiff::OnlyInSecond(key.clone()));
+                }
+            }
+            Ordering::Equal => {
+                if let (Some((key, va)), Some((_, vb))) = (a.next(), b.next()) {
+                    if va != vb {
+                        diffs.push(EntryDiff::Differs(key.clone(), checksum(va), checksum(vb)));
+                    }
+                }
+            }
+        }
+    }
+    diffs
+}
+
+/// Compares the entries of a store with those of another, which might be
+/// the same store on a different server, like after a migration.
+pub fn diff_stores(
+    a_conn: &mut Connection,
+    a_store: &str,
+    b_conn: &mut Connection,
+    b_store: &str,
+) -> Result<Vec<EntryDiff>> {
+    let a = entries(a_conn, a_store)?;
+    let b = entries(b_conn, b_store)?;
+    Ok(diff_entries(&a, &b))
+}
+
+/// Gets a CRC-32 checksum of the value, to tell values apart in a report
+/// without showing them.
+pub fn checksum(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Breaks down the entries in the store by the direction and QoS of the
+/// messages. This reads the whole store.
+pub fn qos_breakdown(conn: &mut Connection, store: &str) -> Result<QosBreakdown> {
+    let format = Format::read(conn, &name::meta_name(store))?;
+    let entries = format
+        .join(adapter::hgetall(conn, store)?)?
+        .into_iter()
+        .map(|(k, v)| Ok((k, format.decode(v)?)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(QosBreakdown::from_entries(
+        entries.iter().map(|(k, v)| (k.as_str(), v.as_slice())),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fake_server::{FakeServer, Value},
+        state::StateCell,
+        store::Store,
+    };
+    use std::collections::BTreeMap;
+
+    /// Opens a store on the server, with an entry in it, and an audit trail
+    /// and the entry info kept.
+    fn fill_store(server: &FakeServer, client_id: &str) -> String {
+        let mut store = Store::from_url(&server.url(), StateCell::default()).unwrap();
+        store.set_audit_stream(Some(100));
+        store.set_entry_info(true);
+        store.open(client_id, "tcp://localhost:1883").unwrap();
+        store.put("key", &[b"value"]).unwrap();
+        store.close().unwrap();
+        name::store_name(client_id, "tcp://localhost:1883")
+    }
+
+    fn hash(fields: &[(&str, &str)]) -> Value {
+        Value::Hash(
+            fields
+                .iter()
+                .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+                .collect::<BTreeMap<_, _>>(),
+        )
+    }
+
+    /// Adds keys that look like those of a store, but aren't.
+    fn add_lookalikes(server: &FakeServer, store: &str) -> Vec<String> {
+        let keys = vec![
+            // Hashes with a store-like name, but no metadata
+            "sensor:notes".to_string(),
+            "sensor:tcp://elsewhere:1883".to_string(),
+            // A "store" whose metadata isn't a hash
+            "sensor:tcp://fake:1883".to_string(),
+            "sensor:tcp://fake:1883:meta".to_string(),
+            // Keys with the names of a real store's auxiliary keys, but
+            // the wrong types
+            name::audit_name(store),
+            name::last_session_name(store),
+            format!("{}other", name::entry_prefix(store)),
+        ];
+        let mut db = server.db();
+        db.insert(&keys[0], hash(&[("a", "1")]));
+        db.insert(&keys[1], hash(&[("a", "1")]));
+        db.insert(&keys[2], hash(&[("a", "1")]));
+        db.insert(&keys[3], Value::Str(b"writer".to_vec()));
+        db.insert(&keys[4], hash(&[("a", "1")]));
+        db.insert(&keys[5], Value::Str(b"summary".to_vec()));
+        db.insert(&keys[6], hash(&[("a", "1")]));
+        keys
+    }
+
+    fn connect(server: &FakeServer) -> Connection {
+        redis::Client::open(server.url())
+            .unwrap()
+            .get_connection()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_list_matching() {
+        let server = FakeServer::start();
+        let store = fill_store(&server, "sensor");
+        let other = fill_store(&server, "sensor-2");
+        add_lookalikes(&server, &store);
+
+        let mut conn = connect(&server);
+        assert_eq!(
+            list_matching(&mut conn, "sensor").unwrap(),
+            vec![store.clone()]
+        );
+        assert_eq!(
+            list_matching(&mut conn, "sensor*").unwrap(),
+            vec![other, store]
+        );
+        assert!(list_matching(&mut conn, "nothing*").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_clear_matching_keeps_unrelated_keys() {
+        let server = FakeServer::start();
+        let store = fill_store(&server, "sensor");
+        let other = fill_store(&server, "sensor-2");
+        assert!(server.db().get(&name::info_name(&store)).is_some());
+        let audit = name::audit_name(&store);
+        assert!(matches!(server.db().get(&audit), Some(Value::Stream(_))));
+        server
+            .db()
+            .insert(&name::last_session_name(&store), hash(&[]));
+
+        // The lookalikes replace some of the store's own keys
+        let mut conn = connect(&server);
+        let before = server.db().key_names();
+        let lookalikes = add_lookalikes(&server, &store);
+
+        let cleared = clear_matching(&mut conn, "sensor").unwrap();
+        assert_eq!(cleared, vec![store.clone()]);
+
+        let mut left = server.db().key_names();
+        left.sort();
+        let mut expected: Vec<String> = before
+            .into_iter()
+            .filter(|key| key.starts_with(&other))
+            .chain(lookalikes)
+            .collect();
+        expected.sort();
+        assert_eq!(left, expected);
+    }
+
+    #[test]
+    fn test_clear_matching_all_keys_of_store() {
+        let server = FakeServer::start();
+        let store = fill_store(&server, "sensor");
+        server
+            .db()
+            .insert(&name::last_session_name(&store), hash(&[]));
+
+        let mut conn = connect(&server);
+        assert_eq!(clear_matching(&mut conn, "sens*").unwrap(), vec![store]);
+        assert!(server.db().key_names().is_empty());
+    }
+
+    #[test]
+    fn test_clear_matching_in_prefix() {
+        let server = FakeServer::start();
+        let store = fill_store(&server, "sensor");
+        let mut conn = connect(&server);
+        assert!(clear_matching_in(&mut conn, "app:", "*")
+            .unwrap()
+            .is_empty());
+        assert_eq!(clear_matching_in(&mut conn, "", "*").unwrap(), vec![store]);
+    }
+
+    #[test]
+    fn test_entries_gathers_segments() {
+        let server = FakeServer::start();
+        let mut store = Store::from_url(&server.url(), StateCell::default()).unwrap();
+        store.set_segmented_puts(true);
+        store.open("sensor", "tcp://localhost:1883").unwrap();
+        store.put("a", &[b"header,", b"payload"]).unwrap();
+        store.put("b", &[b"plain"]).unwrap();
+        store.close().unwrap();
+
+        let name = name::store_name("sensor", "tcp://localhost:1883");
+        let mut conn = connect(&server);
+        let expected = vec![
+            ("a".to_string(), b"header,payload".to_vec()),
+            ("b".to_string(), b"plain".to_vec()),
+        ];
+        assert_eq!(entries(&mut conn, &name).unwrap(), expected);
+
+        // A store that never put segments reads a lookalike as it is
+        let mut store = Store::from_url(&server.url(), StateCell::default()).unwrap();
+        store.open("other", "tcp://localhost:1883").unwrap();
+        store.put("a", &[b"\0seg:2"]).unwrap();
+        store.put("a:0", &[b"not a segment"]).unwrap();
+        assert_eq!(store.get("a").unwrap(), b"\0seg:2");
+        assert_eq!(store.keys().unwrap().len(), 2);
+        store.close().unwrap();
+
+        let name = name::store_name("other", "tcp://localhost:1883");
+        assert_eq!(entries(&mut conn, &name).unwrap().len(), 2);
+    }
+}
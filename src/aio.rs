@@ -0,0 +1,392 @@
+// mqtt.rust.redis/src/aio.rs
+//
+// An async persistence store, over a multiplexed Redis connection.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! An async persistence store, over a multiplexed Redis connection.
+//!
+//! This is in the `tokio` and `async-std` features, for the runtime that
+//! the application uses. The [`AsyncRedisPersistence`] keeps a client's
+//! entries in a Redis hash, like the blocking store, but the operations
+//! are `async`, over a single multiplexed connection, so a service
+//! doesn't tie up a worker thread while it waits on the server. It has
+//! the basic operations, and few of the settings of the blocking store.
+//!
+//! It stores the values in the same way as the blocking store, though, so
+//! either one can be used on the same hash. A value that was put as
+//! segments, or in chunks, is gathered back together on a read, and the
+//! empty value policy and compression can be set, to encode the values
+//! that are put the same way. A store kept in string keys, with
+//! `set_string_keys()`, isn't in a hash, and can't be read by this one.
+//!
+//! A connection that fails, as on a network error or a restart of the
+//! server, is made again, and the operation is tried once more on the new
+//! one, so a brief outage doesn't reach the MQTT client as a persistence
+//! error. With tokio, this is the redis `ConnectionManager`, which makes
+//! the new connection in the background. With async-std, the store makes
+//! it itself.
+//!
+//! The Paho persistence callbacks are blocking, so to hand the store to
+//! the MQTT client, it runs each operation to completion. With tokio,
+//! this is on a runtime handle: the one set with `set_runtime()`, or else
+//! the one it was created on. The Paho library calls them from its own
+//! threads, which is fine, but a call from within the runtime itself would
+//! panic. With async-std, the operations are run on the calling thread.
+//! If both features are on, tokio is used.
+//!
+//! With tokio, each operation runs in a `tracing` span, named for it, with
+//! the name of the store and the key, so that a subscriber, like the one
+//! for tokio-console, can show what the store is waiting on when the
+//! runtime stalls. The store doesn't spawn any tasks of its own. The only
+//! one is the task that redis spawns to drive the connection, which it
+//! doesn't name. The background work of the blocking store is done on
+//! threads, which are named, like `mqtt-redis-invalidate`.
+
+#[cfg(feature = "compression")]
+use crate::compress::Compression;
+use crate::{
+    adapter::{self, AsyncConnection},
+    name,
+    policy::EmptyValuePolicy,
+    segment,
+    store::{Encoding, Format, DEFAULT_URL},
+    Error, Result,
+};
+use redis::{Client, RedisError};
+#[cfg(feature = "paho-mqtt")]
+use std::future::Future;
+#[cfg(feature = "tokio")]
+use tokio::runtime::Handle;
+
+/// Runs an adapter call on the store's connection, and if the connection
+/// fails, runs it once more, on a new one.
+macro_rules! retry {
+    ($store:ident, $conn:ident => $call:expr) => {{
+        let $conn = $store.conn()?;
+        match $call.await {
+            Err(e) => {
+                $store.reconnect(e).await?;
+                let $conn = $store.conn()?;
+                $call.await
+            }
+            res => res,
+        }
+    }};
+}
+
+/// The runtime to run an operation to completion on, for the blocking
+/// calls from the MQTT client.
+#[cfg(feature = "paho-mqtt")]
+#[derive(Clone)]
+pub(crate) struct Runtime {
+    #[cfg(feature = "tokio")]
+    handle: Handle,
+}
+
+#[cfg(feature = "paho-mqtt")]
+impl Runtime {
+    /// Runs the future to completion, blocking the calling thread.
+    pub(crate) fn block_on<F: Future>(&self, f: F) -> F::Output {
+        #[cfg(feature = "tokio")]
+        return self.handle.block_on(f);
+        #[cfg(not(feature = "tokio"))]
+        async_std::task::block_on(f)
+    }
+}
+
+/// A persistence store with async operations.
+#[derive(Clone)]
+pub struct AsyncRedisPersistence {
+    /// The Redis client, to make the connection.
+    client: Client,
+    /// The prefix for the names of the Redis keys of the store.
+    key_prefix: String,
+    /// The name of the Redis hash, once the store is opened.
+    name: String,
+    /// The connection to the server, while the store is open.
+    conn: Option<AsyncConnection>,
+    /// How to store empty values, and compress the values that are put.
+    encoding: Encoding,
+    /// How the values in the store might be encoded, as recorded in its
+    /// metadata.
+    format: Format,
+    /// The runtime to block on for the Paho callbacks.
+    #[cfg(feature = "tokio")]
+    runtime: Option<Handle>,
+}
+
+impl AsyncRedisPersistence {
+    /// Creates a new store for the Redis server on localhost.
+    pub fn new() -> Result<Self> {
+        Self::from_url(DEFAULT_URL)
+    }
+
+    /// Creates a new store for the Redis server at the URL.
+    /// This fails if the URL can't be parsed, but doesn't connect.
+    pub fn from_url(url: &str) -> Result<Self> {
+        Ok(Self::with_client(adapter::open_client(url)?))
+    }
+
+    /// Creates a new store that connects with the Redis client.
+    pub fn with_client(client: Client) -> Self {
+        Self {
+            client,
+            key_prefix: String::new(),
+            name: String::new(),
+            conn: None,
+            encoding: Encoding::default(),
+            format: Format::default(),
+            #[cfg(feature = "tokio")]
+            runtime: Handle::try_current().ok(),
+        }
+    }
+
+    /// Sets a prefix for the names of the store's Redis keys. This takes
+    /// effect the next time the store is opened.
+    pub fn set_key_prefix(&mut self, prefix: &str) {
+        self.key_prefix = prefix.to_string();
+    }
+
+    /// Sets how to store empty values.
+    pub fn set_empty_value_policy(&mut self, policy: EmptyValuePolicy) {
+        self.encoding.empty_value_policy = policy;
+    }
+
+    /// Sets how to compress the values that are put, or `None` to store
+    /// them as they are.
+    #[cfg(feature = "compression")]
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.encoding.compression = compression;
+    }
+
+    /// Sets the runtime to run the operations on, for the blocking calls
+    /// from the MQTT client.
+    #[cfg(feature = "tokio")]
+    pub fn set_runtime(&mut self, runtime: Handle) {
+        self.runtime = Some(runtime);
+    }
+
+    /// Gets the name of the Redis hash for the store.
+    /// This is empty until the store is opened.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Determines if the store is open.
+    pub fn is_open(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    /// Gets the runtime to run the operations on, for a blocking caller.
+    /// With tokio, this fails if there's no runtime handle.
+    #[cfg(feature = "paho-mqtt")]
+    pub(crate) fn runtime(&self) -> Result<Runtime> {
+        #[cfg(feature = "tokio")]
+        return match self.runtime.clone() {
+            Some(handle) => Ok(Runtime { handle }),
+            None => Err(Error::NoRuntime),
+        };
+        #[cfg(not(feature = "tokio"))]
+        Ok(Runtime {})
+    }
+
+    /// Gets the connection, if the store is open.
+    fn conn(&mut self) -> Result<&mut AsyncConnection> {
+        self.conn.as_mut().ok_or(Error::NotOpen)
+    }
+
+    /// Gets ready to try an operation again, after it failed with the
+    /// error, if it was the connection that failed. Otherwise this hands
+    /// back the error.
+    async fn reconnect(&mut self, err: RedisError) -> Result<()> {
+        if !(err.is_io_error() || err.is_connection_dropped()) {
+            return Err(err.into());
+        }
+        debug!(
+            "Async Redis persistence [{}]: reconnecting after: {:?}",
+            self.name, err
+        );
+        // The connection manager is already making a new connection, and
+        // the next command waits for it.
+        #[cfg(not(feature = "tokio"))]
+        {
+            self.conn = Some(adapter::async_connect(&self.client).await?);
+        }
+        Ok(())
+    }
+
+    /// Opens the store for the client, connecting to the server.
+    #[cfg_attr(
+        feature = "tokio",
+        tracing::instrument(level = "debug", skip(self, server_uri))
+    )]
+    pub async fn open(&mut self, client_id: &str, server_uri: &str) -> Result<()> {
+        self.name = format!(
+            "{}{}",
+            self.key_prefix,
+            name::store_name(client_id, server_uri)
+        );
+        trace!("Async Redis persistence [{}]: open", self.name);
+        self.conn = Some(adapter::async_connect(&self.client).await?);
+        let meta = name::meta_name(&self.name);
+        let fields = retry!(self, conn => adapter::async_hmget(conn, &meta, Format::FIELDS))?;
+        self.format = Format::from_fields(&fields);
+        Ok(())
+    }
+
+    /// Gets how the values in the store might be encoded: as its metadata
+    /// records, or as this store puts them. This store doesn't put
+    /// segments, but reads those put by a blocking store.
+    fn format(&self) -> Format {
+        self.format.union(self.encoding.format())
+    }
+
+    /// Records how this store puts its values in the metadata, before it
+    /// puts one, if that isn't recorded yet, as the blocking store does.
+    async fn record_format(&mut self) -> Result<()> {
+        let format = self.format();
+        if format != self.format {
+            let meta = name::meta_name(&self.name);
+            for (field, value) in format.fields() {
+                retry!(self, conn => adapter::async_hset(conn, &meta, field, value.as_bytes()))?;
+            }
+            self.format = format;
+        }
+        Ok(())
+    }
+
+    /// Closes the store, dropping its connection.
+    #[cfg_attr(
+        feature = "tokio",
+        tracing::instrument(level = "debug", skip(self), fields(store = %self.name))
+    )]
+    pub async fn close(&mut self) -> Result<()> {
+        trace!("Async Redis persistence [{}]: close", self.name);
+        self.conn = None;
+        Ok(())
+    }
+
+    /// Puts the value, as the concatenation of the buffers, into the store.
+    /// It's encoded as the empty value policy and compression call for.
+    #[cfg_attr(
+        feature = "tokio",
+        tracing::instrument(level = "debug", skip(self, buffers), fields(store = %self.name))
+    )]
+    pub async fn put(&mut self, key: &str, buffers: &[&[u8]]) -> Result<()> {
+        self.record_format().await?;
+        let value = self.encoding.encode(buffers.concat());
+        let name = self.name.clone();
+        retry!(self, conn => adapter::async_hset(conn, &name, key, &value))?;
+        Ok(())
+    }
+
+    /// Gets the value for the key. A value that was put as segments is
+    /// gathered, and one that was encoded is decoded, as with the blocking
+    /// store.
+    #[cfg_attr(
+        feature = "tokio",
+        tracing::instrument(level = "debug", skip(self), fields(store = %self.name))
+    )]
+    pub async fn get(&mut self, key: &str) -> Result<Vec<u8>> {
+        let name = self.name.clone();
+        let v = retry!(self, conn => adapter::async_hget(conn, &name, key))?;
+        let v = v.ok_or(Error::NotFound)?;
+        let v = match self.format().count(&v) {
+            Some(n) => self.join_segments(key, n).await?,
+            None => v,
+        };
+        self.format().decode(v)
+    }
+
+    /// Gathers the segments of the key's value, from `n` fields.
+    async fn join_segments(&mut self, key: &str, n: usize) -> Result<Vec<u8>> {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let name = self.name.clone();
+        let fields: Vec<String> = (0..n).map(|i| segment::field(key, i)).collect();
+        let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+        let segments = retry!(self, conn => adapter::async_hmget(conn, &name, &fields))?;
+        segment::concat(key, segments)
+    }
+
+    /// Removes the value for the key, along with any segments of it, if
+    /// it's in the store.
+    #[cfg_attr(
+        feature = "tokio",
+        tracing::instrument(level = "debug", skip(self), fields(store = %self.name))
+    )]
+    pub async fn remove(&mut self, key: &str) -> Result<()> {
+        let name = self.name.clone();
+        if self.format().segmented {
+            retry!(self, conn => adapter::async_segmented_hdel(conn, &name, key))?;
+        } else {
+            retry!(self, conn => adapter::async_hdel(conn, &name, key))?;
+        }
+        Ok(())
+    }
+
+    /// Gets all the keys in the store, leaving out the fields that hold
+    /// the segments of the values.
+    #[cfg_attr(
+        feature = "tokio",
+        tracing::instrument(level = "debug", skip(self), fields(store = %self.name))
+    )]
+    pub async fn keys(&mut self) -> Result<Vec<String>> {
+        let name = self.name.clone();
+        let keys = retry!(self, conn => adapter::async_hkeys(conn, &name))?;
+        Ok(self.format().strip(keys))
+    }
+
+    /// Removes all the entries from the store.
+    #[cfg_attr(
+        feature = "tokio",
+        tracing::instrument(level = "debug", skip(self), fields(store = %self.name))
+    )]
+    pub async fn clear(&mut self) -> Result<()> {
+        let name = self.name.clone();
+        retry!(self, conn => adapter::async_del(conn, &name))?;
+        Ok(())
+    }
+
+    /// Determines if the store contains the key.
+    #[cfg_attr(
+        feature = "tokio",
+        tracing::instrument(level = "debug", skip(self), fields(store = %self.name))
+    )]
+    pub async fn contains_key(&mut self, key: &str) -> Result<bool> {
+        let name = self.name.clone();
+        Ok(retry!(self, conn => adapter::async_hexists(conn, &name, key))?)
+    }
+}
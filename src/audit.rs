@@ -0,0 +1,116 @@
+// mqtt.rust.redis/src/audit.rs
+//
+// An audit trail of the changes to a persistence store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! An audit trail of the changes to a persistence store.
+//!
+//! When a message goes missing, the question is usually whether the MQTT
+//! client ever persisted it, or released it too early. With the audit
+//! trail turned on, the store appends each put, remove, and clear to a
+//! capped Redis Stream next to it, so there's a time-ordered record of
+//! what the client did, which an operator can read back with the admin
+//! tools, or with `XRANGE` from any Redis client.
+//!
+//! The stream is trimmed, approximately, to a set length with each entry,
+//! so it only holds the latest changes.
+
+use crate::invalidate::Invalidation;
+use std::fmt;
+
+/// A change to a store, as recorded in its audit stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    /// The ID of the entry in the stream, like "1697040000000-0", which
+    /// orders the events, and gives the time of each.
+    pub id: String,
+    /// The operation, one of "put", "remove", or "clear".
+    pub op: String,
+    /// The key that was put or removed, if any.
+    pub key: Option<String>,
+    /// The stamp of the store instance that made the change, if known.
+    pub writer: Option<String>,
+}
+
+impl AuditEvent {
+    /// Creates the event from an entry in the stream.
+    pub(crate) fn from_entry(id: String, fields: Vec<(String, String)>) -> Self {
+        let mut event = Self {
+            id,
+            op: String::new(),
+            key: None,
+            writer: None,
+        };
+        for (field, value) in fields {
+            match field.as_str() {
+                "op" => event.op = value,
+                "key" => event.key = Some(value),
+                "writer" => event.writer = Some(value),
+                _ => (),
+            }
+        }
+        event
+    }
+
+    /// Gets the time of the event, in milliseconds since the Unix epoch,
+    /// from the ID that the server gave it.
+    pub fn timestamp_ms(&self) -> Option<u64> {
+        self.id.split('-').next()?.parse().ok()
+    }
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.id, self.op)?;
+        if let Some(key) = self.key.as_ref() {
+            write!(f, " '{}'", key)?;
+        }
+        if let Some(writer) = self.writer.as_ref() {
+            write!(f, " by {}", writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Gets the fields to record in the stream for the change, made by the
+/// `writer`.
+pub(crate) fn fields(change: &Invalidation, writer: &str) -> Vec<(&'static str, String)> {
+    let mut fields = match change {
+        Invalidation::Put(key) => vec![("op", "put".to_string()), ("key", key.clone())],
+        Invalidation::Remove(key) => vec![("op", "remove".to_string()), ("key", key.clone())],
+        Invalidation::Clear => vec![("op", "clear".to_string())],
+    };
+    fields.push(("writer", writer.to_string()));
+    fields
+}
@@ -0,0 +1,139 @@
+// mqtt.rust.redis/src/backpressure.rs
+//
+// Backpressure from the store quota to the application.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Backpressure from the store quota to the application.
+//!
+//! With a limit on the number of entries, a `put()` into a full store
+//! fails, and the Paho client then starts failing publishes. By then, it's
+//! too late for the application to do anything but drop messages. A high
+//! watermark below the limit gives it a warning, through a callback, while
+//! there's still room to slow down its publish rate, and a second call
+//! when the store drains back below the watermark, so it can speed up
+//! again.
+
+use std::fmt;
+
+/// The usage of the store quota, passed to a backpressure callback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuotaUsage {
+    /// The number of entries in the store.
+    pub entries: usize,
+    /// The maximum number of entries allowed in the store.
+    pub max_entries: usize,
+    /// Whether the store is at or above the watermark. This is false when
+    /// the store drained back below it.
+    pub high: bool,
+}
+
+impl QuotaUsage {
+    /// Gets the fraction of the quota that is used, from 0.0 to 1.0.
+    pub fn fraction(&self) -> f64 {
+        self.entries as f64 / self.max_entries.max(1) as f64
+    }
+}
+
+/// The callback for backpressure.
+type PressureCallback = Box<dyn FnMut(&QuotaUsage) + Send + 'static>;
+
+/// Backpressure on the application when the store nears its quota.
+pub struct Backpressure {
+    /// The fraction of the quota to report as high.
+    watermark: f64,
+    /// The callback for the changes in pressure.
+    callback: PressureCallback,
+    /// Whether the usage was last reported as high.
+    high: bool,
+}
+
+impl Backpressure {
+    /// Creates the backpressure to call back when the number of entries
+    /// reaches the `watermark` fraction of the quota, like 0.8 for 80%,
+    /// and again when it falls back below it.
+    ///
+    /// The callback is invoked from within a store operation, with the
+    /// store locked, so it must not call back into the persistence object.
+    /// This has no effect unless the store has a limit on its entries.
+    pub fn new<F>(watermark: f64, callback: F) -> Self
+    where
+        F: FnMut(&QuotaUsage) + Send + 'static,
+    {
+        Self {
+            watermark: watermark.clamp(0.0, 1.0),
+            callback: Box::new(callback),
+            high: false,
+        }
+    }
+
+    /// Gets the fraction of the quota to report as high.
+    pub fn watermark(&self) -> f64 {
+        self.watermark
+    }
+
+    /// Checks the number of entries against the quota, calling back if
+    /// it crossed the watermark in either direction.
+    pub(crate) fn check(&mut self, entries: usize, max_entries: usize) {
+        let mut usage = QuotaUsage {
+            entries,
+            max_entries,
+            high: false,
+        };
+        usage.high = usage.fraction() >= self.watermark;
+        if usage.high != self.high {
+            self.high = usage.high;
+            if usage.high {
+                warn!(
+                    "Redis persistence store is at {} of {} entries",
+                    entries, max_entries
+                );
+            } else {
+                debug!(
+                    "Redis persistence store is back down to {} of {} entries",
+                    entries, max_entries
+                );
+            }
+            (self.callback)(&usage);
+        }
+    }
+}
+
+impl fmt::Debug for Backpressure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Backpressure")
+            .field("watermark", &self.watermark)
+            .field("high", &self.high)
+            .finish()
+    }
+}
@@ -0,0 +1,498 @@
+// mqtt-redis.rs
+//
+// Command-line tool to inspect and maintain the MQTT persistence stores
+// on a Redis server.
+//
+
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+use paho_mqtt_redis::{
+    admin::{self, EntryDiff},
+    fmt, Snapshot, PASSWORD_ENV,
+};
+use redis::IntoConnectionInfo;
+use std::{env, fs, process};
+
+/// The server to use if one isn't specified on the command line.
+const DEFAULT_URL: &str = "redis://localhost/";
+
+/// The number of audit events to show, if not given.
+const DEFAULT_AUDIT_COUNT: usize = 20;
+
+/// The client ID for the stores used to replay a trace.
+const REPLAY_CLIENT_ID: &str = "mqtt-redis-replay";
+
+/// Prints the usage message and exits with an error.
+fn usage() -> ! {
+    eprintln!(
+        "USAGE: mqtt-redis [--url <url>] [--db <n>] [--prefix <prefix>] <command> [args]
+
+Commands:
+    list <pattern>                      List the stores whose client ID matches
+    inspect <pattern> [--values] [--hex] [--limit <n>]
+                                        Count the keys in the matching stores, by kind,
+                                        and the messages by direction and QoS, with
+                                        their age and size if the store keeps them,
+                                        and optionally show the values, up to <n> bytes
+    last-session <pattern>              Show the summary of the last session of the
+                                        matching stores
+    audit <pattern> [--limit <n>]       Show the latest <n> changes in the audit
+                                        trail of the matching stores
+    diff <pattern> (--against <url> | --snapshot <file>)
+                                        Compare the matching stores with the same
+                                        stores on another server, or with a snapshot
+                                        file, by key and value checksum
+    clear-matching <pattern> [--dry-run]
+                                        Delete the stores whose client ID matches
+    replay <trace-file>                 Replay a persistence trace into the server
+                                        and into memory, and compare the results
+
+The <pattern> is a Redis-style glob, like 'loadtest-*', matched against
+the client ID. A replay uses the client ID '{}', so it doesn't
+touch the store of the traced client. The default server URL is {}, and
+--db selects a database in place of any in the URL. Use --prefix for
+stores created with a key prefix. The password for the server, if it
+needs one, is taken from the {} environment variable, or the URL",
+        REPLAY_CLIENT_ID, DEFAULT_URL, PASSWORD_ENV
+    );
+    process::exit(2);
+}
+
+/// The stores to work on: those under the key prefix whose client ID
+/// matches the pattern.
+#[derive(Debug, Clone, Copy)]
+struct Selection<'a> {
+    /// The prefix of the store names.
+    prefix: &'a str,
+    /// The glob pattern for the client ID's.
+    pattern: &'a str,
+}
+
+impl Selection<'_> {
+    /// Gets the names of the selected stores.
+    fn list(&self, conn: &mut redis::Connection) -> paho_mqtt_redis::Result<Vec<String>> {
+        admin::list_matching_in(conn, self.prefix, self.pattern)
+    }
+}
+
+/// How to show the values of the entries when inspecting the stores.
+#[derive(Debug, Clone, Copy)]
+enum ShowValues {
+    /// Don't show the values.
+    No,
+    /// Show a printable preview of up to this many bytes of each value.
+    Preview(usize),
+    /// Show a hexdump of up to this many bytes of each value.
+    Hex(usize),
+}
+
+/// Prints the count of keys, by kind, and the messages, by direction and
+/// QoS, for each of the matching stores, and optionally the values.
+fn inspect(conn: &mut redis::Connection, sel: &Selection, show: ShowValues) {
+    let res = sel.list(conn).and_then(|stores| {
+        for store in &stores {
+            let counts = admin::count_kinds(conn, store)?;
+            let breakdown = admin::qos_breakdown(conn, store)?;
+            println!("{}\n    {}", store, counts);
+            for line in breakdown.to_string().lines() {
+                println!("    {}", line);
+            }
+            let info = admin::entries_info(conn, store)?;
+            if let Some(oldest) = info.iter().map(|(_, info)| info.age()).max() {
+                let size: usize = info.iter().map(|(_, info)| info.size).sum();
+                println!("    {} bytes, oldest put {:?} ago", size, oldest);
+            }
+            if let ShowValues::No = show {
+                continue;
+            }
+            for (key, value) in admin::entries(conn, store)? {
+                match show {
+                    ShowValues::Preview(limit) => {
+                        println!(
+                            "    {} [{}]: {}",
+                            key,
+                            value.len(),
+                            fmt::preview(&value, limit)
+                        )
+                    }
+                    ShowValues::Hex(limit) => {
+                        println!("    {} [{}]:", key, value.len());
+                        for line in fmt::hexdump(&value, limit).to_string().lines() {
+                            println!("        {}", line);
+                        }
+                    }
+                    ShowValues::No => (),
+                }
+            }
+        }
+        Ok(())
+    });
+
+    if let Err(err) = res {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Prints the summary of the last session of each of the matching stores.
+fn last_session(conn: &mut redis::Connection, sel: &Selection) {
+    let res = sel.list(conn).and_then(|stores| {
+        for store in &stores {
+            match admin::last_session(conn, store)? {
+                Some(summary) => println!("{}\n    {}", store, summary),
+                None => println!("{}\n    no session summary", store),
+            }
+        }
+        Ok(())
+    });
+
+    if let Err(err) = res {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Prints the latest `count` events of the audit trail of each of the
+/// matching stores.
+fn audit(conn: &mut redis::Connection, sel: &Selection, count: usize) {
+    let res = sel.list(conn).and_then(|stores| {
+        for store in &stores {
+            println!("{}", store);
+            let events = admin::audit_trail(conn, store, count)?;
+            if events.is_empty() {
+                println!("    no audit trail");
+            }
+            for event in events {
+                println!("    {}", event);
+            }
+        }
+        Ok(())
+    });
+
+    if let Err(err) = res {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// What to compare the stores with.
+#[derive(Debug)]
+enum DiffTarget {
+    /// The stores with the same names on another server.
+    Server(String),
+    /// The snapshot in a file.
+    Snapshot(String),
+}
+
+/// The other side of a diff, ready to compare.
+enum Other {
+    /// A connection to the other server.
+    Server(redis::Connection),
+    /// A snapshot, to read the entries from.
+    Snapshot(Snapshot),
+}
+
+/// Prints the differences between each of the matching stores and the
+/// target, exiting with an error if there are any.
+fn diff(conn: &mut redis::Connection, sel: &Selection, target: &DiffTarget, db: Option<i64>) {
+    let res = sel.list(conn).and_then(|stores| {
+        let (mut other, label) = match target {
+            DiffTarget::Server(url) => (Other::Server(connect(url, db)), url),
+            DiffTarget::Snapshot(path) => {
+                let data = fs::read(path).unwrap_or_else(|err| {
+                    eprintln!("Error reading the snapshot '{}': {}", path, err);
+                    process::exit(1);
+                });
+                (Other::Snapshot(Snapshot::from_bytes(data)), path)
+            }
+        };
+
+        let mut n = 0;
+        for store in &stores {
+            let diffs = match &mut other {
+                Other::Server(other) => admin::diff_stores(conn, store, other, store)?,
+                Other::Snapshot(snapshot) => {
+                    let entries = admin::snapshot_entries(conn, snapshot, store)?;
+                    admin::diff_entries(&admin::entries(conn, store)?, &entries)
+                }
+            };
+            println!("{}", store);
+            for diff in &diffs {
+                match diff {
+                    EntryDiff::OnlyInFirst(key) => println!("    '{}' missing from {}", key, label),
+                    EntryDiff::OnlyInSecond(key) => println!("    '{}' only in {}", key, label),
+                    EntryDiff::Differs(key, a, b) => {
+                        println!("    '{}' differs: {:08x} vs {:08x}", key, a, b)
+                    }
+                }
+            }
+            n += diffs.len();
+        }
+        Ok((stores.len(), n))
+    });
+
+    match res {
+        Ok((stores, n)) => {
+            eprintln!("Compared {} store(s): {} difference(s)", stores, n);
+            if n > 0 {
+                process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Connects to the Redis server at the URL, selecting the database, if
+/// given, exiting on an error.
+fn connect(url: &str, db: Option<i64>) -> redis::Connection {
+    url.into_connection_info()
+        .and_then(|mut info| {
+            if let Some(db) = db {
+                info.redis.db = db;
+            }
+            if let Ok(password) = env::var(PASSWORD_ENV) {
+                info.redis.password = Some(password);
+            }
+            redis::Client::open(info)
+        })
+        .and_then(|cli| cli.get_connection())
+        .unwrap_or_else(|err| {
+            eprintln!("Error connecting to Redis at '{}': {}", url, err);
+            process::exit(1);
+        })
+}
+
+/// Replays the trace in the file into the Redis server and into memory,
+/// and reports any results that differ from the trace, or from each
+/// other.
+#[cfg(feature = "paho-mqtt")]
+fn replay(url: &str, db: Option<i64>, path: &str) {
+    use paho_mqtt_redis::{
+        replay::{self, StateDiff},
+        trace, MemoryPersistence, RedisPersistenceBuilder,
+    };
+    use std::{fs::File, io::BufReader};
+
+    let events = File::open(path)
+        .and_then(|f| trace::read_trace(BufReader::new(f)))
+        .unwrap_or_else(|err| {
+            eprintln!("Error reading the trace '{}': {}", path, err);
+            process::exit(1);
+        });
+
+    let mut mem = MemoryPersistence::new();
+
+    let mut builder = RedisPersistenceBuilder::new().url(url).password_from_env();
+    if let Some(db) = db {
+        builder = builder.db(db);
+    }
+
+    let res = builder
+        .finalize()
+        .map_err(|err| err.to_string())
+        .and_then(|mut redis| {
+            let a = replay::replay(&events, &mut redis, Some(REPLAY_CLIENT_ID));
+            let b = replay::replay(&events, &mut mem, Some(REPLAY_CLIENT_ID));
+            a.and_then(|a| b.map(|b| (a, b)))
+                .map_err(|err| err.to_string())
+        });
+
+    let (a, b) = res.unwrap_or_else(|err| {
+        eprintln!("Error replaying the trace: {}", err);
+        process::exit(1);
+    });
+
+    for (name, rep) in [("redis", &a), ("memory", &b)] {
+        for m in &rep.mismatches {
+            println!(
+                "{}: #{} {} => {}, but recorded {}",
+                name, m.seq, m.call, m.actual, m.recorded
+            );
+        }
+    }
+    let diffs = replay::diff_states(&a.state, &b.state);
+    for diff in &diffs {
+        match diff {
+            StateDiff::OnlyInFirst(key) => println!("state: '{}' only in redis", key),
+            StateDiff::OnlyInSecond(key) => println!("state: '{}' only in memory", key),
+            StateDiff::Differs(key) => println!("state: '{}' differs", key),
+        }
+    }
+
+    eprintln!(
+        "Replayed {} calls: {} redis mismatch(es), {} memory mismatch(es), {} state difference(s)",
+        events.len(),
+        a.mismatches.len(),
+        b.mismatches.len(),
+        diffs.len()
+    );
+    if !a.is_match() || !b.is_match() || !diffs.is_empty() {
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "paho-mqtt"))]
+fn replay(_url: &str, _db: Option<i64>, _path: &str) {
+    eprintln!("The replay command requires the 'paho' feature");
+    process::exit(2);
+}
+
+/// Removes the flag from the arguments, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|a| a == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Removes the option and its value from the arguments, returning the
+/// value, if the option was there.
+fn take_opt(args: &mut Vec<String>, opt: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == opt)?;
+    if i + 1 >= args.len() {
+        usage();
+    }
+    let val = args.remove(i + 1);
+    args.remove(i);
+    Some(val)
+}
+
+// --------------------------------------------------------------------------
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    let url = take_opt(&mut args, "--url").unwrap_or_else(|| DEFAULT_URL.to_string());
+    let prefix = take_opt(&mut args, "--prefix").unwrap_or_default();
+    let db = take_opt(&mut args, "--db").map(|n| n.parse().unwrap_or_else(|_| usage()));
+    let dry_run = take_flag(&mut args, "--dry-run");
+    let target = match (
+        take_opt(&mut args, "--against"),
+        take_opt(&mut args, "--snapshot"),
+    ) {
+        (Some(url), None) => Some(DiffTarget::Server(url)),
+        (None, Some(path)) => Some(DiffTarget::Snapshot(path)),
+        (None, None) => None,
+        _ => usage(),
+    };
+
+    let limit: Option<usize> =
+        take_opt(&mut args, "--limit").map(|n| n.parse().unwrap_or_else(|_| usage()));
+    let audit_count = limit.unwrap_or(DEFAULT_AUDIT_COUNT);
+    let limit = limit.unwrap_or(fmt::DEFAULT_LIMIT);
+    let show = match (
+        take_flag(&mut args, "--values"),
+        take_flag(&mut args, "--hex"),
+    ) {
+        (_, true) => ShowValues::Hex(limit),
+        (true, false) => ShowValues::Preview(limit),
+        (false, false) => ShowValues::No,
+    };
+
+    let (cmd, pattern) = match args.as_slice() {
+        [cmd, pattern]
+            if cmd == "list"
+                || cmd == "inspect"
+                || cmd == "last-session"
+                || cmd == "audit"
+                || cmd == "diff"
+                || cmd == "clear-matching"
+                || cmd == "replay" =>
+        {
+            (cmd.as_str(), pattern.as_str())
+        }
+        _ => usage(),
+    };
+
+    if cmd == "replay" {
+        replay(&url, db, pattern);
+        return;
+    }
+
+    let mut conn = connect(&url, db);
+    let sel = Selection {
+        prefix: &prefix,
+        pattern,
+    };
+
+    if cmd == "inspect" {
+        inspect(&mut conn, &sel, show);
+        return;
+    }
+
+    if cmd == "last-session" {
+        last_session(&mut conn, &sel);
+        return;
+    }
+
+    if cmd == "audit" {
+        audit(&mut conn, &sel, audit_count);
+        return;
+    }
+
+    if cmd == "diff" {
+        match target {
+            Some(target) => diff(&mut conn, &sel, &target, db),
+            None => usage(),
+        }
+        return;
+    }
+
+    let res = if cmd == "list" || dry_run {
+        sel.list(&mut conn)
+    } else {
+        admin::clear_matching_in(&mut conn, sel.prefix, sel.pattern)
+    };
+
+    match res {
+        Ok(stores) => {
+            for store in &stores {
+                println!("{}", store);
+            }
+            if cmd == "clear-matching" {
+                let verb = if dry_run { "Would delete" } else { "Deleted" };
+                eprintln!("{} {} store(s)", verb, stores.len());
+            }
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
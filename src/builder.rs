@@ -0,0 +1,478 @@
+// mqtt.rust.redis/src/builder.rs
+//
+// A builder for the Redis persistence store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! A builder for the Redis persistence store.
+//!
+//! The builder gathers the options for connecting to the Redis server,
+//! and for naming the store's keys, that have to be in place before the
+//! store is first opened.
+
+#[cfg(feature = "compression")]
+use crate::compress::Compression;
+#[cfg(feature = "sentinel")]
+use crate::sentinel::SentinelConfig;
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use crate::{
+    name::NameFn,
+    state::StateCell,
+    store::{Store, DEFAULT_URL},
+    write_behind::WriteBehind,
+    OverflowPolicy, ReconnectPolicy, RedisPersistence, Result,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A builder for a [`RedisPersistence`] store.
+///
+/// ```ignore
+/// let persist = RedisPersistenceBuilder::new()
+///     .url("redis://localhost:6380/")
+///     .db(2)
+///     .username("mqtt-gateway")
+///     .password_from_env()
+///     .key_prefix("gateway:")
+///     .connect_timeout(Duration::from_secs(2))
+///     .command_timeout(Duration::from_secs(1))
+///     .finalize()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct RedisPersistenceBuilder {
+    /// The URL of the Redis server.
+    url: String,
+    /// The database number, in place of any in the URL.
+    db: Option<i64>,
+    /// The ACL user for the server, in place of any in the URL.
+    username: Option<String>,
+    /// The password for the server, in place of any in the URL.
+    password: Option<String>,
+    /// Whether to read the password from the environment, if none is set.
+    password_from_env: bool,
+    /// The TLS options for the server, if not the defaults.
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+    /// The sentinels to find the primary, if not found by the URL.
+    #[cfg(feature = "sentinel")]
+    sentinel: Option<SentinelConfig>,
+    /// How to compress the values, if at all.
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+    /// The prefix for the names of the store's Redis keys.
+    key_prefix: String,
+    /// The timeout for connecting to the server, if not the default.
+    connect_timeout: Option<Duration>,
+    /// The timeout for each command, if not the default.
+    command_timeout: Option<Duration>,
+    /// The idle time before the connection is checked, if any.
+    keepalive: Option<Duration>,
+    /// How to reconnect after the connection is lost, if at all.
+    reconnect: Option<ReconnectPolicy>,
+    /// Whether to keep a local mirror of the store.
+    write_through_cache: bool,
+    /// Whether to prefetch the store for recovery, when it's opened.
+    prefetch_on_open: bool,
+    /// Whether to cache the keys with the server's client tracking.
+    client_tracking: bool,
+    /// The COUNT for the HSCAN of the keys, if they're scanned.
+    keys_scan_count: Option<usize>,
+    /// The largest value to put in a single field, if there's a limit.
+    max_value_size: Option<usize>,
+    /// The time each store operation has, with all its retries, if limited.
+    latency_budget: Option<Duration>,
+    /// The TTL of each entry, if each is kept in a string key of its own.
+    string_keys: Option<Option<Duration>>,
+    /// Whether to keep the metadata of each entry in a side hash.
+    entry_info: bool,
+    /// The length of the audit stream, if the changes are audited.
+    audit_stream: Option<usize>,
+    /// The size of the offline buffer, and what to do when it's full.
+    offline_buffer: Option<(usize, OverflowPolicy)>,
+    /// The URL of a replica to read from, if any.
+    read_replica: Option<String>,
+    /// The size of the queue of puts written behind, if any.
+    write_behind: Option<usize>,
+    /// The time between tries of the server, when opened in memory.
+    degrade_interval: Option<Duration>,
+    /// The function to name the store, if not the default.
+    name_fn: Option<NameFn>,
+    /// The function that named the store before, if any.
+    legacy_name_fn: Option<NameFn>,
+}
+
+impl RedisPersistenceBuilder {
+    /// Creates a builder for a store on the Redis server on localhost.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the URL of the Redis server, like `redis://host:6380/2` or
+    /// `redis+unix:///run/redis.sock`.
+    pub fn url<S: Into<String>>(mut self, url: S) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// Sets the number of the database to select, in place of any given
+    /// in the URL. This keeps the persistence stores apart from any other
+    /// data the application keeps on the same server.
+    pub fn db(mut self, db: i64) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Sets the Redis ACL user to authenticate as, in place of any given
+    /// in the URL, for a server with per-service users. Without one, the
+    /// password is for the `default` user.
+    pub fn username<S: Into<String>>(mut self, username: S) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+
+    /// Sets the password for the Redis server, in place of any given in
+    /// the URL.
+    pub fn password<S: Into<String>>(mut self, password: S) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Reads the password for the Redis server from the
+    /// `MQTT_REDIS_PASSWORD` environment variable, each time the store is
+    /// opened, if no password is set with [`password()`](Self::password).
+    /// If the variable isn't set either, any password in the URL is used.
+    pub fn password_from_env(mut self) -> Self {
+        self.password_from_env = true;
+        self
+    }
+
+    /// Sets the TLS options for a `rediss://` server, like a private CA,
+    /// a client certificate, or whether to verify the hostname.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets the sentinels to ask for the address of the primary server,
+    /// in place of the URL. See [`RedisPersistence::set_sentinel()`].
+    #[cfg(feature = "sentinel")]
+    pub fn sentinel(mut self, sentinel: SentinelConfig) -> Self {
+        self.sentinel = Some(sentinel);
+        self
+    }
+
+    /// Sets how to compress the values that are put.
+    /// See [`RedisPersistence::set_compression()`].
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Sets a prefix for the names of the store's Redis keys, so that
+    /// several applications can share a database without their stores
+    /// colliding. This applies to a store opened with a client ID and
+    /// server URI, not one opened by the full name of its hash.
+    pub fn key_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.key_prefix = prefix.into();
+        self
+    }
+
+    /// Sets a function to form the name of the store's Redis hash from the
+    /// client ID and server URI, in place of the default of the escaped
+    /// `{client_id}:{server_uri}`. The key prefix, if any, is added to the
+    /// front of the name.
+    ///
+    /// ```ignore
+    /// let persist = RedisPersistenceBuilder::new()
+    ///     .name_fn(|client_id, _uri| format!("mqtt:{}", client_id))
+    ///     .finalize()?;
+    /// ```
+    pub fn name_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str, &str) -> String + Send + Sync + 'static,
+    {
+        self.name_fn = Some(NameFn::new(f));
+        self
+    }
+
+    /// Names the store with the default layout, but with a different
+    /// separator between the client ID and server URI. This replaces any
+    /// naming function.
+    pub fn name_separator(mut self, sep: char) -> Self {
+        self.name_fn = Some(NameFn::with_separator(sep));
+        self
+    }
+
+    /// Sets the function that named the store before, so that a store left
+    /// under that name is migrated into the new one when it's opened.
+    pub fn legacy_name_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str, &str) -> String + Send + Sync + 'static,
+    {
+        self.legacy_name_fn = Some(NameFn::new(f));
+        self
+    }
+
+    /// Sets the timeout for connecting to the Redis server, in place of
+    /// the default of five seconds. An operation deadline, if one is set,
+    /// takes its place.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the read and write timeout for each Redis command, in place
+    /// of the default of ten seconds. An operation deadline, if one is
+    /// set, takes its place.
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the time the connection can sit idle before it's checked with
+    /// a PING, and replaced if that fails. See
+    /// [`RedisPersistence::set_keepalive()`].
+    pub fn keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+
+    /// Sets the store to reconnect, with a backoff, when an operation
+    /// fails because the connection was lost. See
+    /// [`RedisPersistence::set_reconnect()`].
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// Sets the store to keep a write-through mirror of the whole hash in
+    /// memory, loaded when it's opened. See
+    /// [`RedisPersistence::set_write_through_cache()`].
+    pub fn write_through_cache(mut self) -> Self {
+        self.write_through_cache = true;
+        self
+    }
+
+    /// Sets the store to read all its entries in one round trip when it's
+    /// opened, for the MQTT client to recover from. See
+    /// [`RedisPersistence::set_prefetch_on_open()`].
+    pub fn prefetch_on_open(mut self) -> Self {
+        self.prefetch_on_open = true;
+        self
+    }
+
+    /// Sets the store to cache its keys, with the server's client tracking
+    /// to say when they change. See
+    /// [`RedisPersistence::set_client_tracking()`].
+    pub fn client_tracking(mut self) -> Self {
+        self.client_tracking = true;
+        self
+    }
+
+    /// Sets the store to list its keys with an incremental HSCAN, of about
+    /// `count` at a time. See [`RedisPersistence::set_keys_scan_count()`].
+    pub fn keys_scan_count(mut self, count: usize) -> Self {
+        self.keys_scan_count = Some(count);
+        self
+    }
+
+    /// Sets the largest value to put into a single field of the hash, with
+    /// larger ones put in chunks. See
+    /// [`RedisPersistence::set_max_value_size()`].
+    pub fn max_value_size(mut self, size: usize) -> Self {
+        self.max_value_size = Some(size);
+        self
+    }
+
+    /// Sets the time each store operation has, with all its retries,
+    /// before it fails with a timeout. See
+    /// [`RedisPersistence::set_latency_budget()`].
+    pub fn latency_budget(mut self, budget: Duration) -> Self {
+        self.latency_budget = Some(budget);
+        self
+    }
+
+    /// Sets the store to keep each message in a string key of its own,
+    /// which expires after the `ttl`, if given. See
+    /// [`RedisPersistence::set_string_keys()`].
+    pub fn string_keys(mut self, ttl: Option<Duration>) -> Self {
+        self.string_keys = Some(ttl);
+        self
+    }
+
+    /// Sets the store to keep the time each entry was put, and its size,
+    /// in a side hash. See [`RedisPersistence::set_entry_info()`].
+    pub fn entry_info(mut self) -> Self {
+        self.entry_info = true;
+        self
+    }
+
+    /// Sets the store to append each put, remove, and clear to a stream,
+    /// trimmed to about `max_len` entries. See
+    /// [`RedisPersistence::set_audit_stream()`].
+    pub fn audit_stream(mut self, max_len: usize) -> Self {
+        self.audit_stream = Some(max_len);
+        self
+    }
+
+    /// Sets the store to hold up to `max_ops` writes in memory while Redis
+    /// can't be reached, and replay them once it's back. See
+    /// [`RedisPersistence::set_offline_buffer()`].
+    pub fn offline_buffer(mut self, max_ops: usize, policy: OverflowPolicy) -> Self {
+        self.offline_buffer = Some((max_ops, policy));
+        self
+    }
+
+    /// Sets a replica of the server, at the URL, to take the reads of the
+    /// store. See [`RedisPersistence::set_read_replica()`].
+    pub fn read_replica<S: Into<String>>(mut self, url: S) -> Self {
+        self.read_replica = Some(url.into());
+        self
+    }
+
+    /// Sets puts to be queued, up to `capacity`, and written to the server
+    /// by a background thread. See [`RedisPersistence::set_write_behind()`].
+    pub fn write_behind(mut self, capacity: usize) -> Self {
+        self.write_behind = Some(capacity);
+        self
+    }
+
+    /// Sets the store to open in memory if Redis can't be reached, and try
+    /// the server again every `interval`. See
+    /// [`RedisPersistence::set_degrade_to_memory()`].
+    pub fn degrade_to_memory(mut self, interval: Duration) -> Self {
+        self.degrade_interval = Some(interval);
+        self
+    }
+
+    /// Creates the store.
+    /// This fails if the URL can't be parsed, but doesn't connect.
+    pub fn finalize(self) -> Result<RedisPersistence> {
+        let state = StateCell::default();
+        let mut store = Store::from_url(&self.url, state.clone())?;
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            store.set_tls(self.tls)?;
+        }
+        #[cfg(feature = "sentinel")]
+        store.set_sentinel(self.sentinel);
+        #[cfg(feature = "compression")]
+        store.set_compression(self.compression);
+        if self.username.is_some() {
+            store.set_username(self.username)?;
+        }
+        if self.db.is_some() || self.password.is_some() {
+            store.set_connection_options(self.db, self.password)?;
+        }
+        store.set_password_from_env(self.password_from_env);
+        store.set_key_prefix(&self.key_prefix);
+        if let Some(timeout) = self.connect_timeout {
+            store.set_connect_timeout(Some(timeout));
+        }
+        if let Some(timeout) = self.command_timeout {
+            store.set_command_timeout(Some(timeout));
+        }
+        store.set_keepalive(self.keepalive);
+        store.set_reconnect(self.reconnect);
+        store.set_write_through_cache(self.write_through_cache)?;
+        store.set_prefetch_on_open(self.prefetch_on_open);
+        store.set_client_tracking(self.client_tracking);
+        store.set_keys_scan_count(self.keys_scan_count);
+        store.set_max_value_size(self.max_value_size);
+        store.set_latency_budget(self.latency_budget);
+        if let Some(ttl) = self.string_keys {
+            store.set_string_keys(true, ttl);
+        }
+        store.set_entry_info(self.entry_info);
+        store.set_audit_stream(self.audit_stream);
+        if let Some((max_ops, policy)) = self.offline_buffer {
+            store.set_offline_buffer(Some(max_ops), policy);
+        }
+        store.set_degrade_to_memory(self.degrade_interval);
+        store.set_read_replica(self.read_replica.as_deref())?;
+        store.set_name_fn(self.name_fn);
+        store.set_legacy_name_fn(self.legacy_name_fn);
+
+        let persistence = RedisPersistence {
+            writer: WriteBehind::default(),
+            store: Arc::new(Mutex::new(store)),
+            state,
+        };
+        persistence.set_write_behind(self.write_behind)?;
+        Ok(persistence)
+    }
+}
+
+impl Default for RedisPersistenceBuilder {
+    /// Creates a builder for a store on the Redis server on localhost.
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_URL.to_string(),
+            db: None,
+            username: None,
+            password: None,
+            password_from_env: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "sentinel")]
+            sentinel: None,
+            #[cfg(feature = "compression")]
+            compression: None,
+            key_prefix: String::new(),
+            connect_timeout: None,
+            command_timeout: None,
+            keepalive: None,
+            reconnect: None,
+            write_through_cache: false,
+            prefetch_on_open: false,
+            client_tracking: false,
+            keys_scan_count: None,
+            max_value_size: None,
+            latency_budget: None,
+            string_keys: None,
+            entry_info: false,
+            audit_stream: None,
+            offline_buffer: None,
+            read_replica: None,
+            write_behind: None,
+            degrade_interval: None,
+            name_fn: None,
+            legacy_name_fn: None,
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// mqtt.rust.redis/src/cache.rs
+//
+// Local, in-process cache of a persistence store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Local, in-process cache of a persistence store.
+//!
+//! Right after a reconnect, the Paho client walks the whole store with
+//! `keys()`, `contains_key()`, and `get()` calls. Serving those out of a
+//! local copy of the key set, and optionally the values, saves a round
+//! trip to Redis for each one.
+//!
+//! The cache assumes that this object is the only writer to the store,
+//! which is the normal case for MQTT persistence. It is kept up to date
+//! by the writes that go through it.
+//!
+//! The values are held as `Bytes`, so handing one out doesn't copy it.
+
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+
+/// A local cache of the keys, and some of the values, in a store.
+#[derive(Debug, Default)]
+pub struct Cache {
+    /// All the keys in the store.
+    keys: HashSet<String>,
+    /// The values that we hold locally.
+    values: HashMap<String, Bytes>,
+    /// The maximum number of bytes of values to hold.
+    budget: usize,
+    /// The number of bytes of values currently held.
+    used: usize,
+}
+
+impl Cache {
+    /// Creates a cache for the set of keys, which can hold up to `budget`
+    /// bytes of values.
+    pub fn new<I>(keys: I, budget: usize) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        Self {
+            keys: keys.into_iter().collect(),
+            budget,
+            ..Self::default()
+        }
+    }
+
+    /// Gets the maximum number of bytes of values the cache can hold.
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Gets the number of keys in the store.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Determines if the store contains the key.
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// Gets the value for the key, if it is held locally.
+    pub fn get(&self, key: &str) -> Option<&Bytes> {
+        self.values.get(key)
+    }
+
+    /// Gets all the keys in the store.
+    pub fn keys(&self) -> Vec<String> {
+        self.keys.iter().cloned().collect()
+    }
+
+    /// Records that the key was written to the store.
+    /// The value is held locally if it fits in the remaining budget.
+    pub fn insert(&mut self, key: &str, value: Bytes) {
+        self.remove_value(key);
+        self.keys.insert(key.to_string());
+        if self.used + value.len() <= self.budget {
+            self.used += value.len();
+            self.values.insert(key.to_string(), value);
+        }
+    }
+
+    /// Records that the keys are in the store, without their values.
+    pub fn add_keys<I>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.keys.extend(keys);
+    }
+
+    /// Records that the key was written to the store by someone else,
+    /// so its value is no longer known locally.
+    pub fn invalidate(&mut self, key: &str) {
+        self.remove_value(key);
+        self.keys.insert(key.to_string());
+    }
+
+    /// Drops the local copy of the value for the key, if any.
+    fn remove_value(&mut self, key: &str) {
+        if let Some(v) = self.values.remove(key) {
+            self.used -= v.len();
+        }
+    }
+
+    /// Takes the local copy of the value for the key out of the cache, if
+    /// it has one, leaving just the key.
+    pub fn take(&mut self, key: &str) -> Option<Bytes> {
+        let v = self.values.remove(key)?;
+        self.used -= v.len();
+        Some(v)
+    }
+
+    /// Determines if the cache holds any values.
+    pub fn has_values(&self) -> bool {
+        !self.values.is_empty()
+    }
+
+    /// Records that the key was removed from the store.
+    pub fn remove(&mut self, key: &str) {
+        self.remove_value(key);
+        self.keys.remove(key);
+    }
+
+    /// Records that the whole store was cleared.
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.values.clear();
+        self.used = 0;
+    }
+}
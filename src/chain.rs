@@ -0,0 +1,229 @@
+// mqtt.rust.redis/src/chain.rs
+//
+// A persistence chain that falls back from Redis to a secondary store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! A persistence chain that falls back from Redis to a secondary store.
+//!
+//! A [`ChainedPersistence`] sends everything to a [`RedisPersistence`]
+//! while the Redis server is reachable. When an operation fails because
+//! the server can't be reached, the chain falls back to a secondary
+//! persistence, like a [`MemoryPersistence`](crate::MemoryPersistence),
+//! or a file-based one supplied by the application, and sends the writes
+//! there. From then on, it checks for the Redis server, no more often than
+//! the retry interval, and once it's back, copies the entries written
+//! during the outage back into Redis, applies the removes, and clears the
+//! secondary store.
+//!
+//! The entries already in Redis when it went away can't be read from the
+//! secondary store, so a `get()` of one of those fails until Redis is
+//! back, and `keys()` lists only the ones written since. The MQTT client
+//! reads the keys and values back when it restores a session, which it
+//! does on open, so in practice this only matters if Redis is down when
+//! the client connects.
+//!
+//! Entries left in the secondary store by a process that stopped during
+//! an outage are copied into Redis the next time the chain is opened.
+
+use crate::{client_persistence::compat::mqtt, Error, RedisPersistence};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+/// The default time between checks for the Redis server, while the chain
+/// is running on the secondary store.
+pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A persistence that uses Redis, falling back to a secondary persistence
+/// while the Redis server can't be reached.
+pub struct ChainedPersistence<P> {
+    /// The Redis store, used whenever it can be reached.
+    pub(crate) primary: RedisPersistence,
+    /// The store to use while Redis can't be reached.
+    pub(crate) fallback: P,
+    /// The client ID and server URI the chain was opened for, if open.
+    pub(crate) opened: Option<(String, String)>,
+    /// The time the chain fell back to the secondary store, if it did.
+    pub(crate) since: Option<Instant>,
+    /// The last time the Redis server was checked, while falling back.
+    pub(crate) last_check: Option<Instant>,
+    /// The time between checks for the Redis server.
+    pub(crate) retry_interval: Duration,
+    /// The keys removed while falling back, to remove from Redis.
+    pub(crate) removed: HashSet<String>,
+}
+
+impl<P> ChainedPersistence<P> {
+    /// Creates a chain that uses the Redis store, falling back to the
+    /// secondary store while Redis can't be reached.
+    pub fn new(primary: RedisPersistence, fallback: P) -> Self {
+        Self {
+            primary,
+            fallback,
+            opened: None,
+            since: None,
+            last_check: None,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            removed: HashSet::new(),
+        }
+    }
+
+    /// Sets the time between checks for the Redis server, while the chain
+    /// is running on the secondary store. Each check can take as long as
+    /// the connect timeout of the Redis store.
+    pub fn set_retry_interval(&mut self, interval: Duration) {
+        self.retry_interval = interval;
+    }
+
+    /// Gets the Redis store.
+    pub fn primary(&self) -> &RedisPersistence {
+        &self.primary
+    }
+
+    /// Gets the secondary store.
+    pub fn fallback(&self) -> &P {
+        &self.fallback
+    }
+
+    /// Determines if the chain is running on the secondary store.
+    pub fn is_falling_back(&self) -> bool {
+        self.since.is_some()
+    }
+
+    /// Gets the time since the chain fell back to the secondary store, if
+    /// it's running on it.
+    pub fn falling_back_for(&self) -> Option<Duration> {
+        self.since.map(|since| since.elapsed())
+    }
+
+    /// Unwraps the Redis and secondary stores.
+    pub fn into_inner(self) -> (RedisPersistence, P) {
+        (self.primary, self.fallback)
+    }
+
+    /// Determines if the error from the Redis store means that the server
+    /// can't be reached, so the secondary store should be used.
+    pub(crate) fn is_outage(err: &Error) -> bool {
+        err.is_transient() || matches!(err, Error::NotOpen | Error::NoClient)
+    }
+
+    /// Switches over to the secondary store, after the error from Redis.
+    pub(crate) fn fall_back(&mut self, err: &Error) {
+        if self.since.is_none() {
+            warn!(
+                "Redis persistence is unavailable, using the fallback: {}",
+                err
+            );
+            self.since = Some(Instant::now());
+        }
+        self.last_check = Some(Instant::now());
+    }
+}
+
+impl<P> ChainedPersistence<P>
+where
+    P: mqtt::ClientPersistence,
+{
+    /// Checks if the Redis server is back, if it's time to, and if so,
+    /// copies the entries from the secondary store into it.
+    ///
+    /// Returns whether the chain is using Redis.
+    pub(crate) fn check_primary(&mut self) -> bool {
+        if self.since.is_none() {
+            return true;
+        }
+        if let Some(last) = self.last_check {
+            if last.elapsed() < self.retry_interval {
+                return false;
+            }
+        }
+        self.last_check = Some(Instant::now());
+
+        let res = match self.opened.as_ref() {
+            Some((client_id, server_uri)) if !self.primary.is_connected() => {
+                let _ = self.primary.close();
+                self.primary.open(client_id, server_uri)
+            }
+            _ => self.primary.ping().map(|_| ()),
+        };
+        let keys = match self.fallback.keys() {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Can't read the keys of the fallback persistence: {}", e);
+                return false;
+            }
+        };
+        match res.and_then(|_| self.sync_back(&keys)) {
+            Ok(n) => {
+                info!(
+                    "Redis persistence is back, after {:?}. Restored {} entries.",
+                    self.falling_back_for().unwrap_or_default(),
+                    n
+                );
+                self.since = None;
+                true
+            }
+            Err(e) => {
+                debug!("Redis persistence is still unavailable: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Copies the entries with the keys, from the secondary store into
+    /// Redis, and applies
+    /// the removes made while falling back, then clears the secondary
+    /// store. Returns the number of entries copied.
+    ///
+    /// An entry is only taken out of the secondary store once it's in
+    /// Redis, so a failure part way through leaves the rest of them to
+    /// copy on the next try.
+    pub(crate) fn sync_back(&mut self, keys: &[String]) -> crate::Result<usize> {
+        for key in keys {
+            if let Ok(value) = self.fallback.get(key) {
+                self.primary.put(key, &[&value])?;
+            }
+            let _ = self.fallback.remove(key);
+        }
+        for key in self.removed.iter() {
+            match self.primary.remove(key) {
+                Ok(()) | Err(Error::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.removed.clear();
+        Ok(keys.len())
+    }
+}
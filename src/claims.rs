@@ -0,0 +1,168 @@
+// mqtt.rust.redis/src/claims.rs
+//
+// The claims on store keys within the process.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! The claims on store keys within the process.
+//!
+//! When an application runs more than one MQTT client, each store must
+//! map to its own Redis key. The default names always do, but a custom
+//! naming function, or one that shortens the server URI, might give the
+//! same key to a client ID on two different servers, and the two stores
+//! would then silently overwrite each other's entries.
+//!
+//! Each store that's opened by client ID and server URI claims its key on
+//! the Redis server, here, for as long as it's open, so that a second
+//! store can see that the key is already taken by a different one.
+
+use crate::name::StoreName;
+use std::{collections::HashMap, sync::Mutex};
+
+/// The Redis server and key name of a store.
+type ClaimId = (String, String);
+
+/// The keys claimed in the process, with the name of the store that
+/// claimed each, and the number of stores holding the claim.
+static CLAIMS: Mutex<Option<HashMap<ClaimId, (StoreName, usize)>>> = Mutex::new(None);
+
+/// A claim on a store key, which is released when it's dropped.
+#[derive(Debug)]
+pub(crate) struct Claim {
+    id: ClaimId,
+}
+
+/// Claims the `key` on the `server` for the store with the name.
+///
+/// Any number of stores with the same name can share a claim, but if the
+/// key is already claimed by a store with a different name, this fails
+/// with the name of that store.
+pub(crate) fn claim(server: &str, key: &str, name: &StoreName) -> Result<Claim, StoreName> {
+    let id = (server.to_string(), key.to_string());
+    let mut claims = CLAIMS.lock().unwrap_or_else(|e| e.into_inner());
+    let claims = claims.get_or_insert_with(HashMap::new);
+
+    match claims.get_mut(&id) {
+        Some((holder, count)) if holder == name => *count += 1,
+        Some((holder, _)) => return Err(holder.clone()),
+        None => {
+            claims.insert(id.clone(), (name.clone(), 1));
+        }
+    }
+    Ok(Claim { id })
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        // A panic while the lock was held can't leave the claims half
+        // updated, and a drop mustn't panic in turn, so a poisoned lock
+        // is taken as-is.
+        let mut claims = CLAIMS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(claims) = claims.as_mut() {
+            if let Some((_, count)) = claims.get_mut(&self.id) {
+                *count -= 1;
+                if *count == 0 {
+                    claims.remove(&self.id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The claims are shared by the whole process, so each test uses keys
+    // of its own.
+
+    const SERVER_A: &str = "redis://localhost:6379 db 0";
+    const SERVER_B: &str = "redis://otherhost:6379 db 0";
+
+    #[test]
+    fn test_claim_shared_by_same_name() {
+        let name = StoreName::new("shared", "tcp://mqtt-a:1883");
+        let first = claim(SERVER_A, "test:shared", &name).unwrap();
+        let second = claim(SERVER_A, "test:shared", &name).unwrap();
+        drop(first);
+
+        let other = StoreName::new("shared", "tcp://mqtt-b:1883");
+        assert!(claim(SERVER_A, "test:shared", &other).is_err());
+        drop(second);
+        assert!(claim(SERVER_A, "test:shared", &other).is_ok());
+    }
+
+    #[test]
+    fn test_claim_conflict_and_release() {
+        let name_a = StoreName::new("client", "tcp://mqtt-a:1883");
+        let name_b = StoreName::new("client", "tcp://mqtt-b:1883");
+
+        let claim_a = claim(SERVER_A, "test:conflict", &name_a).unwrap();
+        let holder = claim(SERVER_A, "test:conflict", &name_b).unwrap_err();
+        assert_eq!(holder, name_a);
+
+        drop(claim_a);
+        let _claim_b = claim(SERVER_A, "test:conflict", &name_b).unwrap();
+        let holder = claim(SERVER_A, "test:conflict", &name_a).unwrap_err();
+        assert_eq!(holder, name_b);
+    }
+
+    #[test]
+    fn test_claim_separate_servers() {
+        let name_a = StoreName::new("client", "tcp://mqtt-a:1883");
+        let name_b = StoreName::new("client", "tcp://mqtt-b:1883");
+
+        // The same key on two Redis servers doesn't collide.
+        let _claim_a = claim(SERVER_A, "test:servers", &name_a).unwrap();
+        let _claim_b = claim(SERVER_B, "test:servers", &name_b).unwrap();
+        assert!(claim(SERVER_A, "test:servers", &name_b).is_err());
+        assert!(claim(SERVER_B, "test:servers", &name_a).is_err());
+    }
+
+    #[test]
+    fn test_claim_after_poisoned_lock() {
+        let name = StoreName::new("poisoned", "tcp://mqtt-a:1883");
+        let held = claim(SERVER_A, "test:poisoned", &name).unwrap();
+
+        let _ = std::thread::spawn(|| {
+            let _claims = CLAIMS.lock().unwrap();
+            panic!("poisoning the claims lock");
+        })
+        .join();
+
+        // Releasing and claiming still work with the lock poisoned.
+        drop(held);
+        let other = StoreName::new("poisoned", "tcp://mqtt-b:1883");
+        assert!(claim(SERVER_A, "test:poisoned", &other).is_ok());
+    }
+}
@@ -0,0 +1,434 @@
+// mqtt.rust.redis/src/client_persistence.rs
+//
+// The Paho MQTT client persistence implementation for the Redis store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! The Paho MQTT client persistence implementation for the Redis store.
+//!
+//! This is a thin layer over the store's own API, mapping the persistence
+//! callbacks from the MQTT client to the store operations, and the store
+//! errors to the MQTT persistence error.
+//!
+//! Anything that differs between the supported versions of the Paho
+//! library is kept in the `compat` module, selected by the `paho-0_12`
+//! or `paho-0_13` feature.
+
+use crate::{
+    chain::ChainedPersistence,
+    trace::{TraceCall, TracePersistence, TraceResult},
+    Error, MemoryPersistence, RedisPersistence,
+};
+use compat::{mqtt, Buffers};
+
+/// Adapter for Paho v0.12
+#[cfg(feature = "paho-0_12")]
+pub(crate) mod compat {
+    pub use paho_mqtt_0_12 as mqtt;
+
+    /// The error reported to the client for any failure of the store.
+    pub const PERSISTENCE_ERROR: mqtt::Error = mqtt::PersistenceError;
+
+    /// The collection of buffers that the client sends to `put()`.
+    pub type Buffers<'a> = Vec<&'a [u8]>;
+}
+
+/// Adapter for Paho v0.13
+#[cfg(feature = "paho-0_13")]
+pub(crate) mod compat {
+    pub use paho_mqtt_0_13 as mqtt;
+
+    /// The error reported to the client for any failure of the store.
+    pub const PERSISTENCE_ERROR: mqtt::Error = mqtt::Error::PersistenceError;
+
+    /// The collection of buffers that the client sends to `put()`.
+    pub type Buffers<'a> = Vec<&'a [u8]>;
+}
+
+impl From<Error> for mqtt::Error {
+    /// Any error in the store is reported to the MQTT client as a
+    /// persistence error. Since that loses the cause, an authentication
+    /// failure, which needs fixing in the configuration, is logged.
+    fn from(err: Error) -> Self {
+        if let Error::AuthFailed(_) = err {
+            error!("Redis persistence: {}", err);
+        }
+        compat::PERSISTENCE_ERROR
+    }
+}
+
+impl mqtt::ClientPersistence for RedisPersistence {
+    /// Open the connection to the Redis client.
+    fn open(&mut self, client_id: &str, server_uri: &str) -> mqtt::Result<()> {
+        Ok(RedisPersistence::open(self, client_id, server_uri)?)
+    }
+
+    /// Close the connection to the Redis client.
+    fn close(&mut self) -> mqtt::Result<()> {
+        Ok(RedisPersistence::close(self)?)
+    }
+
+    /// Store a persistent value to Redis.
+    fn put(&mut self, key: &str, buffers: Buffers) -> mqtt::Result<()> {
+        Ok(RedisPersistence::put(self, key, &buffers)?)
+    }
+
+    /// Get the data buffer for the requested key.
+    fn get(&mut self, key: &str) -> mqtt::Result<Vec<u8>> {
+        Ok(RedisPersistence::get(self, key)?)
+    }
+
+    /// Remove the value with the specified `key` from the store.
+    fn remove(&mut self, key: &str) -> mqtt::Result<()> {
+        Ok(RedisPersistence::remove(self, key)?)
+    }
+
+    /// Return a collection of all the keys in the store for this client.
+    fn keys(&mut self) -> mqtt::Result<Vec<String>> {
+        Ok(RedisPersistence::keys(self)?)
+    }
+
+    /// Remove all the data for this client from the store.
+    fn clear(&mut self) -> mqtt::Result<()> {
+        Ok(RedisPersistence::clear(self)?)
+    }
+
+    /// Determines if the store for this client contains the specified `key`.
+    /// Any error is reported as the key not being found.
+    fn contains_key(&mut self, key: &str) -> bool {
+        RedisPersistence::contains_key(self, key).unwrap_or(false)
+    }
+}
+
+impl mqtt::ClientPersistence for MemoryPersistence {
+    fn open(&mut self, client_id: &str, server_uri: &str) -> mqtt::Result<()> {
+        Ok(MemoryPersistence::open(self, client_id, server_uri)?)
+    }
+
+    fn close(&mut self) -> mqtt::Result<()> {
+        Ok(MemoryPersistence::close(self)?)
+    }
+
+    fn put(&mut self, key: &str, buffers: Buffers) -> mqtt::Result<()> {
+        Ok(MemoryPersistence::put(self, key, &buffers)?)
+    }
+
+    fn get(&mut self, key: &str) -> mqtt::Result<Vec<u8>> {
+        Ok(MemoryPersistence::get(self, key)?)
+    }
+
+    fn remove(&mut self, key: &str) -> mqtt::Result<()> {
+        Ok(MemoryPersistence::remove(self, key)?)
+    }
+
+    fn keys(&mut self) -> mqtt::Result<Vec<String>> {
+        Ok(MemoryPersistence::keys(self)?)
+    }
+
+    fn clear(&mut self) -> mqtt::Result<()> {
+        Ok(MemoryPersistence::clear(self)?)
+    }
+
+    fn contains_key(&mut self, key: &str) -> bool {
+        MemoryPersistence::contains_key(self, key).unwrap_or(false)
+    }
+}
+
+impl<P> mqtt::ClientPersistence for ChainedPersistence<P>
+where
+    P: mqtt::ClientPersistence,
+{
+    /// Opens both stores. If Redis can't be reached, the chain starts out
+    /// on the secondary store. Otherwise anything left in the secondary
+    /// store, from an outage in an earlier session, is copied into Redis.
+    fn open(&mut self, client_id: &str, server_uri: &str) -> mqtt::Result<()> {
+        self.fallback.open(client_id, server_uri)?;
+        self.opened = Some((client_id.to_string(), server_uri.to_string()));
+        self.removed.clear();
+        match self.primary.open(client_id, server_uri) {
+            Ok(()) => {
+                self.since = None;
+                let keys = self.fallback.keys()?;
+                if !keys.is_empty() {
+                    let n = self.sync_back(&keys)?;
+                    info!("Restored {} entries from the fallback persistence", n);
+                }
+                Ok(())
+            }
+            Err(e) if Self::is_outage(&e) => {
+                self.fall_back(&e);
+                Ok(())
+            }
+            Err(e) => {
+                let _ = self.fallback.close();
+                self.opened = None;
+                Err(e.into())
+            }
+        }
+    }
+
+    fn close(&mut self) -> mqtt::Result<()> {
+        self.opened = None;
+        let res = if self.is_falling_back() {
+            let _ = self.primary.close();
+            Ok(())
+        } else {
+            self.primary.close()
+        };
+        self.fallback.close()?;
+        Ok(res?)
+    }
+
+    fn put(&mut self, key: &str, buffers: Buffers) -> mqtt::Result<()> {
+        if self.check_primary() {
+            match self.primary.put(key, &buffers) {
+                Err(e) if Self::is_outage(&e) => self.fall_back(&e),
+                res => return Ok(res?),
+            }
+        }
+        self.removed.remove(key);
+        self.fallback.put(key, buffers)
+    }
+
+    fn get(&mut self, key: &str) -> mqtt::Result<Vec<u8>> {
+        if self.check_primary() {
+            match self.primary.get(key) {
+                Err(e) if Self::is_outage(&e) => self.fall_back(&e),
+                res => return Ok(res?),
+            }
+        }
+        self.fallback.get(key)
+    }
+
+    fn remove(&mut self, key: &str) -> mqtt::Result<()> {
+        if self.check_primary() {
+            match self.primary.remove(key) {
+                Err(e) if Self::is_outage(&e) => self.fall_back(&e),
+                res => return Ok(res?),
+            }
+        }
+        let _ = self.fallback.remove(key);
+        self.removed.insert(key.to_string());
+        Ok(())
+    }
+
+    fn keys(&mut self) -> mqtt::Result<Vec<String>> {
+        if self.check_primary() {
+            match self.primary.keys() {
+                Err(e) if Self::is_outage(&e) => self.fall_back(&e),
+                res => return Ok(res?),
+            }
+        }
+        self.fallback.keys()
+    }
+
+    fn clear(&mut self) -> mqtt::Result<()> {
+        if self.check_primary() {
+            match self.primary.clear() {
+                Err(e) if Self::is_outage(&e) => self.fall_back(&e),
+                res => {
+                    res?;
+                    return self.fallback.clear();
+                }
+            }
+        }
+        // The keys in Redis are unknown, so the clear can't be deferred
+        Err(compat::PERSISTENCE_ERROR)
+    }
+
+    fn contains_key(&mut self, key: &str) -> bool {
+        if self.check_primary() {
+            match self.primary.contains_key(key) {
+                Err(e) if Self::is_outage(&e) => self.fall_back(&e),
+                res => return res.unwrap_or(false),
+            }
+        }
+        self.fallback.contains_key(key)
+    }
+}
+
+/// Converts the result of a call that returns nothing to a trace result.
+fn unit_result(res: &mqtt::Result<()>) -> TraceResult {
+    match res {
+        Ok(()) => TraceResult::Ok,
+        Err(e) => TraceResult::Err(e.to_string()),
+    }
+}
+
+impl<P> mqtt::ClientPersistence for TracePersistence<P>
+where
+    P: mqtt::ClientPersistence,
+{
+    fn open(&mut self, client_id: &str, server_uri: &str) -> mqtt::Result<()> {
+        let res = self.inner.open(client_id, server_uri);
+        let call = TraceCall::Open {
+            client_id: client_id.to_string(),
+            server_uri: server_uri.to_string(),
+        };
+        self.recorder.record(call, unit_result(&res));
+        res
+    }
+
+    fn close(&mut self) -> mqtt::Result<()> {
+        let res = self.inner.close();
+        self.recorder.record(TraceCall::Close, unit_result(&res));
+        res
+    }
+
+    fn put(&mut self, key: &str, buffers: Buffers) -> mqtt::Result<()> {
+        let call = TraceCall::Put {
+            key: key.to_string(),
+            sizes: buffers.iter().map(|buf| buf.len()).collect(),
+            data: if self.recorder.is_recording_values() {
+                Some(buffers.concat())
+            } else {
+                None
+            },
+        };
+        let res = self.inner.put(key, buffers);
+        self.recorder.record(call, unit_result(&res));
+        res
+    }
+
+    fn get(&mut self, key: &str) -> mqtt::Result<Vec<u8>> {
+        let res = self.inner.get(key);
+        let result = match res {
+            Ok(ref v) => TraceResult::Value {
+                len: v.len(),
+                data: if self.recorder.is_recording_values() {
+                    Some(v.clone())
+                } else {
+                    None
+                },
+            },
+            Err(ref e) => TraceResult::Err(e.to_string()),
+        };
+        let call = TraceCall::Get {
+            key: key.to_string(),
+        };
+        self.recorder.record(call, result);
+        res
+    }
+
+    fn remove(&mut self, key: &str) -> mqtt::Result<()> {
+        let res = self.inner.remove(key);
+        let call = TraceCall::Remove {
+            key: key.to_string(),
+        };
+        self.recorder.record(call, unit_result(&res));
+        res
+    }
+
+    fn keys(&mut self) -> mqtt::Result<Vec<String>> {
+        let res = self.inner.keys();
+        let result = match res {
+            Ok(ref keys) => TraceResult::Keys(keys.clone()),
+            Err(ref e) => TraceResult::Err(e.to_string()),
+        };
+        self.recorder.record(TraceCall::Keys, result);
+        res
+    }
+
+    fn clear(&mut self) -> mqtt::Result<()> {
+        let res = self.inner.clear();
+        self.recorder.record(TraceCall::Clear, unit_result(&res));
+        res
+    }
+
+    fn contains_key(&mut self, key: &str) -> bool {
+        let res = self.inner.contains_key(key);
+        let call = TraceCall::ContainsKey {
+            key: key.to_string(),
+        };
+        self.recorder.record(call, TraceResult::Bool(res));
+        res
+    }
+}
+
+#[cfg(feature = "async")]
+impl mqtt::ClientPersistence for crate::AsyncRedisPersistence {
+    /// Open the connection to the Redis client, on the store's runtime.
+    fn open(&mut self, client_id: &str, server_uri: &str) -> mqtt::Result<()> {
+        let runtime = self.runtime()?;
+        Ok(runtime.block_on(crate::AsyncRedisPersistence::open(
+            self, client_id, server_uri,
+        ))?)
+    }
+
+    /// Close the connection to the Redis client.
+    fn close(&mut self) -> mqtt::Result<()> {
+        let runtime = self.runtime()?;
+        Ok(runtime.block_on(crate::AsyncRedisPersistence::close(self))?)
+    }
+
+    /// Store a persistent value to Redis.
+    fn put(&mut self, key: &str, buffers: Buffers) -> mqtt::Result<()> {
+        let runtime = self.runtime()?;
+        Ok(runtime.block_on(crate::AsyncRedisPersistence::put(self, key, &buffers))?)
+    }
+
+    /// Get the data buffer for the requested key.
+    fn get(&mut self, key: &str) -> mqtt::Result<Vec<u8>> {
+        let runtime = self.runtime()?;
+        Ok(runtime.block_on(crate::AsyncRedisPersistence::get(self, key))?)
+    }
+
+    /// Remove the value with the specified `key` from the store.
+    fn remove(&mut self, key: &str) -> mqtt::Result<()> {
+        let runtime = self.runtime()?;
+        Ok(runtime.block_on(crate::AsyncRedisPersistence::remove(self, key))?)
+    }
+
+    /// Return a collection of all the keys in the store for this client.
+    fn keys(&mut self) -> mqtt::Result<Vec<String>> {
+        let runtime = self.runtime()?;
+        Ok(runtime.block_on(crate::AsyncRedisPersistence::keys(self))?)
+    }
+
+    /// Remove all the data for this client from the store.
+    fn clear(&mut self) -> mqtt::Result<()> {
+        let runtime = self.runtime()?;
+        Ok(runtime.block_on(crate::AsyncRedisPersistence::clear(self))?)
+    }
+
+    /// Determines if the store for this client contains the specified `key`.
+    /// Any error is reported as the key not being found.
+    fn contains_key(&mut self, key: &str) -> bool {
+        match self.runtime() {
+            Ok(runtime) => runtime
+                .block_on(crate::AsyncRedisPersistence::contains_key(self, key))
+                .unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+}
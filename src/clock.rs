@@ -0,0 +1,145 @@
+// mqtt.rust.redis/src/clock.rs
+//
+// The source of time for the persistence store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! The source of time for the persistence store.
+//!
+//! Everything in the store that depends on the passage of time, like the
+//! operation deadlines, the compaction and growth intervals, the remove
+//! batching window, the rate limiter, and the slow-operation reports,
+//! reads the time from a [`Clock`]. By default that's the system's
+//! monotonic clock, but an application can supply its own, such as a
+//! hardware timer on an embedded target, or a [`MockClock`] to step
+//! through time deterministically in a test.
+//!
+//! The TTL of a store is kept by the Redis server, not the client, so it
+//! isn't affected by the clock.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A source of monotonic time for the store.
+pub trait Clock: Send + Sync {
+    /// Gets the current time.
+    ///
+    /// This must never go backwards, even if the wall clock is changed.
+    fn now(&self) -> Instant;
+
+    /// Waits for the duration to pass, as when the rate limiter holds
+    /// back an operation.
+    fn sleep(&self, dur: Duration) {
+        thread::sleep(dur);
+    }
+}
+
+/// A shared handle to a clock.
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The system's monotonic clock, from [`Instant::now()`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Gets a handle to the system clock.
+pub(crate) fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// A clock that only moves when it's told to.
+///
+/// This is a handle to a shared time, so a test can keep a clone to
+/// advance the time of a store that it gave the clock to. Sleeping on
+/// the clock advances it by the duration, right away.
+#[derive(Clone)]
+pub struct MockClock {
+    /// The time that the clock started at.
+    base: Instant,
+    /// How far the clock has been advanced from the start.
+    offset: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    /// Creates a clock that starts at the current system time.
+    pub fn new() -> Self {
+        Self {
+            base: Instant::now(),
+            offset: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    /// Moves the clock ahead by the duration.
+    pub fn advance(&self, dur: Duration) {
+        let mut offset = self.offset.lock().unwrap();
+        *offset += dur;
+    }
+
+    /// Gets how far the clock has been advanced since it was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.offset.lock().unwrap()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.base + self.elapsed()
+    }
+
+    fn sleep(&self, dur: Duration) {
+        self.advance(dur);
+    }
+}
+
+impl fmt::Debug for MockClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MockClock")
+            .field("elapsed", &self.elapsed())
+            .finish()
+    }
+}
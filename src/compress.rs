@@ -0,0 +1,285 @@
+// mqtt.rust.redis/src/compress.rs
+//
+// Optional compression of the stored values.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Optional compression of the stored values.
+//!
+//! This is in the `compression` feature. MQTT payloads can be large, and
+//! compress well when they're text, like JSON. With compression set, a
+//! value at least as large as the threshold is compressed before it's put
+//! into the hash, and is kept that way only if it came out smaller.
+//!
+//! A compressed value starts with a small header naming the codec, so it
+//! is decompressed when it's read back, whatever the store's compression
+//! setting is at the time. Before a store first puts a value with
+//! compression set, it records that in its metadata, and the headers are
+//! only looked for in the values of a store with that record, or with
+//! compression set. While compression is set, a value that isn't
+//! compressed gets a header too, marking it as raw, so that one which
+//! happens to start with the header of a codec can't be mistaken for a
+//! compressed value. Values that were stored without compression are read
+//! as they are.
+
+/// The header on a value compressed with zstd.
+const ZSTD_HEADER: &[u8] = b"\0zstd\0";
+
+/// The header on a value compressed with LZ4.
+const LZ4_HEADER: &[u8] = b"\0lz4\0";
+
+/// The header on a value that was stored uncompressed while compression
+/// was set.
+const RAW_HEADER: &[u8] = b"\0raw\0";
+
+/// The most that LZ4 can expand a block, as the size of the value over the
+/// size of the block, is about 255.
+const LZ4_MAX_RATIO: usize = 255;
+
+/// The default zstd compression level.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// The default size, in bytes, at which values are compressed.
+pub const DEFAULT_THRESHOLD: usize = 1024;
+
+/// The algorithm to compress the values with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// zstd, at the compression level.
+    Zstd(i32),
+    /// LZ4, which is faster, but doesn't compress as well.
+    Lz4,
+}
+
+/// The settings to compress the stored values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    /// The algorithm to compress with.
+    pub codec: Codec,
+    /// The smallest value, in bytes, that is compressed.
+    pub threshold: usize,
+}
+
+impl Compression {
+    /// Compresses values at or above the default threshold with zstd, at
+    /// the default level.
+    pub fn zstd() -> Self {
+        Self::new(Codec::Zstd(DEFAULT_ZSTD_LEVEL))
+    }
+
+    /// Compresses values at or above the default threshold with LZ4.
+    pub fn lz4() -> Self {
+        Self::new(Codec::Lz4)
+    }
+
+    /// Compresses values at or above the default threshold with the codec.
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            codec,
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+
+    /// Sets the smallest value, in bytes, that is compressed.
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Gets the value to store for a value that was put. This is the
+    /// value itself, with the raw header, if it's small, or doesn't get
+    /// any smaller.
+    pub(crate) fn encode(&self, value: Vec<u8>) -> Vec<u8> {
+        if value.len() < self.threshold {
+            return raw(&value);
+        }
+        let (header, compressed) = match self.codec {
+            Codec::Zstd(level) => match zstd::bulk::compress(&value, level) {
+                Ok(v) => (ZSTD_HEADER, v),
+                Err(e) => {
+                    warn!("Can't compress a value of {} bytes: {}", value.len(), e);
+                    return raw(&value);
+                }
+            },
+            Codec::Lz4 => (LZ4_HEADER, lz4_flex::compress_prepend_size(&value)),
+        };
+        if header.len() + compressed.len() >= value.len() + RAW_HEADER.len() {
+            return raw(&value);
+        }
+        trace!(
+            "Compressed {} bytes to {} with {:?}",
+            value.len(),
+            compressed.len(),
+            self.codec
+        );
+        [header, compressed.as_slice()].concat()
+    }
+
+    /// Describes the settings, for the config summary.
+    pub(crate) fn summary(&self) -> String {
+        let codec = match self.codec {
+            Codec::Zstd(level) => format!("zstd level {}", level),
+            Codec::Lz4 => "lz4".to_string(),
+        };
+        format!("{} at {} bytes", codec, self.threshold)
+    }
+}
+
+/// Gets the value to store, uncompressed, with the raw header.
+fn raw(value: &[u8]) -> Vec<u8> {
+    [RAW_HEADER, value].concat()
+}
+
+/// Gets the value that was put from the value that was stored,
+/// decompressing it if it has the header of a codec, or stripping the
+/// raw header.
+///
+/// A value with the header of a codec that can't be decompressed is taken
+/// to be one stored, untagged, before the raw header was added, which
+/// just happens to start with the same bytes, and is returned as it is,
+/// with a warning.
+pub(crate) fn decode(value: Vec<u8>) -> Vec<u8> {
+    let decoded = if let Some(data) = value.strip_prefix(RAW_HEADER) {
+        return data.to_vec();
+    } else if let Some(data) = value.strip_prefix(ZSTD_HEADER) {
+        zstd::stream::decode_all(data).map_err(|e| e.to_string())
+    } else if let Some(data) = value.strip_prefix(LZ4_HEADER) {
+        lz4_decompress(data)
+    } else {
+        return value;
+    };
+    decoded.unwrap_or_else(|e| {
+        warn!(
+            "Can't decompress a stored value of {} bytes, reading it as is: {}",
+            value.len(),
+            e
+        );
+        value
+    })
+}
+
+/// Decompresses an LZ4 block with its size prepended, failing, rather than
+/// allocating the memory for it, if the size is more than the block could
+/// possibly expand to.
+fn lz4_decompress(data: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    let size = match data.get(..4) {
+        Some(size) => u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize,
+        None => return Err("no size for the value".to_string()),
+    };
+    if size > data.len().saturating_mul(LZ4_MAX_RATIO) {
+        return Err(format!(
+            "a size of {} bytes is too large for {} compressed",
+            size,
+            data.len()
+        ));
+    }
+    lz4_flex::decompress_size_prepended(data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A value that compresses well.
+    fn text(n: usize) -> Vec<u8> {
+        b"{\"temp\": 21.5, \"unit\": \"C\"} ".repeat(n)
+    }
+
+    /// Values that start with the headers, but weren't compressed.
+    fn lookalikes() -> Vec<Vec<u8>> {
+        let mut values = vec![];
+        for header in [ZSTD_HEADER, LZ4_HEADER, RAW_HEADER] {
+            values.push(header.to_vec());
+            values.push([header, b"not compressed"].concat());
+            values.push([header, text(100).as_slice()].concat());
+        }
+        values
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for compression in [Compression::zstd(), Compression::lz4()] {
+            for value in [vec![], b"small".to_vec(), text(100)] {
+                let stored = compression.encode(value.clone());
+                assert_eq!(decode(stored), value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compresses_large_values() {
+        for compression in [Compression::zstd(), Compression::lz4()] {
+            let value = vec![b'x'; 4096];
+            let stored = compression.encode(value.clone());
+            assert!(stored.len() < value.len());
+            assert!(!stored.starts_with(RAW_HEADER));
+        }
+    }
+
+    #[test]
+    fn test_lookalike_round_trip() {
+        for compression in [
+            Compression::zstd(),
+            Compression::lz4(),
+            Compression::zstd().threshold(0),
+            Compression::lz4().threshold(usize::MAX),
+        ] {
+            for value in lookalikes() {
+                let stored = compression.encode(value.clone());
+                assert_eq!(decode(stored), value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lz4_size_is_capped() {
+        let value = [LZ4_HEADER, &u32::MAX.to_le_bytes(), b"\x10x"].concat();
+        assert_eq!(decode(value.clone()), value);
+
+        let value = vec![b'x'; 100_000];
+        let stored = Compression::lz4().encode(value.clone());
+        assert!(stored.len() < 1000);
+        assert_eq!(decode(stored), value);
+    }
+
+    #[test]
+    fn test_untagged_lookalike() {
+        // Stored by an older version, without the raw header
+        for value in lookalikes() {
+            if !value.starts_with(RAW_HEADER) {
+                assert_eq!(decode(value.clone()), value);
+            }
+        }
+        assert_eq!(decode(b"plain".to_vec()), b"plain");
+    }
+}
@@ -0,0 +1,80 @@
+// mqtt.rust.redis/src/config.rs
+//
+// Runtime configuration of a store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Runtime configuration of a store.
+//!
+//! The settings in a [`Config`] can be pushed to a running store with
+//! [`RedisPersistence::reload_config()`](crate::RedisPersistence::reload_config),
+//! so that a fleet-wide config change doesn't require restarting the MQTT
+//! client, and losing its session, to take effect.
+
+use crate::RateLimiter;
+use std::time::Duration;
+
+/// The runtime settings of a store.
+///
+/// A config replaces all of these settings at once, so a field that is
+/// left at its default turns that feature off.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    /// The URL of the Redis server, or `None` to keep the current one.
+    pub url: Option<String>,
+    /// The deadline for each store operation, if any.
+    pub op_deadline: Option<Duration>,
+    /// The number of times to retry a command that times out before the
+    /// deadline.
+    pub op_retries: u32,
+    /// The time each store operation has, with all its retries, if any.
+    pub latency_budget: Option<Duration>,
+    /// The time for an operation to be reported as slow, if any.
+    pub slow_threshold: Option<Duration>,
+    /// Whether to sample the server's latency reports for slow operations.
+    pub sample_latency: bool,
+    /// The limit on the rate of the store operations, if any.
+    pub rate_limiter: Option<RateLimiter>,
+    /// How long the store lives after the last write, if it expires.
+    pub ttl: Option<Duration>,
+    /// The maximum number of entries in the store, if limited.
+    pub max_entries: Option<usize>,
+}
+
+impl Config {
+    /// Creates a config with everything turned off, keeping the current
+    /// server.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,195 @@
+// mqtt.rust.redis/src/consistency.rs
+//
+// Consistency levels for the store operations.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Consistency levels for the store operations.
+//!
+//! Not every key in the store needs the same rigor. Losing track of an
+//! inbound QoS 2 message can cause a duplicate delivery, while a QoS 0
+//! queue entry is cheap to lose. A [`ConsistencyPolicy`] lets the
+//! application pick the [`Consistency`] for reads and writes separately,
+//! and override it for a [`KeyKind`], like the `Received` QoS 2 messages,
+//! or for the keys that match a glob pattern.
+
+use crate::{admin::glob_match, key_kind::KeyKind};
+use std::time::Duration;
+
+/// The level of rigor for a store operation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Consistency {
+    /// Whether to read back the result of a write to verify that it
+    /// reached the server intact. This has no effect on reads.
+    pub verify: bool,
+    /// The number of times to retry an operation that fails due to a
+    /// problem with the connection, or a timeout, reconnecting each time.
+    pub retries: u32,
+    /// The number of replicas that must have a write, by the Redis WAIT
+    /// command, before it succeeds, and the longest time to wait for them,
+    /// if any. This has no effect on reads.
+    pub replicas: Option<(usize, Duration)>,
+}
+
+impl Consistency {
+    /// Creates a consistency level.
+    pub fn new(verify: bool, retries: u32) -> Self {
+        Self {
+            verify,
+            retries,
+            replicas: None,
+        }
+    }
+
+    /// Sets the level to wait, after each write, for at least `replicas`
+    /// of the server's replicas to have it, for up to `timeout`, with the
+    /// Redis WAIT command. If fewer have it by then, the write fails with
+    /// [`Error::Unreplicated`](crate::Error::Unreplicated), although it
+    /// stays on the primary.
+    ///
+    /// A zero `timeout` waits forever, as WAIT does, up to the command
+    /// timeout or operation deadline of the store, which should be longer
+    /// than the timeout here.
+    pub fn wait_replicas(mut self, replicas: usize, timeout: Duration) -> Self {
+        self.replicas = Some((replicas, timeout));
+        self
+    }
+}
+
+/// The consistency levels for the store operations, by operation class
+/// and key name.
+#[derive(Debug, Default, Clone)]
+pub struct ConsistencyPolicy {
+    /// The level for reads of keys that don't match a rule.
+    reads: Consistency,
+    /// The level for writes of keys that don't match a rule.
+    writes: Consistency,
+    /// The rules for reads, as matching keys and level.
+    read_rules: Vec<(Rule, Consistency)>,
+    /// The rules for writes, as matching keys and level.
+    write_rules: Vec<(Rule, Consistency)>,
+}
+
+/// The keys that a rule applies to.
+#[derive(Debug, Clone)]
+enum Rule {
+    /// The keys that match a glob pattern.
+    Pattern(String),
+    /// The Paho keys of a specific kind.
+    Kind(KeyKind),
+}
+
+impl Rule {
+    /// Determines if the rule applies to the key.
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Rule::Pattern(pat) => glob_match(pat, key),
+            Rule::Kind(kind) => KeyKind::of(key) == Some(*kind),
+        }
+    }
+}
+
+impl ConsistencyPolicy {
+    /// Creates a policy with the default level, no verification and no
+    /// retries, for all operations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the level for reads: `get()`, `size_of()`, `contains_key()`,
+    /// and `keys()`.
+    pub fn reads(mut self, level: Consistency) -> Self {
+        self.reads = level;
+        self
+    }
+
+    /// Sets the level for writes: `put()` and `remove()`.
+    pub fn writes(mut self, level: Consistency) -> Self {
+        self.writes = level;
+        self
+    }
+
+    /// Adds a rule for reads of the keys that match the glob `pattern`.
+    /// The rules are checked in the order they were added, and the first
+    /// match wins.
+    pub fn read_rule(mut self, pattern: &str, level: Consistency) -> Self {
+        self.read_rules
+            .push((Rule::Pattern(pattern.to_string()), level));
+        self
+    }
+
+    /// Adds a rule for reads of the Paho keys of the specified kind.
+    /// The rules are checked in the order they were added, and the first
+    /// match wins.
+    pub fn read_kind(mut self, kind: KeyKind, level: Consistency) -> Self {
+        self.read_rules.push((Rule::Kind(kind), level));
+        self
+    }
+
+    /// Adds a rule for writes of the keys that match the glob `pattern`.
+    /// The rules are checked in the order they were added, and the first
+    /// match wins.
+    pub fn write_rule(mut self, pattern: &str, level: Consistency) -> Self {
+        self.write_rules
+            .push((Rule::Pattern(pattern.to_string()), level));
+        self
+    }
+
+    /// Adds a rule for writes of the Paho keys of the specified kind.
+    /// The rules are checked in the order they were added, and the first
+    /// match wins.
+    pub fn write_kind(mut self, kind: KeyKind, level: Consistency) -> Self {
+        self.write_rules.push((Rule::Kind(kind), level));
+        self
+    }
+
+    /// Gets the level to read the key, if any.
+    /// Reads that aren't for a single key, like `keys()`, use `None`.
+    pub fn for_read(&self, key: Option<&str>) -> Consistency {
+        Self::lookup(&self.read_rules, key).unwrap_or(self.reads)
+    }
+
+    /// Gets the level to write the key.
+    pub fn for_write(&self, key: &str) -> Consistency {
+        Self::lookup(&self.write_rules, Some(key)).unwrap_or(self.writes)
+    }
+
+    /// Finds the level of the first rule that matches the key.
+    fn lookup(rules: &[(Rule, Consistency)], key: Option<&str>) -> Option<Consistency> {
+        let key = key?;
+        rules
+            .iter()
+            .find(|(rule, _)| rule.matches(key))
+            .map(|(_, level)| *level)
+    }
+}
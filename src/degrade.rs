@@ -0,0 +1,81 @@
+// mqtt.rust.redis/src/degrade.rs
+//
+// Retrying the Redis server for a store running in memory.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Retrying the Redis server for a store running in memory.
+//!
+//! When a store that's allowed to degrade can't reach the server to open,
+//! it opens in memory instead, and a background thread tries the server
+//! again, now and then, until it can move the entries there.
+
+use crate::store::Store;
+use std::{
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::Duration,
+};
+
+/// The name of the recovery thread, as seen in a debugger or profiler.
+pub const THREAD_NAME: &str = "mqtt-redis-recover";
+
+/// Starts a thread to try the server every `interval`, until the store
+/// gets there, is closed, or is dropped.
+pub(crate) fn start(store: &Arc<Mutex<Store>>, interval: Duration) {
+    let store = Arc::downgrade(store);
+    let res = thread::Builder::new()
+        .name(THREAD_NAME.to_string())
+        .spawn(move || recover(store, interval));
+
+    if let Err(e) = res {
+        warn!("Redis persistence can't start the recovery thread: {:?}", e);
+    }
+}
+
+/// The body of the recovery thread.
+fn recover(store: Weak<Mutex<Store>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let store = match store.upgrade() {
+            Some(store) => store,
+            None => break,
+        };
+        let mut store = store.lock().unwrap_or_else(|e| e.into_inner());
+        match store.recover() {
+            Ok(()) => break,
+            Err(e) => debug!("Redis persistence still in memory: {:?}", e),
+        }
+    }
+    debug!("Recovery thread done");
+}
@@ -0,0 +1,101 @@
+// mqtt.rust.redis/src/entry_info.rs
+//
+// The metadata kept for each entry in a persistence store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! The metadata kept for each entry in a persistence store.
+//!
+//! When it's turned on, the store keeps a side hash next to its own, with
+//! a record for each key of when it was put and how big its value was.
+//! This lets the inspection tools report the age of the messages held in
+//! the store, and how it grew, without reading the values themselves.
+
+use crate::stamp;
+use std::{fmt, time::Duration};
+
+/// The metadata of an entry in the store: when it was put, and the size
+/// of its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryInfo {
+    /// The time the entry was last put, in seconds since the Unix epoch.
+    pub inserted: u64,
+    /// The size of the value, in bytes, as it was put.
+    pub size: usize,
+}
+
+impl EntryInfo {
+    /// Creates the metadata for an entry of `size` bytes put now.
+    pub fn new(size: usize) -> Self {
+        Self {
+            inserted: stamp::unix_time(),
+            size,
+        }
+    }
+
+    /// Parses the metadata from its field in the side hash, as written by
+    /// its `Display` form, "<inserted> <size>".
+    pub fn parse(s: &str) -> Option<Self> {
+        let (inserted, size) = s.split_once(' ')?;
+        Some(Self {
+            inserted: inserted.parse().ok()?,
+            size: size.parse().ok()?,
+        })
+    }
+
+    /// Gets how long ago the entry was put.
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(stamp::unix_time().saturating_sub(self.inserted))
+    }
+}
+
+impl fmt::Display for EntryInfo {
+    /// Writes the metadata as it's kept in the side hash.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.inserted, self.size)
+    }
+}
+
+/// Parses the fields of a side hash into the metadata for each key,
+/// skipping any that can't be read.
+pub(crate) fn from_fields(fields: Vec<(String, Vec<u8>)>) -> Vec<(String, EntryInfo)> {
+    fields
+        .into_iter()
+        .filter_map(|(key, v)| from_value(&v).map(|info| (key, info)))
+        .collect()
+}
+
+/// Parses the metadata from the raw value of its field in the side hash.
+pub(crate) fn from_value(v: &[u8]) -> Option<EntryInfo> {
+    std::str::from_utf8(v).ok().and_then(EntryInfo::parse)
+}
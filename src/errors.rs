@@ -0,0 +1,152 @@
+// mqtt.rust.redis/src/errors.rs
+//
+// Error types for the Redis persistence store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Error types for the Redis persistence store.
+
+use std::result;
+use thiserror::Error;
+
+/// The errors from a persistence store operation.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The store was used before it was opened, or after it was closed.
+    #[error("The persistence store is not open")]
+    NotOpen,
+    /// The requested key is not in the store.
+    #[error("Key not found")]
+    NotFound,
+    /// The operation didn't complete before its deadline.
+    #[error("Operation timed out")]
+    Timeout,
+    /// The operation exceeded the configured rate limit.
+    #[error("Rate limit exceeded")]
+    RateLimited,
+    /// The store was opened with keys left over from a previous session,
+    /// which the leftover policy doesn't allow.
+    #[error("The store contains {0} leftover keys")]
+    LeftoverKeys(usize),
+    /// A write couldn't be held for later while the connection to the
+    /// server is down, because the offline buffer is full.
+    #[error("The offline buffer is full, with {0} writes")]
+    OfflineBufferFull(usize),
+    /// A new key couldn't be put into the store because it is full.
+    #[error("The store is full, with {0} entries")]
+    QuotaExceeded(usize),
+    /// A write didn't read back as expected, as required by the
+    /// consistency level for the key.
+    #[error("The write of key '{0}' could not be verified")]
+    Unverified(String),
+    /// The Redis user isn't permitted, by the server's ACL, to run a
+    /// command that the store needs.
+    #[error("The Redis user is not permitted to run {0}")]
+    NotPermitted(String),
+    /// A write reached fewer replicas of the server than the consistency
+    /// level requires, as given, before the WAIT timed out.
+    #[error("The write of key '{0}' reached only {1} replicas")]
+    Unreplicated(String, usize),
+    /// The server refused a write as a read-only replica, and no primary
+    /// could be found among its endpoints.
+    #[error("No primary Redis server could be found")]
+    NoPrimary,
+    /// The server refused the credentials, or needs some that weren't
+    /// given, for the ACL user named.
+    #[error("Authentication failed with the Redis server, as user '{0}'")]
+    AuthFailed(String),
+    /// The store was created with a connection, rather than a client, and
+    /// can't make a new one after the connection was lost.
+    #[error("The store has no Redis client to make a new connection")]
+    NoClient,
+    /// The store's key is already in use, in this process, by the store of
+    /// a different client ID or server URI, as given.
+    #[error("The store key '{0}' is already used by client '{1}' on {2}")]
+    NameCollision(String, String, String),
+    /// A segmented value is missing one of its segments, given by its
+    /// index, so it can't be read back whole.
+    #[error("The stored value of key '{0}' is missing segment {1}")]
+    MissingSegment(String, usize),
+    /// A certificate or key file for TLS couldn't be read.
+    #[error("Can't read the TLS file '{0}': {1}")]
+    TlsFile(String, std::io::Error),
+    /// A blocking call was made to the async store, with no tokio runtime
+    /// to run it on.
+    #[cfg(feature = "async")]
+    #[error("No async runtime to run the store operation")]
+    NoRuntime,
+    /// A connection couldn't be taken from the pool in time.
+    #[cfg(feature = "pool")]
+    #[error("Redis connection pool error: {0}")]
+    Pool(#[from] r2d2::Error),
+    /// An error reading or writing a local file, like the entries spilled
+    /// to disk, or a support bundle.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// An error from the Redis client or server.
+    #[error("Redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+}
+
+impl Error {
+    /// Determines if this is an error with the connection to the Redis
+    /// server, such as a dropped connection or an I/O failure, as opposed
+    /// to an error in the request itself.
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            Error::Redis(e) => e.is_io_error() || e.is_connection_dropped(),
+            #[cfg(feature = "pool")]
+            Error::Pool(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Determines if this is the server refusing a write because it's a
+    /// read-only replica, as happens when a failover leaves the client
+    /// pointed at the old primary.
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            Error::Redis(e) => e.code() == Some("READONLY"),
+            _ => false,
+        }
+    }
+
+    /// Determines if this is an error that might go away by retrying the
+    /// operation, like a connection error or a timeout.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Timeout) || self.is_connection_error()
+    }
+}
+
+/// The result type for persistence store operations.
+pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,713 @@
+// mqtt.rust.redis/src/fake_server.rs
+//
+// A minimal stand-in for a Redis server, for the tests.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+//! A minimal stand-in for a Redis server, for the tests.
+//!
+//! This speaks enough RESP2, over a TCP socket on localhost, for a store
+//! to open and do its work with the real redis-rs client, without a Redis
+//! server. It keeps strings, hashes, sets, and streams in memory, has no
+//! expiry, and runs the adapter's Lua scripts as Rust code, picking them
+//! out by their text.
+
+use crate::{adapter, admin::glob_match};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+};
+
+/// A value kept by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Value {
+    Str(Vec<u8>),
+    Hash(BTreeMap<Vec<u8>, Vec<u8>>),
+    Set(BTreeSet<Vec<u8>>),
+    Stream(Vec<(String, Vec<Vec<u8>>)>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "string",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::Stream(_) => "stream",
+        }
+    }
+}
+
+/// A reply to a command.
+#[derive(Debug, Clone)]
+enum Reply {
+    Status(&'static str),
+    Error(String),
+    Int(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+const OK: Reply = Reply::Status("OK");
+
+fn wrong_type() -> Reply {
+    Reply::Error("WRONGTYPE Operation against a key holding the wrong kind of value".into())
+}
+
+fn bulk<T: Into<Vec<u8>>>(v: T) -> Reply {
+    Reply::Bulk(Some(v.into()))
+}
+
+fn nil() -> Reply {
+    Reply::Bulk(None)
+}
+
+fn array<I: IntoIterator<Item = Vec<u8>>>(items: I) -> Reply {
+    Reply::Array(items.into_iter().map(bulk).collect())
+}
+
+fn int<T: TryInto<i64>>(n: T) -> Reply {
+    Reply::Int(n.try_into().unwrap_or(i64::MAX))
+}
+
+fn text(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).into_owned()
+}
+
+fn number(arg: &[u8]) -> i64 {
+    text(arg).parse().unwrap_or_default()
+}
+
+/// A test's hook into each command, given its name. It returns false to
+/// have the server drop the connection instead of running the command.
+type Hook = Box<dyn FnMut(&str) -> bool + Send>;
+
+/// The data kept by the server.
+#[derive(Default)]
+pub(crate) struct Db {
+    keys: BTreeMap<String, Value>,
+    scripts: HashMap<String, String>,
+    stream_seq: u64,
+    calls: HashMap<String, usize>,
+    hook: Option<Hook>,
+    replica: bool,
+}
+
+impl Db {
+    /// Gets the names of all the keys.
+    pub fn key_names(&self) -> Vec<String> {
+        self.keys.keys().cloned().collect()
+    }
+
+    /// Gets the value of a key.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.keys.get(key)
+    }
+
+    /// Gets the number of times the command was run.
+    pub fn calls(&self, cmd: &str) -> usize {
+        self.calls.get(cmd).copied().unwrap_or_default()
+    }
+
+    /// Sets whether the server reports the role of a replica, rather than
+    /// a primary. It still takes writes.
+    pub fn set_replica(&mut self, on: bool) {
+        self.replica = on;
+    }
+
+    /// Sets a function to call with the name of each command, before it's
+    /// run, which can have the server drop the connection instead.
+    pub fn set_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&str) -> bool + Send + 'static,
+    {
+        self.hook = Some(Box::new(hook));
+    }
+
+    /// Removes a key, as if it expired.
+    pub fn remove(&mut self, key: &str) {
+        self.keys.remove(key);
+    }
+
+    /// Sets the value of a key.
+    pub fn insert(&mut self, key: &str, value: Value) {
+        self.keys.insert(key.to_string(), value);
+    }
+
+    fn hash(&mut self, key: &[u8]) -> Result<&mut BTreeMap<Vec<u8>, Vec<u8>>, Reply> {
+        let v = self
+            .keys
+            .entry(text(key))
+            .or_insert_with(|| Value::Hash(BTreeMap::new()));
+        match v {
+            Value::Hash(h) => Ok(h),
+            _ => Err(wrong_type()),
+        }
+    }
+
+    fn set(&mut self, key: &[u8]) -> Result<&mut BTreeSet<Vec<u8>>, Reply> {
+        let v = self
+            .keys
+            .entry(text(key))
+            .or_insert_with(|| Value::Set(BTreeSet::new()));
+        match v {
+            Value::Set(s) => Ok(s),
+            _ => Err(wrong_type()),
+        }
+    }
+
+    /// Removes a hash or set that was left empty, as Redis does.
+    fn prune(&mut self, key: &[u8]) {
+        let key = text(key);
+        let empty = match self.keys.get(&key) {
+            Some(Value::Hash(h)) => h.is_empty(),
+            Some(Value::Set(s)) => s.is_empty(),
+            _ => false,
+        };
+        if empty {
+            self.keys.remove(&key);
+        }
+    }
+
+    fn hget(&mut self, key: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>, Reply> {
+        match self.keys.get(&text(key)) {
+            None => Ok(None),
+            Some(Value::Hash(h)) => Ok(h.get(field).cloned()),
+            Some(_) => Err(wrong_type()),
+        }
+    }
+
+    fn hset(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> Result<i64, Reply> {
+        let added = self.hash(key)?.insert(field.to_vec(), value.to_vec());
+        Ok(i64::from(added.is_none()))
+    }
+
+    fn hdel(&mut self, key: &[u8], fields: &[Vec<u8>]) -> Result<i64, Reply> {
+        let n = match self.keys.get_mut(&text(key)) {
+            None => 0,
+            Some(Value::Hash(h)) => fields.iter().filter(|f| h.remove(*f).is_some()).count(),
+            Some(_) => return Err(wrong_type()),
+        };
+        self.prune(key);
+        Ok(n as i64)
+    }
+
+    fn exists(&self, key: &[u8]) -> bool {
+        self.keys.contains_key(&text(key))
+    }
+
+    fn matching(&self, pattern: Option<&[u8]>, ty: Option<&[u8]>) -> Vec<Vec<u8>> {
+        self.keys
+            .iter()
+            .filter(|(k, _)| pattern.iter().all(|p| glob_match(&text(p), k)))
+            .filter(|(_, v)| {
+                ty.iter()
+                    .all(|t| t.eq_ignore_ascii_case(v.type_name().as_bytes()))
+            })
+            .map(|(k, _)| k.clone().into_bytes())
+            .collect()
+    }
+
+    /// Runs a single command.
+    fn exec(&mut self, args: &[Vec<u8>]) -> Reply {
+        match self.try_exec(args) {
+            Ok(reply) | Err(reply) => reply,
+        }
+    }
+
+    fn try_exec(&mut self, args: &[Vec<u8>]) -> Result<Reply, Reply> {
+        let cmd = text(&args[0]).to_ascii_uppercase();
+        *self.calls.entry(cmd.clone()).or_default() += 1;
+        let a = &args[1..];
+        let reply = match cmd.as_str() {
+            "PING" => Reply::Status("PONG"),
+            "SELECT" | "CLIENT" | "READONLY" => OK,
+            "ECHO" => bulk(a[0].clone()),
+            "INFO" => bulk("# Server\r\nredis_version:7.2.0\r\n"),
+            "ROLE" if self.replica => array([b"slave".to_vec()]),
+            "ROLE" => Reply::Array(vec![bulk("master"), int(0), Reply::Array(vec![])]),
+            "WAIT" | "PUBLISH" => int(0),
+            "TYPE" => Reply::Status(match self.keys.get(&text(&a[0])) {
+                Some(v) => v.type_name(),
+                None => "none",
+            }),
+            "EXISTS" => int(a.iter().filter(|k| self.exists(k)).count()),
+            "DEL" | "UNLINK" => int(a
+                .iter()
+                .filter(|k| self.keys.remove(&text(k)).is_some())
+                .count()),
+            "EXPIRE" | "PEXPIRE" | "PERSIST" => int(i64::from(self.exists(&a[0]))),
+            "RENAME" => match self.keys.remove(&text(&a[0])) {
+                Some(v) => {
+                    self.keys.insert(text(&a[1]), v);
+                    OK
+                }
+                None => return Err(Reply::Error("ERR no such key".into())),
+            },
+            "SCAN" => {
+                let pattern = option(a, "MATCH");
+                let ty = option(a, "TYPE");
+                Reply::Array(vec![bulk("0"), array(self.matching(pattern, ty))])
+            }
+            "GET" => match self.keys.get(&text(&a[0])) {
+                None => nil(),
+                Some(Value::Str(v)) => bulk(v.clone()),
+                Some(_) => return Err(wrong_type()),
+            },
+            "MGET" => Reply::Array(
+                a.iter()
+                    .map(|k| match self.keys.get(&text(k)) {
+                        Some(Value::Str(v)) => bulk(v.clone()),
+                        _ => nil(),
+                    })
+                    .collect(),
+            ),
+            "STRLEN" => match self.keys.get(&text(&a[0])) {
+                None => int(0),
+                Some(Value::Str(v)) => int(v.len()),
+                Some(_) => return Err(wrong_type()),
+            },
+            "SET" => {
+                let nx = a[2..].iter().any(|o| o.eq_ignore_ascii_case(b"NX"));
+                if nx && self.exists(&a[0]) {
+                    nil()
+                } else {
+                    self.keys.insert(text(&a[0]), Value::Str(a[1].clone()));
+                    OK
+                }
+            }
+            "HSET" | "HMSET" => {
+                let mut n = 0;
+                for pair in a[1..].chunks(2) {
+                    n += self.hset(&a[0], &pair[0], &pair[1])?;
+                }
+                if cmd == "HMSET" {
+                    OK
+                } else {
+                    int(n)
+                }
+            }
+            "HSETNX" => {
+                if self.hget(&a[0], &a[1])?.is_some() {
+                    int(0)
+                } else {
+                    int(self.hset(&a[0], &a[1], &a[2])?)
+                }
+            }
+            "HGET" => Reply::Bulk(self.hget(&a[0], &a[1])?),
+            "HMGET" => {
+                let values = a[1..]
+                    .iter()
+                    .map(|f| self.hget(&a[0], f).map(Reply::Bulk))
+                    .collect::<Result<_, _>>()?;
+                Reply::Array(values)
+            }
+            "HDEL" => int(self.hdel(&a[0], &a[1..])?),
+            "HEXISTS" => int(i64::from(self.hget(&a[0], &a[1])?.is_some())),
+            "HSTRLEN" => int(self.hget(&a[0], &a[1])?.map_or(0, |v| v.len())),
+            "HINCRBY" => {
+                let n = self.hget(&a[0], &a[1])?.map_or(0, |v| number(&v)) + number(&a[2]);
+                self.hset(&a[0], &a[1], n.to_string().as_bytes())?;
+                int(n)
+            }
+            "HLEN" | "HKEYS" | "HVALS" | "HGETALL" | "HSCAN" => {
+                let h = match self.keys.get(&text(&a[0])) {
+                    None => BTreeMap::new(),
+                    Some(Value::Hash(h)) => h.clone(),
+                    Some(_) => return Err(wrong_type()),
+                };
+                match cmd.as_str() {
+                    "HLEN" => int(h.len()),
+                    "HKEYS" => array(h.into_keys()),
+                    "HVALS" => array(h.into_values()),
+                    "HGETALL" => array(h.into_iter().flat_map(|(k, v)| [k, v])),
+                    _ => {
+                        let pattern = option(&a[1..], "MATCH").map(text);
+                        let entries = h
+                            .into_iter()
+                            .filter(|(k, _)| pattern.iter().all(|p| glob_match(p, &text(k))))
+                            .flat_map(|(k, v)| [k, v]);
+                        Reply::Array(vec![bulk("0"), array(entries)])
+                    }
+                }
+            }
+            "SADD" => {
+                let set = self.set(&a[0])?;
+                int(a[1..].iter().filter(|m| set.insert(m.to_vec())).count())
+            }
+            "SREM" => {
+                let n = match self.keys.get_mut(&text(&a[0])) {
+                    None => 0,
+                    Some(Value::Set(s)) => a[1..].iter().filter(|m| s.remove(*m)).count(),
+                    Some(_) => return Err(wrong_type()),
+                };
+                self.prune(&a[0]);
+                int(n)
+            }
+            "SCARD" | "SMEMBERS" | "SSCAN" | "SISMEMBER" => {
+                let s = match self.keys.get(&text(&a[0])) {
+                    None => BTreeSet::new(),
+                    Some(Value::Set(s)) => s.clone(),
+                    Some(_) => return Err(wrong_type()),
+                };
+                match cmd.as_str() {
+                    "SCARD" => int(s.len()),
+                    "SISMEMBER" => int(i64::from(s.contains(&a[1]))),
+                    "SMEMBERS" => array(s),
+                    _ => Reply::Array(vec![bulk("0"), array(s)]),
+                }
+            }
+            "XADD" => {
+                let start = a
+                    .iter()
+                    .position(|arg| arg == b"*")
+                    .ok_or_else(|| Reply::Error("ERR only '*' IDs are supported".into()))?;
+                self.stream_seq += 1;
+                let id = format!("1-{}", self.stream_seq);
+                let fields = a[start + 1..].to_vec();
+                match self
+                    .keys
+                    .entry(text(&a[0]))
+                    .or_insert_with(|| Value::Stream(Vec::new()))
+                {
+                    Value::Stream(entries) => entries.push((id.clone(), fields)),
+                    _ => return Err(wrong_type()),
+                }
+                bulk(id)
+            }
+            "XRANGE" | "XREVRANGE" => {
+                let mut entries = match self.keys.get(&text(&a[0])) {
+                    None => Vec::new(),
+                    Some(Value::Stream(entries)) => entries.clone(),
+                    Some(_) => return Err(wrong_type()),
+                };
+                if cmd == "XREVRANGE" {
+                    entries.reverse();
+                }
+                if let Some(n) = option(a, "COUNT") {
+                    entries.truncate(number(n) as usize);
+                }
+                Reply::Array(
+                    entries
+                        .into_iter()
+                        .map(|(id, fields)| Reply::Array(vec![bulk(id), array(fields)]))
+                        .collect(),
+                )
+            }
+            "EVAL" => {
+                let script = text(&a[0]);
+                self.eval(&script, &a[1..])?
+            }
+            "EVALSHA" => match self.scripts.get(&text(&a[0])).cloned() {
+                Some(script) => self.eval(&script, &a[1..])?,
+                None => return Err(Reply::Error("NOSCRIPT No matching script".into())),
+            },
+            _ => {
+                eprintln!("Fake Redis server: unknown command {}", cmd);
+                return Err(Reply::Error(format!("ERR unknown command '{}'", cmd)));
+            }
+        };
+        Ok(reply)
+    }
+
+    /// Runs one of the adapter's scripts, with its number of keys, keys,
+    /// and arguments.
+    fn eval(&mut self, script: &str, a: &[Vec<u8>]) -> Result<Reply, Reply> {
+        let nkeys = number(&a[0]) as usize;
+        let (keys, argv) = a[1..].split_at(nkeys);
+        let stamp = |db: &mut Self, meta: &[u8], field: &[u8], writer: &[u8]| {
+            let prev = db.hget(meta, field)?;
+            db.hset(meta, field, writer)?;
+            Ok::<_, Reply>(Reply::Bulk(prev))
+        };
+        let reply = if script == adapter::STAMPED_HSETNX {
+            if self.hget(&keys[0], &argv[0])?.is_some() {
+                Reply::Array(vec![nil(), int(0)])
+            } else {
+                let added = self.hset(&keys[0], &argv[0], &argv[1])?;
+                let prev = stamp(self, &keys[1], &argv[2], &argv[3])?;
+                Reply::Array(vec![prev, int(added)])
+            }
+        } else if script == adapter::STAMPED_SET {
+            let added = i64::from(!self.exists(&keys[0]));
+            if added == 0 && argv[4] == b"1" {
+                Reply::Array(vec![nil(), int(0)])
+            } else {
+                self.keys
+                    .insert(text(&keys[0]), Value::Str(argv[0].clone()));
+                self.set(&keys[2])?.insert(keys[0].clone());
+                let prev = stamp(self, &keys[1], &argv[1], &argv[2])?;
+                Reply::Array(vec![prev, int(added)])
+            }
+        } else if script == adapter::SEQUENCED_HSET {
+            let last = self.hget(&keys[1], &argv[3])?.map_or(0, |v| number(&v));
+            if last >= number(&argv[4]) {
+                nil()
+            } else {
+                let prev = self.hget(&keys[1], &argv[1])?;
+                let added = self.hset(&keys[0], &argv[0], &argv[2])?;
+                self.hset(&keys[1], &argv[1], &argv[5])?;
+                self.hset(&keys[1], &argv[3], &argv[4])?;
+                Reply::Array(vec![Reply::Bulk(prev), int(added)])
+            }
+        } else if script == adapter::SEGMENTED_HSET || script == adapter::CHUNKED_COMMIT {
+            let key = text(&argv[0]);
+            let (n, writer) = if script == adapter::SEGMENTED_HSET {
+                (argv.len() - 3, (&argv[1], &argv[2]))
+            } else {
+                (number(&argv[1]) as usize, (&argv[2], &argv[3]))
+            };
+            let old = self.hget(&keys[0], key.as_bytes())?;
+            if let Some(old_n) = old.as_deref().and_then(crate::segment::count) {
+                let stale: Vec<Vec<u8>> = (n..old_n)
+                    .map(|i| format!("{}:{}", key, i).into_bytes())
+                    .collect();
+                self.hdel(&keys[0], &stale)?;
+            }
+            let prev = self.hget(&keys[1], writer.0)?;
+            let header = format!("\0seg:{}", n);
+            let added = self.hset(&keys[0], key.as_bytes(), header.as_bytes())?;
+            if script == adapter::SEGMENTED_HSET {
+                for (i, segment) in argv[3..].iter().enumerate() {
+                    self.hset(&keys[0], format!("{}:{}", key, i).as_bytes(), segment)?;
+                }
+            }
+            self.hset(&keys[1], writer.0, writer.1)?;
+            Reply::Array(vec![Reply::Bulk(prev), int(added)])
+        } else if script == adapter::SEGMENTED_HDEL {
+            let key = text(&argv[0]);
+            let old = self.hget(&keys[0], key.as_bytes())?;
+            if let Some(n) = old.as_deref().and_then(crate::segment::count) {
+                let segments: Vec<Vec<u8>> = (0..n)
+                    .map(|i| format!("{}:{}", key, i).into_bytes())
+                    .collect();
+                self.hdel(&keys[0], &segments)?;
+            }
+            int(self.hdel(&keys[0], &argv[..1])?)
+        } else if script == adapter::SEQUENCED_HDEL {
+            let last = self.hget(&keys[1], &argv[0])?.map_or(0, |v| number(&v));
+            if last >= number(&argv[1]) {
+                nil()
+            } else {
+                self.hset(&keys[1], &argv[0], &argv[1])?;
+                int(self.hdel(&keys[0], &argv[2..])?)
+            }
+        } else if script == adapter::MERGE_HASH {
+            match self.keys.remove(&text(&keys[0])) {
+                None => int(0),
+                Some(Value::Hash(from)) => {
+                    let n = from.len();
+                    let to = self.hash(&keys[1])?;
+                    for (k, v) in from {
+                        to.entry(k).or_insert(v);
+                    }
+                    int(n)
+                }
+                Some(_) => return Err(wrong_type()),
+            }
+        } else {
+            return Err(Reply::Error("ERR unknown script".into()));
+        };
+        Ok(reply)
+    }
+}
+
+/// Gets the argument that follows the named option, like `MATCH`.
+fn option<'a>(args: &'a [Vec<u8>], name: &str) -> Option<&'a [u8]> {
+    args.iter()
+        .position(|arg| arg.eq_ignore_ascii_case(name.as_bytes()))
+        .and_then(|i| args.get(i + 1))
+        .map(Vec::as_slice)
+}
+
+/// A stand-in Redis server, which runs until the test process exits.
+pub(crate) struct FakeServer {
+    port: u16,
+    db: Arc<Mutex<Db>>,
+}
+
+impl FakeServer {
+    /// Starts a server on a free port on localhost.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let db = Arc::new(Mutex::new(Db::default()));
+        let shared = Arc::clone(&db);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let db = Arc::clone(&shared);
+                thread::spawn(move || {
+                    let _ = serve(stream, db);
+                });
+            }
+        });
+        Self { port, db }
+    }
+
+    /// Gets the URL of the server, for a Redis client.
+    pub fn url(&self) -> String {
+        format!("redis://127.0.0.1:{}/", self.port)
+    }
+
+    /// Locks the data, to look at or change it directly.
+    pub fn db(&self) -> MutexGuard<'_, Db> {
+        self.db.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Serves the commands from a client until it disconnects.
+fn serve(stream: TcpStream, db: Arc<Mutex<Db>>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut out = stream.try_clone()?;
+    let mut input = BufReader::new(stream);
+    let mut queued: Option<Vec<Vec<Vec<u8>>>> = None;
+    let mut pending_sha = None;
+
+    while let Some(args) = read_command(&mut input)? {
+        if args.is_empty() {
+            continue;
+        }
+        let cmd = text(&args[0]).to_ascii_uppercase();
+        let mut db = db.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(hook) = db.hook.as_mut() {
+            if !hook(&cmd) {
+                return Ok(());
+            }
+        }
+        let reply = match (cmd.as_str(), queued.as_mut()) {
+            ("MULTI", _) => {
+                queued = Some(Vec::new());
+                OK
+            }
+            ("EXEC", Some(_)) => Reply::Array(
+                queued
+                    .take()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|args| db.exec(args))
+                    .collect(),
+            ),
+            ("DISCARD", Some(_)) => {
+                queued = None;
+                OK
+            }
+            (_, Some(cmds)) => {
+                cmds.push(args);
+                Reply::Status("QUEUED")
+            }
+            ("QUIT", None) => {
+                write_reply(&mut out, &OK)?;
+                return Ok(());
+            }
+            // The client sends the script after an EVALSHA it doesn't
+            // know, so it's filed under that SHA.
+            ("SCRIPT", None) if args[1].eq_ignore_ascii_case(b"LOAD") => {
+                let sha: String = pending_sha.take().unwrap_or_default();
+                db.scripts.insert(sha.clone(), text(&args[2]));
+                bulk(sha)
+            }
+            ("EVALSHA", None) => {
+                let reply = db.exec(&args);
+                if matches!(&reply, Reply::Error(e) if e.starts_with("NOSCRIPT")) {
+                    pending_sha = Some(text(&args[1]));
+                }
+                reply
+            }
+            _ => db.exec(&args),
+        };
+        drop(db);
+        write_reply(&mut out, &reply)?;
+    }
+    Ok(())
+}
+
+/// Reads a command, as an array of bulk strings, or `None` at the end of
+/// the stream.
+fn read_command<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let bad = || io::Error::new(io::ErrorKind::InvalidData, "bad RESP");
+    let line = match read_line(input)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let n: usize = line
+        .strip_prefix('*')
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(bad)?;
+    let mut args = Vec::with_capacity(n);
+    for _ in 0..n {
+        let len: usize = read_line(input)?
+            .and_then(|line| line.strip_prefix('$').and_then(|n| n.parse().ok()))
+            .ok_or_else(bad)?;
+        let mut arg = vec![0; len + 2];
+        input.read_exact(&mut arg)?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+fn read_line<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end().to_string()))
+}
+
+fn write_reply<W: Write>(out: &mut W, reply: &Reply) -> io::Result<()> {
+    let mut buf = Vec::new();
+    encode(&mut buf, reply);
+    out.write_all(&buf)
+}
+
+fn encode(buf: &mut Vec<u8>, reply: &Reply) {
+    match reply {
+        Reply::Status(s) => buf.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+        Reply::Error(e) => buf.extend_from_slice(format!("-{}\r\n", e).as_bytes()),
+        Reply::Int(n) => buf.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+        Reply::Bulk(None) => buf.extend_from_slice(b"$-1\r\n"),
+        Reply::Bulk(Some(v)) => {
+            buf.extend_from_slice(format!("${}\r\n", v.len()).as_bytes());
+            buf.extend_from_slice(v);
+            buf.extend_from_slice(b"\r\n");
+        }
+        Reply::Array(items) => {
+            buf.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+            for item in items {
+                encode(buf, item);
+            }
+        }
+    }
+}
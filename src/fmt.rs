@@ -0,0 +1,134 @@
+// mqtt.rust.redis/src/fmt.rs
+//
+// Formatting of binary values for display.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Formatting of binary values for display.
+//!
+//! The values in a store are raw MQTT packets and messages, which can hold
+//! anything, including terminal control sequences and huge payloads. These
+//! helpers make them safe to print or log, showing no more than a limited
+//! number of bytes, with anything that isn't printable ASCII replaced.
+//!
+//! Both formatters are lazy wrappers that implement `Display`, so they
+//! cost nothing in a log statement that's filtered out.
+
+use std::fmt;
+
+/// The default number of bytes to show of a value.
+pub const DEFAULT_LIMIT: usize = 64;
+
+/// The number of bytes on each line of a hexdump.
+const BYTES_PER_LINE: usize = 16;
+
+/// Gets the character to show for a byte in a preview, as itself if it's
+/// printable ASCII, or as a '.' otherwise.
+fn printable(b: u8) -> char {
+    if b.is_ascii_graphic() || b == b' ' {
+        b as char
+    } else {
+        '.'
+    }
+}
+
+/// Writes the note for the bytes that weren't shown, if any.
+fn write_more(f: &mut fmt::Formatter, data: &[u8], limit: usize) -> fmt::Result {
+    if data.len() > limit {
+        write!(f, "... (+{} bytes)", data.len() - limit)?;
+    }
+    Ok(())
+}
+
+/// A printable, single-line, preview of a binary value, showing up to a
+/// limited number of bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct Preview<'a> {
+    /// The value.
+    data: &'a [u8],
+    /// The maximum number of bytes to show.
+    limit: usize,
+}
+
+/// Creates a printable preview of the value, showing up to `limit` bytes.
+pub fn preview(data: &[u8], limit: usize) -> Preview<'_> {
+    Preview { data, limit }
+}
+
+impl fmt::Display for Preview<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let n = self.data.len().min(self.limit);
+        for &b in &self.data[..n] {
+            write!(f, "{}", printable(b))?;
+        }
+        write_more(f, self.data, self.limit)
+    }
+}
+
+/// A hexdump of a binary value, showing up to a limited number of bytes.
+///
+/// Each line has the offset, the bytes in hex, and the printable preview
+/// of them, like the output of `hexdump -C`.
+#[derive(Debug, Clone, Copy)]
+pub struct HexDump<'a> {
+    /// The value.
+    data: &'a [u8],
+    /// The maximum number of bytes to show.
+    limit: usize,
+}
+
+/// Creates a hexdump of the value, showing up to `limit` bytes.
+pub fn hexdump(data: &[u8], limit: usize) -> HexDump<'_> {
+    HexDump { data, limit }
+}
+
+impl fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let n = self.data.len().min(self.limit);
+        for (i, line) in self.data[..n].chunks(BYTES_PER_LINE).enumerate() {
+            write!(f, "{:08x} ", i * BYTES_PER_LINE)?;
+            for j in 0..BYTES_PER_LINE {
+                match line.get(j) {
+                    Some(b) => write!(f, " {:02x}", b)?,
+                    None => write!(f, "   ")?,
+                }
+            }
+            write!(f, "  |")?;
+            for &b in line {
+                write!(f, "{}", printable(b))?;
+            }
+            writeln!(f, "|")?;
+        }
+        write_more(f, self.data, self.limit)
+    }
+}
@@ -0,0 +1,183 @@
+// mqtt.rust.redis/src/growth.rs
+//
+// Monitoring the growth of a persistence store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Monitoring the growth of a persistence store.
+//!
+//! When the MQTT broker is unreachable, but the application keeps
+//! publishing, the outbound messages pile up in the store. A backlog that
+//! keeps growing, interval after interval, is the signature of that kind
+//! of outage. The monitor samples the size of the store on a fixed
+//! interval, tracks the growth rate, and can fire an alarm callback when
+//! the store has grown for a number of intervals in a row.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// The information passed to a growth alarm callback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrowthAlarm {
+    /// The number of entries in the store.
+    pub len: usize,
+    /// The growth rate over the last interval, in entries per minute.
+    pub rate: f64,
+    /// The number of consecutive intervals that the store has grown.
+    pub intervals: u32,
+}
+
+/// The callback for a growth alarm.
+type AlarmCallback = Box<dyn FnMut(&GrowthAlarm) + Send + 'static>;
+
+/// A monitor for the growth of a store.
+pub struct GrowthMonitor {
+    /// The time between samples of the store size.
+    interval: Duration,
+    /// The number of consecutive growing intervals to fire the alarm.
+    threshold: u32,
+    /// The alarm callback, if any.
+    callback: Option<AlarmCallback>,
+    /// The time of the last sample.
+    last: Instant,
+    /// The size of the store at the last sample.
+    last_len: Option<usize>,
+    /// The growth rate over the last interval, in entries per minute.
+    rate: Option<f64>,
+    /// The number of consecutive intervals that the store has grown.
+    growing: u32,
+}
+
+impl GrowthMonitor {
+    /// Creates a monitor that samples the size of the store on the
+    /// interval, to track its growth rate.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            threshold: 0,
+            callback: None,
+            last: Instant::now(),
+            last_len: None,
+            rate: None,
+            growing: 0,
+        }
+    }
+
+    /// Creates a monitor that also fires the alarm callback when the
+    /// store has grown for `intervals` consecutive intervals.
+    ///
+    /// The alarm fires once when the threshold is reached, and is re-armed
+    /// when the store stops growing. The callback is invoked from within a
+    /// store operation, with the store locked, so it must not call back
+    /// into the persistence object.
+    pub fn with_alarm<F>(interval: Duration, intervals: u32, callback: F) -> Self
+    where
+        F: FnMut(&GrowthAlarm) + Send + 'static,
+    {
+        Self {
+            threshold: intervals.max(1),
+            callback: Some(Box::new(callback)),
+            ..Self::new(interval)
+        }
+    }
+
+    /// Gets the growth rate over the last interval, in entries per minute,
+    /// if there have been enough samples to know it.
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Gets the number of consecutive intervals that the store has grown.
+    pub fn growing_intervals(&self) -> u32 {
+        self.growing
+    }
+
+    /// Starts over, as when the store is opened.
+    pub(crate) fn reset(&mut self, now: Instant) {
+        self.last = now;
+        self.last_len = None;
+        self.rate = None;
+        self.growing = 0;
+    }
+
+    /// Determines if it's time to take another sample.
+    /// The first sample is due right away.
+    pub(crate) fn is_due(&self, now: Instant) -> bool {
+        self.last_len.is_none() || now.saturating_duration_since(self.last) >= self.interval
+    }
+
+    /// Records a sample of the size of the store, firing the alarm if
+    /// this sample reaches the threshold.
+    pub(crate) fn sample(&mut self, len: usize, now: Instant) {
+        if let Some(last_len) = self.last_len {
+            let mins = now.saturating_duration_since(self.last).as_secs_f64() / 60.0;
+            let rate = (len as f64 - last_len as f64) / mins.max(f64::EPSILON);
+            self.rate = Some(rate);
+
+            if len > last_len {
+                self.growing += 1;
+                if self.growing == self.threshold {
+                    let alarm = GrowthAlarm {
+                        len,
+                        rate,
+                        intervals: self.growing,
+                    };
+                    warn!(
+                        "Redis persistence store has grown for {} intervals, to {} entries",
+                        alarm.intervals, len
+                    );
+                    if let Some(callback) = self.callback.as_mut() {
+                        callback(&alarm);
+                    }
+                }
+            } else {
+                self.growing = 0;
+            }
+        }
+        self.last = now;
+        self.last_len = Some(len);
+    }
+}
+
+impl fmt::Debug for GrowthMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GrowthMonitor")
+            .field("interval", &self.interval)
+            .field("threshold", &self.threshold)
+            .field("rate", &self.rate)
+            .field("growing", &self.growing)
+            .finish()
+    }
+}
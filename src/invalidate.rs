@@ -0,0 +1,182 @@
+// mqtt.rust.redis/src/invalidate.rs
+//
+// Invalidation of cached views of a shared store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Invalidation of cached views of a shared store.
+//!
+//! Normally a store has a single writer, the MQTT client that owns it,
+//! which is what lets it keep a local cache. But a second process can
+//! legitimately read the same store, like an inspector showing a live
+//! view of a client's backlog. Rather than polling the server, the reader
+//! can keep its cache fresh from invalidation messages that the owner
+//! publishes with each write.
+//!
+//! The messages use the sharded pub/sub of Redis 7 (SPUBLISH and
+//! SSUBSCRIBE), so on a cluster they stay on the shard that holds the
+//! store, rather than being broadcast to every node.
+
+use crate::{adapter, store::Store, Result};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The name of the listener thread, as seen in a debugger or profiler.
+pub const THREAD_NAME: &str = "mqtt-redis-invalidate";
+
+/// How often the listener checks if it's been stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Creates the name of the invalidation channel for a store.
+pub fn channel_name(store_name: &str) -> String {
+    format!("{}:inval", store_name)
+}
+
+/// A change to a store, which invalidates a cached view of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation {
+    /// The key was put into the store.
+    Put(String),
+    /// The key was removed from the store.
+    Remove(String),
+    /// The whole store was cleared.
+    Clear,
+}
+
+impl Invalidation {
+    /// Encodes the invalidation as the payload of a message.
+    pub fn encode(&self) -> String {
+        match self {
+            Invalidation::Put(key) => format!("put {}", key),
+            Invalidation::Remove(key) => format!("remove {}", key),
+            Invalidation::Clear => "clear".to_string(),
+        }
+    }
+
+    /// Decodes the invalidation from the payload of a message.
+    pub fn decode(msg: &str) -> Option<Self> {
+        match msg.split_once(' ') {
+            Some(("put", key)) => Some(Invalidation::Put(key.to_string())),
+            Some(("remove", key)) => Some(Invalidation::Remove(key.to_string())),
+            None if msg == "clear" => Some(Invalidation::Clear),
+            _ => None,
+        }
+    }
+}
+
+/// A background listener that applies the invalidations published by
+/// the owner of a store to a reader's cached view of it.
+///
+/// The listener runs until this object is dropped, or the store is.
+#[derive(Debug)]
+pub struct InvalidationListener {
+    /// Flag to stop the listener thread.
+    stop: Arc<AtomicBool>,
+    /// The listener thread.
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl InvalidationListener {
+    /// Subscribes to the invalidations for the store, and starts applying
+    /// them in a background thread.
+    pub(crate) fn start(store: &Arc<Mutex<Store>>) -> Result<Self> {
+        let (client, channel) = {
+            let store = store.lock().unwrap_or_else(|e| e.into_inner());
+            (store.client()?, channel_name(store.name()))
+        };
+        let mut conn = adapter::connect(&client)?;
+        adapter::ssubscribe(&mut conn, &channel)?;
+        conn.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let store = Arc::downgrade(store);
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name(THREAD_NAME.to_string())
+                .spawn(move || listen(conn, store, stop))?
+        };
+        debug!("Listening for invalidations on {}", channel);
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Stops the listener, waiting for its thread to finish.
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for InvalidationListener {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The body of the listener thread.
+fn listen(mut conn: redis::Connection, store: Weak<Mutex<Store>>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        let msg = match adapter::recv_smessage(&mut conn) {
+            Ok(Some(msg)) => msg,
+            Ok(None) => continue,
+            Err(e) if e.is_timeout() => continue,
+            Err(e) => {
+                warn!("Redis persistence invalidation listener error: {:?}", e);
+                break;
+            }
+        };
+        let store = match store.upgrade() {
+            Some(store) => store,
+            None => break,
+        };
+        match Invalidation::decode(&msg) {
+            Some(inval) => store
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .apply_invalidation(&inval),
+            None => debug!("Ignoring bad invalidation message: {:?}", msg),
+        }
+    }
+    debug!("Invalidation listener done");
+}
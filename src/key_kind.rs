@@ -0,0 +1,258 @@
+// mqtt.rust.redis/src/key_kind.rs
+//
+// Classification of the Paho persistence keys.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Classification of the Paho persistence keys.
+//!
+//! The Paho C library, which does the persistence for the Rust client,
+//! names each entry with a short prefix for the type of entry, followed
+//! by a number: the MQTT packet ID for messages in flight, or a sequence
+//! number for the queues of the async client. For example, `"s-12"` is
+//! the outbound PUBLISH with packet ID 12, and `"r5-7"` is an inbound
+//! QoS 2 PUBLISH, from an MQTT v5 session, with packet ID 7.
+
+use std::fmt;
+
+/// The type of entry held under a Paho persistence key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyKind {
+    /// An outbound QoS 1 or 2 PUBLISH, awaiting its acknowledgment.
+    Sent,
+    /// The PUBREL for an outbound QoS 2 message, awaiting the PUBCOMP.
+    PubRel,
+    /// An inbound QoS 2 PUBLISH, awaiting the PUBREL.
+    Received,
+    /// A command queued by the async client, like a publish that hasn't
+    /// been sent yet.
+    Command,
+    /// An inbound message queued for delivery to the application.
+    Queued,
+}
+
+/// The direction of the messages for a kind of key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Direction {
+    /// Messages going out to the broker.
+    Outbound,
+    /// Messages coming in from the broker.
+    Inbound,
+}
+
+impl KeyKind {
+    /// All the kinds of keys.
+    pub const ALL: [KeyKind; 5] = [
+        KeyKind::Sent,
+        KeyKind::PubRel,
+        KeyKind::Received,
+        KeyKind::Command,
+        KeyKind::Queued,
+    ];
+
+    /// Gets the kind of the persistence key, if it's one that Paho uses.
+    pub fn of(key: &str) -> Option<KeyKind> {
+        PahoKey::parse(key).map(|k| k.kind)
+    }
+
+    /// Gets the key prefix for this kind, as used in MQTT v3.x sessions.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            KeyKind::Sent => "s-",
+            KeyKind::PubRel => "sc-",
+            KeyKind::Received => "r-",
+            KeyKind::Command => "c-",
+            KeyKind::Queued => "q-",
+        }
+    }
+
+    /// Gets the key prefix for this kind, as used in MQTT v5 sessions.
+    pub fn v5_prefix(&self) -> &'static str {
+        match self {
+            KeyKind::Sent => "s5-",
+            KeyKind::PubRel => "sc5-",
+            KeyKind::Received => "r5-",
+            KeyKind::Command => "c5-",
+            KeyKind::Queued => "q5-",
+        }
+    }
+
+    /// Gets the direction of the messages for this kind of key.
+    pub fn direction(&self) -> Direction {
+        match self {
+            KeyKind::Sent | KeyKind::PubRel | KeyKind::Command => Direction::Outbound,
+            KeyKind::Received | KeyKind::Queued => Direction::Inbound,
+        }
+    }
+
+    /// Determines if the number in the key is an MQTT packet ID, rather
+    /// than a sequence number.
+    pub fn has_packet_id(&self) -> bool {
+        matches!(self, KeyKind::Sent | KeyKind::PubRel | KeyKind::Received)
+    }
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            KeyKind::Sent => "sent",
+            KeyKind::PubRel => "pubrel",
+            KeyKind::Received => "received",
+            KeyKind::Command => "command",
+            KeyKind::Queued => "queued",
+        };
+        f.write_str(s)
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Outbound => f.write_str("outbound"),
+            Direction::Inbound => f.write_str("inbound"),
+        }
+    }
+}
+
+/// A Paho persistence key, parsed into its parts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PahoKey {
+    /// The kind of entry.
+    pub kind: KeyKind,
+    /// Whether the entry is from an MQTT v5 session.
+    pub v5: bool,
+    /// The packet ID or sequence number, according to the kind.
+    pub id: u32,
+}
+
+impl PahoKey {
+    /// Parses a persistence key, if it's one that Paho uses.
+    pub fn parse(key: &str) -> Option<PahoKey> {
+        let (prefix, num) = key.split_once('-')?;
+        // Only plain decimal numbers, without a sign
+        if num.is_empty() || !num.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let id = num.parse().ok()?;
+
+        let (prefix, v5) = match prefix.strip_suffix('5') {
+            Some(prefix) => (prefix, true),
+            None => (prefix, false),
+        };
+        let kind = match prefix {
+            "s" => KeyKind::Sent,
+            "sc" => KeyKind::PubRel,
+            "r" => KeyKind::Received,
+            "c" => KeyKind::Command,
+            "q" => KeyKind::Queued,
+            _ => return None,
+        };
+        Some(PahoKey { kind, v5, id })
+    }
+
+    /// Gets the MQTT packet ID, if the key is for a message in flight.
+    pub fn packet_id(&self) -> Option<u16> {
+        if self.kind.has_packet_id() {
+            u16::try_from(self.id).ok()
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for PahoKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = if self.v5 {
+            self.kind.v5_prefix()
+        } else {
+            self.kind.prefix()
+        };
+        write!(f, "{}{}", prefix, self.id)
+    }
+}
+
+/// A count of the keys in a store, by kind.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KindCounts {
+    /// The count for each kind, in the order of `KeyKind::ALL`.
+    counts: [usize; KeyKind::ALL.len()],
+    /// The count of keys that aren't Paho keys.
+    other: usize,
+}
+
+impl KindCounts {
+    /// Counts the keys.
+    pub fn from_keys<I, S>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut counts = Self::default();
+        for key in keys {
+            counts.add(key.as_ref());
+        }
+        counts
+    }
+
+    /// Adds a key to the count.
+    pub fn add(&mut self, key: &str) {
+        match KeyKind::of(key) {
+            Some(kind) => self.counts[kind as usize] += 1,
+            None => self.other += 1,
+        }
+    }
+
+    /// Gets the number of keys of the kind.
+    pub fn get(&self, kind: KeyKind) -> usize {
+        self.counts[kind as usize]
+    }
+
+    /// Gets the number of keys that aren't Paho keys.
+    pub fn other(&self) -> usize {
+        self.other
+    }
+
+    /// Gets the total number of keys.
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum::<usize>() + self.other
+    }
+}
+
+impl fmt::Display for KindCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for kind in KeyKind::ALL {
+            write!(f, "{}={} ", kind, self.get(kind))?;
+        }
+        write!(f, "other={}", self.other)
+    }
+}
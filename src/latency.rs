@@ -0,0 +1,133 @@
+// mqtt.rust.redis/src/latency.rs
+//
+// Sampling of the server's latency reports for slow operations.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Sampling of the server's latency reports for slow operations.
+//!
+//! When a store operation is slow, it's not obvious from the client side
+//! whether the server stalled, like for a fork to save an RDB snapshot, or
+//! the delay was in the client or the network. The server keeps its own
+//! record of slow events, in its latency monitor and slow log, so a sample
+//! of those taken right after the slow operation helps tell the two apart.
+//!
+//! The latency monitor is only active on servers configured with a
+//! `latency-monitor-threshold`, otherwise it reports nothing.
+
+use std::{fmt, time::Duration};
+
+/// The number of slow log entries to sample.
+pub(crate) const SLOWLOG_COUNT: usize = 8;
+
+/// A latency event from the server's latency monitor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyEvent {
+    /// The name of the event, like "command" or "fork".
+    pub event: String,
+    /// The Unix time of the latest occurrence, in seconds.
+    pub timestamp: i64,
+    /// The latency of the latest occurrence, in milliseconds.
+    pub latest_ms: i64,
+    /// The maximum latency of the event, in milliseconds.
+    pub max_ms: i64,
+}
+
+/// An entry from the server's slow log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowlogEntry {
+    /// The unique ID of the entry.
+    pub id: i64,
+    /// The Unix time that the command was run, in seconds.
+    pub timestamp: i64,
+    /// The time it took to run the command, in microseconds.
+    pub micros: i64,
+    /// The command and its arguments, possibly truncated by the server.
+    pub args: Vec<String>,
+}
+
+/// A sample of the server's latency reports.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ServerLatency {
+    /// The latest latency events.
+    pub events: Vec<LatencyEvent>,
+    /// The most recent slow log entries.
+    pub slowlog: Vec<SlowlogEntry>,
+}
+
+impl ServerLatency {
+    /// Determines if the server reports a stall that could account for an
+    /// operation that took `elapsed`, ending at Unix time `now`.
+    ///
+    /// That's a latency event or slow command from around the time of the
+    /// operation that took at least half as long.
+    pub fn explains(&self, elapsed: Duration, now: i64) -> bool {
+        let since = now - elapsed.as_secs() as i64 - 1;
+        let half_ms = (elapsed.as_millis() / 2) as i64;
+
+        self.events
+            .iter()
+            .any(|ev| ev.timestamp >= since && ev.latest_ms >= half_ms)
+            || self
+                .slowlog
+                .iter()
+                .any(|e| e.timestamp >= since && e.micros / 1000 >= half_ms)
+    }
+}
+
+impl fmt::Display for ServerLatency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.events.is_empty() && self.slowlog.is_empty() {
+            return f.write_str("no server latency reports");
+        }
+        let mut sep = "";
+        for ev in &self.events {
+            write!(
+                f,
+                "{}{} {}ms (max {}ms) at {}",
+                sep, ev.event, ev.latest_ms, ev.max_ms, ev.timestamp
+            )?;
+            sep = "; ";
+        }
+        for e in &self.slowlog {
+            let cmd = e.args.first().map(String::as_str).unwrap_or("?");
+            write!(
+                f,
+                "{}slowlog #{} {} {}us at {}",
+                sep, e.id, cmd, e.micros, e.timestamp
+            )?;
+            sep = "; ";
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,796 @@
+// mqtt.rust.redis/src/link.rs
+//
+// The link to the Redis server.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! The link to the Redis server.
+//!
+//! This holds the Redis client and connection for a store, and runs each
+//! command over the connection. When an operation deadline is set, the
+//! store fixes the time it ends at when each of its operations starts,
+//! and every command the operation sends, on every attempt, has to be
+//! done by then. The time remaining is applied as the socket timeout for
+//! each attempt at a command, and a command that times out is retried, on
+//! a fresh connection, only while there is time left. That bounds the
+//! worst-case latency of an operation to the deadline, no matter how many
+//! commands it sends, or how many retries are allowed. A command run
+//! outside of a store operation gets the full deadline to itself.
+//!
+//! Without a deadline, each command is bounded by the command timeout
+//! instead, which is applied to the socket of every connection the link
+//! makes, and isn't retried. Along with the connect timeout, this keeps a
+//! store from hanging forever on a server that can't be reached, or that
+//! stopped answering. Both have defaults, which can be changed or turned
+//! off.
+//!
+//! A connection that has sat idle for longer than the keepalive interval,
+//! if one is set, is checked with a PING before it's used again, and
+//! replaced if that fails. A firewall or NAT box that drops an idle TCP
+//! session often does so without telling either end, and the next write
+//! over the half-open connection would otherwise be lost to a timeout.
+//! TCP keepalive probes are also sent by the OS, with its own timing,
+//! through the redis crate's default `keep-alive` feature, and Nagle's
+//! algorithm can be turned off for every connection with this crate's
+//! `tcp-nodelay` feature.
+//!
+//! With the `pool` feature, a link can take its connections from an r2d2
+//! pool that's shared with other stores, in place of a connection of its
+//! own. The pooled connection is only held for the length of a command,
+//! then handed back, so that any number of stores can share a small,
+//! bounded set of connections, which the pool checks before handing out.
+//! Pooled connections aren't named, since they belong to no one store.
+//!
+//! A link can also share a single connection with the links of other
+//! stores, through a [`SharedSlot`]. The link locks the slot for each
+//! command, so the stores take turns on the connection, and if it's
+//! dropped, the link that finds out makes a new one, with the shared
+//! client, for all of them. A shared connection isn't named either.
+//!
+//! When the store is closed, the link keeps its connection, and the next
+//! open uses it again, once a PING shows it's still good, rather than
+//! making a new one. The Paho C library can close and open the store over
+//! and over while the MQTT client reconnects, and that saves a TCP
+//! connect, and the AUTH, SELECT, and SETNAME, each time.
+//!
+//! A link can also be made from a connection that the application already
+//! has open, without a client. That connection is kept when the store is
+//! closed, so it can be opened again, but once it's dropped, after an
+//! error or a timeout, there's no way to make a new one.
+
+use crate::{
+    adapter,
+    clock::{self, SharedClock},
+    state::{ConnectionState, StateCell},
+    Error, Result,
+};
+#[cfg(feature = "cluster")]
+use redis::cluster::{ClusterClient, ClusterConnection};
+use redis::{Client, Connection, ConnectionLike, RedisError, RedisResult};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A connection shared by the links of several stores, if it's open.
+pub type SharedSlot = Arc<Mutex<Option<Connection>>>;
+
+/// A pool of connections to a Redis server, shared by several stores.
+#[cfg(feature = "pool")]
+pub type Pool = r2d2::Pool<Client>;
+
+/// A connection used by the link, either its own, one that's on loan
+/// from a pool, or one to a whole cluster.
+enum LinkConn {
+    /// A connection that belongs to the link.
+    Own(Connection),
+    /// A connection from the pool, which goes back when it's dropped.
+    #[cfg(feature = "pool")]
+    Pooled(r2d2::PooledConnection<Client>),
+    /// A set of connections to the nodes of a cluster, which sends each
+    /// command to the node with the slot of its key. It's boxed, since
+    /// it's much larger than a single connection.
+    #[cfg(feature = "cluster")]
+    Cluster(Box<ClusterConnection>),
+}
+
+impl LinkConn {
+    /// Gets the connection to send the commands over.
+    fn as_conn(&mut self) -> &mut dyn ConnectionLike {
+        match self {
+            LinkConn::Own(conn) => conn,
+            #[cfg(feature = "pool")]
+            LinkConn::Pooled(conn) => &mut **conn,
+            #[cfg(feature = "cluster")]
+            LinkConn::Cluster(conn) => &mut **conn,
+        }
+    }
+
+    /// Sets the read and write timeouts on the socket, or sockets, of the
+    /// connection, or clears them if `None`.
+    fn set_timeouts(&mut self, timeout: Option<Duration>) -> RedisResult<()> {
+        match self {
+            LinkConn::Own(conn) => adapter::set_timeouts(conn, timeout),
+            #[cfg(feature = "pool")]
+            LinkConn::Pooled(conn) => adapter::set_timeouts(conn, timeout),
+            #[cfg(feature = "cluster")]
+            LinkConn::Cluster(conn) => adapter::set_cluster_timeouts(conn, timeout),
+        }
+    }
+}
+
+/// The default timeout for making a connection to the server.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default timeout for reading or writing a command, when there's no
+/// operation deadline.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The link to the Redis server for a single store.
+pub struct Link {
+    /// The Redis client, if there is one to make new connections.
+    client: Option<Client>,
+    /// The connection to the Redis client, if it's open.
+    conn: Option<LinkConn>,
+    /// The pool to take the connections from, if any, in place of the
+    /// client.
+    #[cfg(feature = "pool")]
+    pool: Option<Pool>,
+    /// The client for a Redis Cluster, if the link is to one, in place of
+    /// the client for a single server.
+    #[cfg(feature = "cluster")]
+    cluster: Option<ClusterClient>,
+    /// The connection shared with other links, if any. While the link
+    /// runs a command, the connection is moved over to `conn`.
+    shared: Option<SharedSlot>,
+    /// Whether the link is supposed to be open.
+    /// This is true from `connect()` until `disconnect()`, even if the
+    /// connection was dropped in between.
+    open: bool,
+    /// The deadline for each operation, if any.
+    deadline: Option<Duration>,
+    /// The number of times to retry a command that timed out.
+    retries: u32,
+    /// The time by which the current store operation has to be done, if
+    /// it has a latency budget.
+    until: Option<Instant>,
+    /// The timeout for making a connection, when there's no deadline.
+    connect_timeout: Option<Duration>,
+    /// The socket timeout for each command, when there's no deadline.
+    command_timeout: Option<Duration>,
+    /// The name to give each connection, if any.
+    name: Option<String>,
+    /// The time a connection can sit idle before it's checked, if any.
+    keepalive: Option<Duration>,
+    /// The time the connection was last used, if it was.
+    last_used: Option<Instant>,
+    /// The count of connections the link has put in place, so that
+    /// anything set up on one connection can tell when it was replaced.
+    generation: u64,
+    /// The state of the connection, shared with the handles.
+    state: StateCell,
+    /// The clock for the operation deadlines.
+    clock: SharedClock,
+}
+
+impl Link {
+    /// Creates a link to the server for the client, reporting its state
+    /// to the cell.
+    pub fn new(client: Client, state: StateCell) -> Self {
+        Self::with_parts(Some(client), None, state)
+    }
+
+    /// Creates a link that uses an existing connection to the server, and
+    /// has no client to make another.
+    pub fn from_connection(conn: Connection, state: StateCell) -> Self {
+        Self::with_parts(None, Some(LinkConn::Own(conn)), state)
+    }
+
+    /// Creates a link that borrows a connection from the pool for each
+    /// command.
+    #[cfg(feature = "pool")]
+    pub fn from_pool(pool: Pool, state: StateCell) -> Self {
+        let mut link = Self::with_parts(None, None, state);
+        link.pool = Some(pool);
+        link
+    }
+
+    /// Creates a link to the nodes of a Redis Cluster.
+    #[cfg(feature = "cluster")]
+    pub fn cluster(client: ClusterClient, state: StateCell) -> Self {
+        let mut link = Self::with_parts(None, None, state);
+        link.cluster = Some(client);
+        link
+    }
+
+    /// Creates a link that shares the connection in the slot with other
+    /// links, using the client to replace it when it's dropped.
+    pub fn shared(client: Client, slot: SharedSlot, state: StateCell) -> Self {
+        let mut link = Self::with_parts(Some(client), None, state);
+        link.shared = Some(slot);
+        link
+    }
+
+    /// Creates a link with no client yet, which must be set before the
+    /// link is connected.
+    pub fn unbound(state: StateCell) -> Self {
+        Self::with_parts(None, None, state)
+    }
+
+    /// Creates a link from the client and connection, if any.
+    fn with_parts(client: Option<Client>, conn: Option<LinkConn>, state: StateCell) -> Self {
+        Self {
+            client,
+            conn,
+            #[cfg(feature = "pool")]
+            pool: None,
+            #[cfg(feature = "cluster")]
+            cluster: None,
+            shared: None,
+            open: false,
+            deadline: None,
+            retries: 0,
+            until: None,
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            command_timeout: Some(DEFAULT_COMMAND_TIMEOUT),
+            name: None,
+            keepalive: None,
+            last_used: None,
+            generation: 0,
+            state,
+            clock: clock::system(),
+        }
+    }
+
+    /// Gets the Redis client, if there is one.
+    pub fn client(&self) -> Option<&Client> {
+        self.client.as_ref()
+    }
+
+    /// Determines if the link takes its connections from a pool.
+    pub fn is_pooled(&self) -> bool {
+        #[cfg(feature = "pool")]
+        if self.pool.is_some() {
+            return true;
+        }
+        false
+    }
+
+    /// Sets the time by which the commands of the current operation have
+    /// to be done, from its deadline and latency budget, or `None` for no
+    /// limit. This takes the place of the deadline for each command.
+    /// Returns the time set before, to put back when the operation is done.
+    pub fn set_until(&mut self, until: Option<Instant>) -> Option<Instant> {
+        std::mem::replace(&mut self.until, until)
+    }
+
+    /// Gets the time by which the commands of the current operation have
+    /// to be done, if there's a limit.
+    pub fn until(&self) -> Option<Instant> {
+        self.until
+    }
+
+    /// Determines if the link shares its connection with other links.
+    pub fn is_shared(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    /// Gets the count of the connections that the link has put in place.
+    /// This changes whenever the connection might have been replaced.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Determines if the link is to a Redis Cluster.
+    pub fn is_cluster(&self) -> bool {
+        #[cfg(feature = "cluster")]
+        if self.cluster.is_some() {
+            return true;
+        }
+        false
+    }
+
+    /// Determines if the link can make a new connection, when the current
+    /// one is dropped.
+    fn can_reconnect(&self) -> bool {
+        self.client.is_some() || self.is_pooled() || self.is_cluster()
+    }
+
+    /// Hands a pooled connection back to the pool, after a command.
+    fn release(&mut self) {
+        if self.is_pooled() {
+            self.conn = None;
+        }
+    }
+
+    /// Gets the deadline for each operation and the number of retries.
+    pub fn deadline(&self) -> (Option<Duration>, u32) {
+        (self.deadline, self.retries)
+    }
+
+    /// Gets the timeouts for making a connection and for each command.
+    pub fn timeouts(&self) -> (Option<Duration>, Option<Duration>) {
+        (self.connect_timeout, self.command_timeout)
+    }
+
+    /// Determines if the link is open.
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Sets the deadline for each operation, and the number of times to
+    /// retry a command that times out before the deadline. With no
+    /// deadline, each command is limited by the command timeout.
+    pub fn set_deadline(&mut self, deadline: Option<Duration>, retries: u32) {
+        self.deadline = deadline;
+        self.retries = retries;
+        if deadline.is_none() {
+            self.apply_command_timeout();
+        }
+    }
+
+    /// Sets the read and write timeout for each command, for when no
+    /// operation deadline applies, or `None` to wait as long as the
+    /// connection allows.
+    pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+        self.command_timeout = timeout;
+        if self.deadline.is_none() {
+            self.apply_command_timeout();
+        }
+    }
+
+    /// Applies the command timeout to the socket of the current connection.
+    fn apply_command_timeout(&mut self) {
+        if let Some(conn) = self.conn.as_mut() {
+            let _ = conn.set_timeouts(self.command_timeout);
+        }
+    }
+
+    /// Sets the timeout for making a connection to the server, for when
+    /// no operation deadline applies, or `None` to wait as long as the
+    /// OS allows.
+    pub fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        self.connect_timeout = timeout;
+    }
+
+    /// Sets the name to give each connection to the server, as shown by
+    /// `CLIENT LIST`, or `None` to leave them unnamed. The current
+    /// connection, if any, is renamed right away.
+    pub fn set_name(&mut self, name: Option<String>) {
+        if name == self.name {
+            return;
+        }
+        self.name = name;
+        if let Some(mut conn) = self.conn.take() {
+            self.apply_name(conn.as_conn());
+            self.conn = Some(conn);
+        }
+    }
+
+    /// Names the connection, if the link has a name. A server that doesn't
+    /// allow it only gets a warning, since it's just for observability.
+    /// The connections to a cluster aren't named, since the command would
+    /// only reach one node.
+    fn apply_name(&self, conn: &mut dyn ConnectionLike) {
+        if self.shared.is_some() || self.is_cluster() {
+            return;
+        }
+        if let Some(name) = self.name.as_ref() {
+            if let Err(e) = adapter::client_setname(conn, name) {
+                warn!(
+                    "Redis persistence couldn't name the connection '{}': {}",
+                    name, e
+                );
+            }
+        }
+    }
+
+    /// Sets the time a connection can sit idle before it's checked with a
+    /// PING, or `None` to never check it.
+    pub fn set_keepalive(&mut self, interval: Option<Duration>) {
+        self.keepalive = interval;
+    }
+
+    /// Gets the keepalive interval, if any.
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.keepalive
+    }
+
+    /// Checks the connection with a PING if it's been idle for longer than
+    /// the keepalive interval, and drops it if that fails, so the next
+    /// command is sent over a new one.
+    ///
+    /// A connection handed to the link without a client can't be
+    /// replaced, so this leaves it alone.
+    fn check_idle(&mut self) {
+        let (interval, last_used) = match (self.keepalive, self.last_used) {
+            (Some(interval), Some(last_used)) => (interval, last_used),
+            _ => return,
+        };
+        if !self.can_reconnect() || self.clock.now().saturating_duration_since(last_used) < interval
+        {
+            return;
+        }
+        if let Some(conn) = self.conn.as_mut() {
+            if let Err(e) = adapter::ping(conn.as_conn()) {
+                debug!("Idle Redis persistence connection is gone: {}", e);
+                self.conn = None;
+            }
+        }
+    }
+
+    /// Checks the connection with a PING, for a supervisor, dropping it if
+    /// that fails. Returns whether the link is open and could use a new
+    /// connection from the supervisor, which is only for a link that
+    /// makes its own connections with a client.
+    pub fn needs_connection(&mut self) -> bool {
+        if !self.open || self.client.is_none() || self.shared.is_some() || self.is_pooled() {
+            return false;
+        }
+        if let Some(conn) = self.conn.as_mut() {
+            match adapter::ping(conn.as_conn()) {
+                Ok(()) => return false,
+                Err(e) => {
+                    debug!("Supervised Redis persistence connection is gone: {}", e);
+                    self.conn = None;
+                    self.state.set(ConnectionState::down());
+                }
+            }
+        }
+        true
+    }
+
+    /// Puts a connection made by a supervisor, off to the side, in place,
+    /// if the link still needs one.
+    pub fn install(&mut self, mut conn: Connection) {
+        if !self.open || self.conn.is_some() {
+            return;
+        }
+        let _ = adapter::set_timeouts(&mut conn, self.command_timeout);
+        self.apply_name(&mut conn);
+        self.conn = Some(LinkConn::Own(conn));
+        self.generation += 1;
+        self.last_used = Some(self.clock.now());
+        self.state.set(ConnectionState::Connected);
+    }
+
+    /// Sets the clock for the operation deadlines.
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    /// Opens a new connection to the server, with the deadline, if any, as
+    /// the connect timeout, otherwise the configured connect timeout.
+    fn new_connection(&self, timeout: Option<Duration>) -> Result<LinkConn> {
+        #[cfg(feature = "pool")]
+        if let Some(pool) = self.pool.as_ref() {
+            let mut conn = match timeout.or(self.connect_timeout) {
+                Some(timeout) => pool.get_timeout(timeout)?,
+                None => pool.get()?,
+            };
+            adapter::set_timeouts(&mut conn, self.command_timeout)?;
+            return Ok(LinkConn::Pooled(conn));
+        }
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = self.cluster.as_ref() {
+            let conn = adapter::cluster_connect(cluster)?;
+            adapter::set_cluster_timeouts(&conn, self.command_timeout)?;
+            return Ok(LinkConn::Cluster(Box::new(conn)));
+        }
+        let client = self.client.as_ref().ok_or(Error::NoClient)?;
+        let mut conn = Self::connect_with(client, timeout.or(self.connect_timeout))?;
+        adapter::set_timeouts(&mut conn, self.command_timeout)?;
+        self.apply_name(&mut conn);
+        Ok(LinkConn::Own(conn))
+    }
+
+    /// Opens a new connection with the client, within the timeout, if any.
+    fn connect_with(client: &Client, timeout: Option<Duration>) -> Result<Connection> {
+        let res = match timeout {
+            Some(timeout) => adapter::connect_timeout(client, timeout),
+            None => adapter::connect(client),
+        };
+        res.map_err(|e| Self::error(Some(client), e))
+    }
+
+    /// Replaces a connection that was dropped, while the link is open,
+    /// reporting the state of the link as it comes back, or doesn't.
+    fn reconnect(&mut self, timeout: Option<Duration>) -> Result<()> {
+        match self.new_connection(timeout) {
+            Ok(conn) => {
+                self.conn = Some(conn);
+                self.generation += 1;
+                self.state.set(ConnectionState::Connected);
+                Ok(())
+            }
+            Err(e) => {
+                self.state.set(ConnectionState::down());
+                Err(e)
+            }
+        }
+    }
+
+    /// Drops the connection after an I/O error on it, or if the server
+    /// closed it, so that the next command is sent over a new one, rather
+    /// than failing on the dead one until the store is reopened.
+    fn drop_if_broken(&mut self, e: &RedisError) {
+        if e.is_io_error() || e.is_connection_dropped() {
+            self.conn = None;
+            self.state.set(ConnectionState::down());
+        }
+    }
+
+    /// Converts an error from the client, making a failure to authenticate
+    /// with the server stand out from the other errors.
+    fn error(client: Option<&Client>, e: RedisError) -> Error {
+        if adapter::is_auth_error(&e) {
+            let user = client.map_or_else(|| "default".to_string(), adapter::client_user);
+            error!(
+                "Redis persistence authentication failed as '{}': {}",
+                user, e
+            );
+            Error::AuthFailed(user)
+        } else {
+            e.into()
+        }
+    }
+
+    /// Connects to the server.
+    /// An existing connection, handed to the link without a client, is
+    /// used for the first connect.
+    /// A shared connection is used if it's open, and made otherwise.
+    /// The connection kept from before the link was disconnected is used
+    /// again if it answers a PING.
+    pub fn connect(&mut self) -> Result<()> {
+        self.with_shared(|link| {
+            let conn = match link.conn.take() {
+                Some(conn) if !link.can_reconnect() || link.shared.is_some() => Ok(conn),
+                Some(conn) => link.reuse(conn),
+                None => link.new_connection(link.deadline),
+            };
+            match conn {
+                Ok(conn) => {
+                    link.conn = Some(conn);
+                    link.generation += 1;
+                    link.release();
+                    link.open = true;
+                    link.state.set(ConnectionState::Connected);
+                    Ok(())
+                }
+                Err(e) => {
+                    link.state.set(ConnectionState::down());
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    /// Uses the connection kept from before, if it still answers a PING,
+    /// or otherwise opens a new one.
+    fn reuse(&self, mut conn: LinkConn) -> Result<LinkConn> {
+        match adapter::ping(conn.as_conn()) {
+            Ok(()) => {
+                debug!("Reusing the Redis persistence connection");
+                Ok(conn)
+            }
+            Err(e) => {
+                debug!("The kept Redis persistence connection is gone: {}", e);
+                self.new_connection(self.deadline)
+            }
+        }
+    }
+
+    /// Runs `f` with the shared connection, if any, moved over to the
+    /// link, holding the lock on it until `f` is done. Then the
+    /// connection, or the new one that replaced it, is moved back.
+    fn with_shared<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let slot = match self.shared.clone() {
+            Some(slot) => slot,
+            None => return f(self),
+        };
+        let mut shared = slot.lock().unwrap_or_else(|e| e.into_inner());
+        self.conn = shared.take().map(LinkConn::Own);
+        let res = f(self);
+        *shared = match self.conn.take() {
+            Some(LinkConn::Own(conn)) => Some(conn),
+            _ => None,
+        };
+        res
+    }
+
+    /// Disconnects from the server.
+    /// The connection is kept to connect again, unless it's on loan from
+    /// a pool, or it's shared, in which case the link doesn't hold it.
+    pub fn disconnect(&mut self) {
+        self.release();
+        self.open = false;
+        self.state.set(ConnectionState::down());
+    }
+
+    /// Disconnects from the server for good, ending the session with a
+    /// QUIT, rather than keeping the connection to connect again. A
+    /// connection that's shared, or from a pool, isn't held by the link,
+    /// and is left to its owner.
+    pub fn quit(&mut self) {
+        self.disconnect();
+        if let Some(mut conn) = self.conn.take() {
+            if let Err(e) = adapter::quit(conn.as_conn()) {
+                debug!("Redis QUIT failed: {:?}", e);
+            }
+        }
+    }
+
+    /// Drops the connection, if any, so that the next command is sent over
+    /// a new one. The link stays open.
+    pub fn reset(&mut self) {
+        self.conn = None;
+    }
+
+    /// Changes the client for the link. If the link is open, this connects
+    /// with the new client right away, keeping the old connection if that
+    /// fails.
+    pub fn set_client(&mut self, client: Client) -> Result<()> {
+        if self.open {
+            let conn = Self::connect_with(&client, self.deadline.or(self.connect_timeout))?;
+            self.switch_to(client, conn);
+        } else {
+            self.client = Some(client);
+            self.conn = None;
+        }
+        Ok(())
+    }
+
+    /// Opens a new connection with the client, set up like the link's own,
+    /// for the caller to check the server before switching the link over
+    /// to it with [`switch_to()`](Self::switch_to).
+    pub fn connect_to(&self, client: &Client) -> Result<Connection> {
+        let mut conn = Self::connect_with(client, self.deadline.or(self.connect_timeout))?;
+        adapter::set_timeouts(&mut conn, self.command_timeout)?;
+        Ok(conn)
+    }
+
+    /// Switches the link over to a different server, using an existing
+    /// connection to it. A link that shared its connection, or took it
+    /// from a pool, has this one to itself from now on.
+    pub fn switch_to(&mut self, client: Client, mut conn: Connection) {
+        let _ = adapter::set_timeouts(&mut conn, self.command_timeout);
+        self.apply_name(&mut conn);
+        self.client = Some(client);
+        self.conn = Some(LinkConn::Own(conn));
+        self.generation += 1;
+        self.shared = None;
+        #[cfg(feature = "pool")]
+        {
+            self.pool = None;
+        }
+        #[cfg(feature = "cluster")]
+        {
+            self.cluster = None;
+        }
+        self.open = true;
+        self.state.set(ConnectionState::Connected);
+    }
+
+    /// Runs a command, or set of commands, over the connection.
+    ///
+    /// With a deadline, a command that times out is retried on a fresh
+    /// connection, up to the retry limit, as long as the deadline hasn't
+    /// passed. The connection is always replaced after a timeout, since a
+    /// late reply from the server would otherwise be taken as the reply
+    /// to the next command. That's also the case for a command that hits
+    /// the command timeout, without a deadline, although it isn't retried.
+    pub fn run<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnMut(&mut dyn ConnectionLike) -> RedisResult<T>,
+    {
+        if !self.open {
+            return Err(Error::NotOpen);
+        }
+
+        self.with_shared(|link| {
+            link.check_idle();
+            let res = link.run_cmd(f);
+            link.release();
+            link.last_used = Some(link.clock.now());
+            res
+        })
+    }
+
+    /// Runs the command within the deadline or command timeout, for
+    /// [`run()`](Self::run).
+    fn run_cmd<T, F>(&mut self, mut f: F) -> Result<T>
+    where
+        F: FnMut(&mut dyn ConnectionLike) -> RedisResult<T>,
+    {
+        // Within a store operation, the time it has to be done by covers
+        // all of its commands. Otherwise the deadline is for this one.
+        let deadline = self
+            .until
+            .or_else(|| self.deadline.map(|d| self.clock.now() + d));
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => {
+                if self.conn.is_none() {
+                    self.reconnect(None)?;
+                }
+                let conn = self.conn.as_mut().ok_or(Error::NotOpen)?;
+                return match f(conn.as_conn()) {
+                    Ok(v) => Ok(v),
+                    Err(e) if e.is_timeout() => {
+                        self.conn = None;
+                        warn!("Redis persistence command timed out");
+                        Err(Error::Timeout)
+                    }
+                    Err(e) => {
+                        self.drop_if_broken(&e);
+                        Err(Self::error(self.client.as_ref(), e))
+                    }
+                };
+            }
+        };
+
+        let mut attempt = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(self.clock.now());
+            if remaining.is_zero() {
+                return Err(Error::Timeout);
+            }
+
+            if self.conn.is_none() {
+                self.reconnect(Some(remaining))?;
+            }
+            let conn = self.conn.as_mut().ok_or(Error::NotOpen)?;
+            conn.set_timeouts(Some(remaining))?;
+
+            match f(conn.as_conn()) {
+                Ok(v) => return Ok(v),
+                Err(e) if e.is_timeout() => {
+                    self.conn = None;
+                    if attempt >= self.retries {
+                        warn!("Redis persistence command timed out");
+                        return Err(Error::Timeout);
+                    }
+                    attempt += 1;
+                    debug!("Redis command timed out. Retry #{}", attempt);
+                }
+                Err(e) => {
+                    self.drop_if_broken(&e);
+                    return Err(Self::error(self.client.as_ref(), e));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// mqtt.rust.redis/src/memory.rs
+//
+// An in-memory persistence store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! An in-memory persistence store.
+//!
+//! This keeps the entries in a local map, with the same operations as
+//! the Redis store. It's not meant for production use, since the data is
+//! lost when the process exits, but it gives a reference to compare the
+//! Redis store against, and a place for calls to go when tracing the
+//! persistence calls of the MQTT client without touching a server.
+
+use crate::{Error, Result};
+use std::collections::HashMap;
+
+/// A persistence store held in memory.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryPersistence {
+    /// The entries in the store.
+    entries: HashMap<String, Vec<u8>>,
+    /// Whether the store is open.
+    open: bool,
+}
+
+impl MemoryPersistence {
+    /// Creates a new, empty, in-memory store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the entries in the store, by key.
+    pub fn entries(&self) -> &HashMap<String, Vec<u8>> {
+        &self.entries
+    }
+
+    /// Checks that the store is open.
+    fn check_open(&self) -> Result<()> {
+        if self.open {
+            Ok(())
+        } else {
+            Err(Error::NotOpen)
+        }
+    }
+
+    /// Opens the store.
+    /// The entries are kept from one session to the next, as with a
+    /// server, so the client ID and server URI aren't used.
+    pub fn open(&mut self, _client_id: &str, _server_uri: &str) -> Result<()> {
+        self.open = true;
+        Ok(())
+    }
+
+    /// Closes the store.
+    pub fn close(&mut self) -> Result<()> {
+        self.open = false;
+        Ok(())
+    }
+
+    /// Puts the value, as the concatenation of the buffers, into the store.
+    pub fn put(&mut self, key: &str, buffers: &[&[u8]]) -> Result<()> {
+        self.check_open()?;
+        self.entries.insert(key.to_string(), buffers.concat());
+        Ok(())
+    }
+
+    /// Gets the value for the key.
+    pub fn get(&mut self, key: &str) -> Result<Vec<u8>> {
+        self.check_open()?;
+        self.entries.get(key).cloned().ok_or(Error::NotFound)
+    }
+
+    /// Removes the value for the key, if it's in the store.
+    pub fn remove(&mut self, key: &str) -> Result<()> {
+        self.check_open()?;
+        self.entries.remove(key);
+        Ok(())
+    }
+
+    /// Gets all the keys in the store.
+    pub fn keys(&mut self) -> Result<Vec<String>> {
+        self.check_open()?;
+        Ok(self.entries.keys().cloned().collect())
+    }
+
+    /// Removes all the entries from the store.
+    pub fn clear(&mut self) -> Result<()> {
+        self.check_open()?;
+        self.entries.clear();
+        Ok(())
+    }
+
+    /// Determines if the store contains the key.
+    pub fn contains_key(&mut self, key: &str) -> Result<bool> {
+        self.check_open()?;
+        Ok(self.entries.contains_key(key))
+    }
+}
@@ -0,0 +1,110 @@
+// mqtt.rust.redis/src/metrics_facade.rs
+//
+// Reporting to the metrics crate facade.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Reporting to the `metrics` crate facade.
+//!
+//! With the `metrics` feature, the store reports its operations through
+//! the lightweight [metrics](https://crates.io/crates/metrics) facade, so
+//! an application that already installs one of the `metrics-exporter-*`
+//! recorders gets the persistence counters with no extra setup. Without a
+//! recorder installed, the calls do nothing.
+//!
+//! The metrics are named with a configurable prefix:
+//!
+//! - `<prefix>_ops_total` counter, labeled by `op` and `outcome`
+//! - `<prefix>_op_duration_seconds` histogram, labeled by `op`
+//! - `<prefix>_backlog` gauge, with the number of entries in the store
+//! - `<prefix>_bytes_total` counter of value bytes, labeled by `direction`
+//!   as `read` or `written`
+
+use crate::timeline::Outcome;
+use ::metrics::{counter, gauge, histogram};
+use std::time::Duration;
+
+/// The default prefix for the names of the metrics.
+pub const DEFAULT_PREFIX: &str = "mqtt_redis";
+
+/// The names of the metrics reported by a store.
+#[derive(Debug, Clone)]
+pub(crate) struct Metrics {
+    /// The name of the counter of operations.
+    ops: String,
+    /// The name of the histogram of operation durations.
+    duration: String,
+    /// The name of the gauge of the backlog.
+    backlog: String,
+    /// The name of the counter of bytes.
+    bytes: String,
+}
+
+impl Metrics {
+    /// Creates the metrics with names starting with the prefix.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            ops: format!("{}_ops_total", prefix),
+            duration: format!("{}_op_duration_seconds", prefix),
+            backlog: format!("{}_backlog", prefix),
+            bytes: format!("{}_bytes_total", prefix),
+        }
+    }
+
+    /// Reports a store operation.
+    pub fn op(&self, op: &'static str, outcome: &Outcome, elapsed: Duration) {
+        let outcome = match outcome {
+            Outcome::Ok => "ok",
+            Outcome::NotFound => "not_found",
+            Outcome::Failed(_) => "failed",
+        };
+        counter!(self.ops.clone(), "op" => op, "outcome" => outcome).increment(1);
+        histogram!(self.duration.clone(), "op" => op).record(elapsed.as_secs_f64());
+    }
+
+    /// Reports the number of entries in the store.
+    pub fn backlog(&self, n: usize) {
+        gauge!(self.backlog.clone()).set(n as f64);
+    }
+
+    /// Reports the bytes written to, or read from, the server.
+    pub fn bytes(&self, direction: &'static str, n: usize) {
+        counter!(self.bytes.clone(), "direction" => direction).increment(n as u64);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new(DEFAULT_PREFIX)
+    }
+}
@@ -0,0 +1,259 @@
+// mqtt.rust.redis/src/offline.rs
+//
+// Buffering of the writes to a store while Redis can't be reached.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Buffering of the writes to a store while Redis can't be reached.
+//!
+//! When the connection to the server drops, a put or remove from the MQTT
+//! client would normally fail, and the client would give up on the QoS
+//! state of the message. With a buffer, the writes are held in memory, in
+//! order, and replayed to the server, in the same order, once it can be
+//! reached again. Reads of a buffered key are answered from the buffer,
+//! so the client sees its own writes in the meantime.
+//!
+//! The buffer is only in memory, so the writes in it are lost if the
+//! process stops before the server comes back.
+
+use crate::{policy::OverflowPolicy, Error, Result};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// The time to wait after a failed replay before trying again, so that
+/// each write during an outage doesn't wait on a connect of its own.
+pub(crate) const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A write to the store that's waiting for the server.
+#[derive(Debug, Clone)]
+pub(crate) enum OfflineOp {
+    /// A put of the key, with the value.
+    Put(String, Vec<u8>),
+    /// A remove of the key.
+    Remove(String),
+}
+
+impl OfflineOp {
+    /// Gets the key that the write is for.
+    pub fn key(&self) -> &str {
+        match self {
+            OfflineOp::Put(key, _) | OfflineOp::Remove(key) => key,
+        }
+    }
+}
+
+/// The writes held for the server while it can't be reached.
+#[derive(Debug)]
+pub(crate) struct OfflineBuffer {
+    /// The writes, oldest first.
+    ops: VecDeque<OfflineOp>,
+    /// The most writes to hold.
+    max_ops: usize,
+    /// What to do with a write when the buffer is full.
+    policy: OverflowPolicy,
+    /// The time of the last failed replay, if any.
+    last_failure: Option<Instant>,
+}
+
+impl OfflineBuffer {
+    /// Creates a buffer for up to `max_ops` writes.
+    pub fn new(max_ops: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            ops: VecDeque::new(),
+            max_ops,
+            policy,
+            last_failure: None,
+        }
+    }
+
+    /// Gets the number of writes in the buffer.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Determines if there are no writes in the buffer.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Adds a write to the end of the buffer, making room for it, or
+    /// failing, if it's full, as set by the overflow policy.
+    pub fn push(&mut self, op: OfflineOp) -> Result<()> {
+        if self.ops.len() >= self.max_ops {
+            match self.policy {
+                OverflowPolicy::Fail => return Err(Error::OfflineBufferFull(self.max_ops)),
+                OverflowPolicy::DropOldest => match self.ops.pop_front() {
+                    Some(old) => warn!(
+                        "Redis persistence offline buffer is full. Dropped the write of '{}'",
+                        old.key()
+                    ),
+                    None => return Err(Error::OfflineBufferFull(self.max_ops)),
+                },
+            }
+        }
+        self.ops.push_back(op);
+        Ok(())
+    }
+
+    /// Takes the oldest write out of the buffer, to replay it.
+    pub fn pop(&mut self) -> Option<OfflineOp> {
+        self.ops.pop_front()
+    }
+
+    /// Puts a write that couldn't be replayed back at the front of the
+    /// buffer, and notes the failure.
+    pub fn unpop(&mut self, op: OfflineOp, now: Instant) {
+        self.ops.push_front(op);
+        self.last_failure = Some(now);
+    }
+
+    /// Notes that the server couldn't be reached for a write.
+    pub fn failed(&mut self, now: Instant) {
+        self.last_failure = Some(now);
+    }
+
+    /// Determines if it's time to try replaying the buffer.
+    pub fn is_due(&self, now: Instant) -> bool {
+        match self.last_failure {
+            Some(last) => now.saturating_duration_since(last) >= RETRY_INTERVAL,
+            None => true,
+        }
+    }
+
+    /// Gets the latest buffered write of the key, if any: the value of a
+    /// put, or `None` for a remove.
+    pub fn lookup(&self, key: &str) -> Option<Option<&[u8]>> {
+        self.ops
+            .iter()
+            .rev()
+            .find(|op| op.key() == key)
+            .map(|op| match op {
+                OfflineOp::Put(_, value) => Some(value.as_slice()),
+                OfflineOp::Remove(_) => None,
+            })
+    }
+
+    /// Gets the keys with writes in the buffer, each once, oldest first.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = Vec::new();
+        for op in &self.ops {
+            if !keys.iter().any(|key| key == op.key()) {
+                keys.push(op.key().to_string());
+            }
+        }
+        keys
+    }
+
+    /// Drops all the writes in the buffer.
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, fake_server::FakeServer, state::StateCell, store::Store};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    fn put(key: &str, value: &[u8]) -> OfflineOp {
+        OfflineOp::Put(key.to_string(), value.to_vec())
+    }
+
+    #[test]
+    fn test_overflow_fail() {
+        let mut buf = OfflineBuffer::new(2, OverflowPolicy::Fail);
+        buf.push(put("a", b"1")).unwrap();
+        buf.push(put("b", b"2")).unwrap();
+        assert!(matches!(
+            buf.push(put("c", b"3")),
+            Err(Error::OfflineBufferFull(2))
+        ));
+        assert_eq!(buf.keys(), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_overflow_drop_oldest() {
+        let mut buf = OfflineBuffer::new(2, OverflowPolicy::DropOldest);
+        buf.push(put("a", b"1")).unwrap();
+        buf.push(put("b", b"2")).unwrap();
+        buf.push(OfflineOp::Remove("b".to_string())).unwrap();
+        assert_eq!(buf.len(), 2);
+        assert_eq!(buf.lookup("a"), None);
+        assert_eq!(buf.lookup("b"), Some(None));
+    }
+
+    #[test]
+    fn test_replay_in_order_on_reconnect() {
+        let server = FakeServer::start();
+        let clock = MockClock::new();
+        let mut store = Store::from_url(&server.url(), StateCell::default()).unwrap();
+        store.set_clock(Arc::new(clock.clone()));
+        store.set_offline_buffer(Some(10), OverflowPolicy::Fail);
+        store.open("client", "tcp://localhost:1883").unwrap();
+
+        let down = Arc::new(AtomicBool::new(true));
+        let is_down = Arc::clone(&down);
+        server
+            .db()
+            .set_hook(move |_| !is_down.load(Ordering::SeqCst));
+
+        store.put("a", &[b"1"]).unwrap();
+        store.put("b", &[b"2"]).unwrap();
+        store.remove("a").unwrap();
+        store.put("a", &[b"3"]).unwrap();
+        store.remove("b").unwrap();
+        assert_eq!(store.offline_len(), 5);
+        assert_eq!(store.get("a").unwrap(), b"3");
+        assert!(matches!(store.get("b"), Err(Error::NotFound)));
+
+        // The server is back, but it isn't tried until the interval is up
+        down.store(false, Ordering::SeqCst);
+        store.put("c", &[b"4"]).unwrap();
+        assert_eq!(store.offline_len(), 6);
+
+        clock.advance(RETRY_INTERVAL);
+        store.put("d", &[b"5"]).unwrap();
+        assert_eq!(store.offline_len(), 0);
+        let mut keys = store.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["a", "c", "d"]);
+        assert_eq!(store.get("a").unwrap(), b"3");
+        assert_eq!(store.get("c").unwrap(), b"4");
+    }
+}
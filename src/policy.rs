@@ -0,0 +1,146 @@
+// mqtt.rust.redis/src/policy.rs
+//
+// Policies for how a store handles unusual conditions.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Policies for how a store handles unusual conditions.
+
+/// What to do when a store is opened and it already contains keys.
+///
+/// Keys left in the store when it's opened are normally the in-flight
+/// messages from a previous session, which the MQTT client will restore
+/// and resend. But an application that always uses clean sessions never
+/// expects to find any, and might prefer to know about them rather than
+/// silently resend stale messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LeftoverPolicy {
+    /// Keep the keys, so the client can restore them.
+    #[default]
+    Restore,
+    /// Keep the keys, but log a warning.
+    Warn,
+    /// Delete the keys, with a warning, and open an empty store.
+    Clear,
+    /// Fail the open with `Error::LeftoverKeys`.
+    Fail,
+}
+
+/// What to do when the store is used while it isn't open, before the
+/// first `open()`, or after a `close()`.
+///
+/// Every operation on a closed store fails the same way, with
+/// `Error::NotOpen`, unless the store can reopen itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClosedPolicy {
+    /// Fail the operation with `Error::NotOpen`.
+    #[default]
+    Fail,
+    /// Reopen the store that was last opened, and carry on with the
+    /// operation. A store that was never opened still fails, since
+    /// there's no name to open it with.
+    Reopen,
+}
+
+/// What to do when a store is opened with a key that's already in use,
+/// in the same process, by the store of a different client ID or server
+/// URI.
+///
+/// This can only happen with a custom naming function, such as one that
+/// shortens the server URI, that gives the same name to two stores. The
+/// two would then overwrite each other's entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// Fail the open with `Error::NameCollision`.
+    #[default]
+    Fail,
+    /// Open the store anyway, but log a warning.
+    Warn,
+    /// Don't check for collisions.
+    Ignore,
+}
+
+/// How to store an empty value.
+///
+/// The Paho client occasionally persists a zero-length buffer. Redis
+/// stores and returns those just fine, but an empty hash field can be
+/// hard to tell apart from a missing one in some tools and scripts, and
+/// in layers that transform the values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyValuePolicy {
+    /// Store an empty value as-is.
+    #[default]
+    Raw,
+    /// Store an empty value as the [`EMPTY_MARKER`], which reads back as
+    /// an empty value.
+    Marker,
+}
+
+/// The value that stands in for an empty one, under the `Marker` policy.
+///
+/// A stored value that matches this is always read back as empty, no
+/// matter what the current policy is, so that a store keeps working if
+/// the policy changes. It can't be mistaken for a Paho packet or message,
+/// since none of them start with a NUL byte followed by this text.
+pub const EMPTY_MARKER: &[u8] = b"\0mqtt-redis:empty\0";
+
+impl EmptyValuePolicy {
+    /// Gets the value to store for a value that was put.
+    pub fn encode(self, value: Vec<u8>) -> Vec<u8> {
+        match self {
+            EmptyValuePolicy::Marker if value.is_empty() => EMPTY_MARKER.to_vec(),
+            _ => value,
+        }
+    }
+
+    /// Gets the value that was put, from the value that was stored.
+    pub fn decode(value: Vec<u8>) -> Vec<u8> {
+        if value == EMPTY_MARKER {
+            Vec::new()
+        } else {
+            value
+        }
+    }
+}
+
+/// What to do with a write, while the connection to Redis is down, when
+/// the offline buffer is already full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Fail the write with `Error::OfflineBufferFull`, keeping what's
+    /// already in the buffer.
+    #[default]
+    Fail,
+    /// Drop the oldest write in the buffer, with a warning, to make room.
+    DropOldest,
+}
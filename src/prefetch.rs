@@ -0,0 +1,133 @@
+// mqtt.rust.redis/src/prefetch.rs
+//
+// Prefetching the caches of many stores at once.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Prefetching the caches of many stores at once.
+//!
+//! When a process that runs many MQTT clients restarts, each client opens
+//! its store and walks it to resume its session. With a warm up on open,
+//! that's at least one round trip per store, one after the other, as the
+//! clients connect. On a gateway with hundreds of clients, that adds up to
+//! seconds.
+//!
+//! Prefetching reads the keys, and optionally the values, of all the
+//! stores in one pipelined pass per Redis server before the clients
+//! connect, and hands each store its cache, ready to use when the
+//! client opens it.
+
+use crate::{adapter, cache::Cache, name, store::Format, RedisPersistence, Result, StoreName};
+use redis::Client;
+use std::collections::BTreeMap;
+
+/// The stores to prefetch from one server, with a client for it.
+type Group = (Client, Vec<(RedisPersistence, String)>);
+
+/// Prefetches the caches for all the stores, each of which will be opened
+/// with the client ID and server URI in its name.
+///
+/// The stores are grouped by their Redis server, and each group is read
+/// in a single pipeline. With a `value_budget` of zero, only the keys are
+/// read, otherwise the values are loaded as well, up to that many bytes
+/// for each store. A split store for the received messages is prefetched
+/// along with its main store.
+///
+/// The cache is used in place of the warm up when the store is next
+/// opened with the same name. Like the warm up, it assumes that the store
+/// is the only writer to the hash, so nothing else should change it in
+/// between.
+///
+/// Returns the total number of keys prefetched.
+pub fn prefetch(stores: &[(&RedisPersistence, StoreName)], value_budget: usize) -> Result<usize> {
+    let mut by_server = BTreeMap::<(String, Option<i64>), Group>::new();
+    for (persist, store_name) in stores {
+        let (server, client, key, received) = {
+            let store = persist.lock();
+            let server = (store.url().to_string(), store.db());
+            (
+                server,
+                store.client()?,
+                store.key_for(store_name),
+                store.received_store().cloned(),
+            )
+        };
+        let (_, group) = by_server
+            .entry(server)
+            .or_insert_with(|| (client, Vec::new()));
+        group.push(((*persist).clone(), key.clone()));
+        if let Some(received) = received {
+            group.push((received, name::received_name(&key)));
+        }
+    }
+
+    let mut n = 0;
+    for ((url, _), (client, group)) in by_server {
+        let mut conn = adapter::connect(&client)?;
+        let names: Vec<String> = group.iter().map(|(_, name)| name.clone()).collect();
+        let formats = group
+            .iter()
+            .map(|(persist, name)| {
+                let format = Format::read(&mut conn, &name::meta_name(name))?;
+                Ok(format.union(persist.lock().put_format()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let caches: Vec<Cache> = if value_budget == 0 {
+            adapter::hkeys_many(&mut conn, &names)?
+                .into_iter()
+                .zip(&formats)
+                .map(|(keys, format)| Cache::new(format.strip(keys), 0))
+                .collect()
+        } else {
+            adapter::hgetall_many(&mut conn, &names)?
+                .into_iter()
+                .zip(&formats)
+                .map(|(entries, format)| {
+                    let mut cache = Cache::new(Vec::new(), value_budget);
+                    for (key, value) in format.join(entries)? {
+                        cache.insert(&key, format.decode(value)?.into());
+                    }
+                    Ok(cache)
+                })
+                .collect::<Result<_>>()?
+        };
+
+        for ((persist, name), cache) in group.into_iter().zip(caches) {
+            n += cache.len();
+            persist.lock().set_prefetched(name, cache);
+        }
+        debug!("Prefetched {} stores from {}", names.len(), url);
+    }
+    Ok(n)
+}
@@ -0,0 +1,177 @@
+// mqtt.rust.redis/src/rate_limit.rs
+//
+// Rate limiting of the persistence operations.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Rate limiting of the persistence operations.
+//!
+//! On a small gateway the application and the local Redis server may
+//! share a single CPU core. A publish storm could then have the client
+//! hammering the server to the point that neither gets much work done.
+//! A token bucket limiter allows for short bursts of operations while
+//! holding the long-term rate to a configured maximum.
+
+use crate::{
+    clock::{Clock, SystemClock},
+    Error, Result,
+};
+use std::time::{Duration, Instant};
+
+/// What to do when an operation exceeds the rate limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitPolicy {
+    /// Block the caller until the operation is allowed.
+    Block,
+    /// Fail the operation with `Error::RateLimited`.
+    Error,
+}
+
+/// A token bucket rate limiter for persistence operations.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    /// The number of tokens added to the bucket per second.
+    rate: f64,
+    /// The maximum number of tokens the bucket can hold.
+    burst: f64,
+    /// The number of tokens currently in the bucket.
+    tokens: f64,
+    /// The last time the bucket was refilled.
+    last: Instant,
+    /// Whether to block or fail when the bucket is empty.
+    policy: RateLimitPolicy,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter that allows, on average, `ops_per_sec`
+    /// operations per second, with bursts of up to `burst` operations.
+    ///
+    /// The bucket starts out full. Values of zero are treated as one.
+    pub fn new(ops_per_sec: u32, burst: u32, policy: RateLimitPolicy) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            rate: f64::from(ops_per_sec.max(1)),
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+            policy,
+        }
+    }
+
+    /// Gets the policy for operations that exceed the limit.
+    pub fn policy(&self) -> RateLimitPolicy {
+        self.policy
+    }
+
+    /// Adds the tokens that accumulated since the last refill.
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+    }
+
+    /// Takes a token for a single operation.
+    ///
+    /// If the bucket is empty this either sleeps until a token is
+    /// available or returns `Error::RateLimited`, according to the policy.
+    pub fn acquire(&mut self) -> Result<()> {
+        self.acquire_with(&SystemClock)
+    }
+
+    /// Takes a token for a single operation, reading the time from, and
+    /// waiting on, the clock.
+    pub fn acquire_with(&mut self, clock: &dyn Clock) -> Result<()> {
+        self.refill(clock.now());
+
+        if self.tokens < 1.0 {
+            if self.policy == RateLimitPolicy::Error {
+                return Err(Error::RateLimited);
+            }
+            let wait = (1.0 - self.tokens) / self.rate;
+            clock.sleep(Duration::from_secs_f64(wait));
+            self.refill(clock.now());
+        }
+        self.tokens = (self.tokens - 1.0).max(0.0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_error_when_empty() {
+        let clock = MockClock::new();
+        let mut limiter = RateLimiter::new(10, 2, RateLimitPolicy::Error);
+        limiter.acquire_with(&clock).unwrap();
+        limiter.acquire_with(&clock).unwrap();
+        assert!(matches!(
+            limiter.acquire_with(&clock),
+            Err(Error::RateLimited)
+        ));
+        assert_eq!(clock.elapsed(), Duration::ZERO);
+
+        // A token comes back every 100ms
+        clock.advance(Duration::from_millis(100));
+        limiter.acquire_with(&clock).unwrap();
+        assert!(limiter.acquire_with(&clock).is_err());
+    }
+
+    #[test]
+    fn test_block_when_empty() {
+        let clock = MockClock::new();
+        let mut limiter = RateLimiter::new(10, 2, RateLimitPolicy::Block);
+        limiter.acquire_with(&clock).unwrap();
+        limiter.acquire_with(&clock).unwrap();
+        assert_eq!(clock.elapsed(), Duration::ZERO);
+
+        // Each one after the burst waits for its token
+        for i in 1..=3 {
+            limiter.acquire_with(&clock).unwrap();
+            let expected = Duration::from_millis(100 * i);
+            let elapsed = clock.elapsed();
+            assert!(
+                elapsed >= expected - Duration::from_micros(1),
+                "{:?}",
+                elapsed
+            );
+            assert!(
+                elapsed <= expected + Duration::from_micros(1),
+                "{:?}",
+                elapsed
+            );
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// mqtt.rust.redis/src/readahead.rs
+//
+// The read-ahead from contains_key() to get(), and recent misses.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! The read-ahead from `contains_key()` to `get()`, and recent misses.
+//!
+//! The Paho library often checks that the store contains a key right
+//! before it gets the value. Rather than a round trip for each, the check
+//! reads the value, with `HGET`, and holds it for a `get()` of the key
+//! that comes next. The keys found missing are remembered too, a few at
+//! a time, so that asking again doesn't go to the server. A write to a key
+//! forgets whatever was known about it.
+
+use std::collections::VecDeque;
+
+/// The number of missing keys to remember.
+pub const MAX_MISSING: usize = 64;
+
+/// The values held for a `get()`, and the keys known to be missing.
+#[derive(Debug, Default)]
+pub struct ReadAhead {
+    /// The key and value read by the last `contains_key()`.
+    held: Option<(String, Vec<u8>)>,
+    /// The keys recently found to be missing, oldest first.
+    missing: VecDeque<String>,
+}
+
+impl ReadAhead {
+    /// Holds the value read for the key, for the next `get()` of it.
+    pub fn hold(&mut self, key: &str, value: Vec<u8>) {
+        self.held = Some((key.to_string(), value));
+    }
+
+    /// Takes the value held for the key, if it's the one that was read.
+    /// Any other value that was held is dropped, since it's only for the
+    /// `get()` right after the check.
+    pub fn take(&mut self, key: &str) -> Option<Vec<u8>> {
+        match self.held.take() {
+            Some((k, v)) if k == key => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Remembers that the key is missing from the store, forgetting the
+    /// oldest one if there are too many.
+    pub fn missed(&mut self, key: &str) {
+        if !self.is_missing(key) {
+            if self.missing.len() >= MAX_MISSING {
+                self.missing.pop_front();
+            }
+            self.missing.push_back(key.to_string());
+        }
+    }
+
+    /// Determines if the key is known to be missing from the store.
+    pub fn is_missing(&self, key: &str) -> bool {
+        self.missing.iter().any(|k| k == key)
+    }
+
+    /// Forgets what's known about the key, which was just written.
+    pub fn wrote(&mut self, key: &str) {
+        if matches!(&self.held, Some((k, _)) if k == key) {
+            self.held = None;
+        }
+        self.missing.retain(|k| k != key);
+    }
+
+    /// Forgets everything, as when the store is cleared or reopened.
+    pub fn clear(&mut self) {
+        self.held = None;
+        self.missing.clear();
+    }
+}
@@ -0,0 +1,184 @@
+// mqtt.rust.redis/src/reconnect.rs
+//
+// Reconnecting to the Redis server after a dropped connection.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Reconnecting to the Redis server after a dropped connection.
+//!
+//! When Redis is restarted, or the network to it blips, the connection
+//! that the store was using is gone, and the server may take a moment to
+//! come back. A [`ReconnectPolicy`] has each store operation that fails
+//! on the connection wait a little, then try again on a new one, with the
+//! wait doubling each time, up to a limit, so that a short outage is
+//! ridden out without the MQTT client ever seeing it, and a long one
+//! doesn't have the store hammering a server that isn't there.
+
+use std::time::Duration;
+
+/// The default number of times to reconnect for a single operation.
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// The default time to wait before the first reconnect.
+pub const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(50);
+
+/// The default limit on the time to wait between reconnects.
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// How to reconnect when a store operation fails on the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// The number of times to reconnect and retry a single operation.
+    pub max_retries: u32,
+    /// The time to wait before the first reconnect.
+    pub initial_delay: Duration,
+    /// The limit on the time to wait between reconnects.
+    pub max_delay: Duration,
+}
+
+impl ReconnectPolicy {
+    /// Creates a policy to reconnect up to `max_retries` times, waiting
+    /// `initial_delay` before the first attempt, and doubling the wait for
+    /// each one after that, up to `max_delay`.
+    pub fn new(max_retries: u32, initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_retries,
+            initial_delay,
+            max_delay,
+        }
+    }
+
+    /// Gets the time to wait before the reconnect, where the first one is
+    /// attempt zero.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        self.initial_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    /// Gets the longest that an operation can spend waiting to reconnect,
+    /// over all its attempts.
+    pub fn max_wait(&self) -> Duration {
+        (0..self.max_retries).map(|i| self.delay(i)).sum()
+    }
+}
+
+impl Default for ReconnectPolicy {
+    /// Creates a policy with five reconnects, waiting from 50ms, up to
+    /// two seconds, between them.
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_MAX_RETRIES,
+            DEFAULT_INITIAL_DELAY,
+            DEFAULT_MAX_DELAY,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::MockClock,
+        consistency::{Consistency, ConsistencyPolicy},
+        fake_server::FakeServer,
+        state::StateCell,
+        store::Store,
+        Error,
+    };
+    use std::sync::Arc;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn policy() -> ReconnectPolicy {
+        ReconnectPolicy::new(5, 50 * MS, 200 * MS)
+    }
+
+    // Opens a store that backs off on the mock clock, with no other retries.
+    fn open_store(server: &FakeServer, clock: &MockClock) -> Store {
+        let mut store = Store::from_url(&server.url(), StateCell::default()).unwrap();
+        store.set_clock(Arc::new(clock.clone()));
+        store.set_consistency(ConsistencyPolicy::new().writes(Consistency::new(false, 0)));
+        store.set_reconnect(Some(policy()));
+        store.open("client", "tcp://localhost:1883").unwrap();
+        store
+    }
+
+    #[test]
+    fn test_delays() {
+        let policy = policy();
+        let delays: Vec<_> = (0..5).map(|i| policy.delay(i)).collect();
+        assert_eq!(delays, [50 * MS, 100 * MS, 200 * MS, 200 * MS, 200 * MS]);
+        assert_eq!(policy.delay(40), 200 * MS);
+        assert_eq!(policy.delay(u32::MAX), 200 * MS);
+        assert_eq!(policy.max_wait(), 750 * MS);
+    }
+
+    #[test]
+    fn test_backoff_until_connected() {
+        let server = FakeServer::start();
+        let clock = MockClock::new();
+        let mut store = open_store(&server, &clock);
+
+        let mut failures = 3;
+        server.db().set_hook(move |cmd| {
+            if cmd != "HSET" || failures == 0 {
+                return true;
+            }
+            failures -= 1;
+            false
+        });
+
+        let start = clock.elapsed();
+        store.put("a", &[b"1"]).unwrap();
+        assert_eq!(clock.elapsed() - start, 350 * MS);
+        assert_eq!(store.get("a").unwrap(), b"1");
+    }
+
+    #[test]
+    fn test_backoff_gives_up() {
+        let server = FakeServer::start();
+        let clock = MockClock::new();
+        let mut store = open_store(&server, &clock);
+
+        server.db().set_hook(|cmd| cmd != "HSET");
+
+        let start = clock.elapsed();
+        let err = store.put("a", &[b"1"]).unwrap_err();
+        assert!(err.is_connection_error(), "{:?}", err);
+        assert_eq!(clock.elapsed() - start, policy().max_wait());
+        assert!(matches!(store.get("a"), Err(Error::NotFound)));
+    }
+}
@@ -0,0 +1,234 @@
+// mqtt.rust.redis/src/replay.rs
+//
+// Replay of recorded persistence traces.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Replay of recorded persistence traces.
+//!
+//! A trace recorded with a [`TracePersistence`](crate::TracePersistence)
+//! can be fed back into any persistence implementation, like the Redis
+//! store, the in-memory store, or a third-party one, to check that it
+//! gives the same results as the recording, and to compare the state it
+//! ends up in with that of another implementation. That makes it possible
+//! to turn a bug report into a regression test, or to check a new backend
+//! against a known-good one.
+
+use crate::{
+    client_persistence::compat::mqtt,
+    trace::{TraceCall, TraceEvent, TraceResult},
+};
+use std::collections::BTreeMap;
+
+/// A call whose result in the replay differs from the recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The sequence number of the recorded event.
+    pub seq: u64,
+    /// The call.
+    pub call: TraceCall,
+    /// The result in the recording.
+    pub recorded: TraceResult,
+    /// The result in the replay.
+    pub actual: TraceResult,
+}
+
+/// The outcome of replaying a trace.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Replay {
+    /// The calls that gave a different result than the recording.
+    pub mismatches: Vec<Mismatch>,
+    /// The contents of the store at the end of the replay.
+    pub state: BTreeMap<String, Vec<u8>>,
+}
+
+impl Replay {
+    /// Determines if all the calls gave the same results as the
+    /// recording.
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// A difference between the states of two stores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateDiff {
+    /// The key is only in the first store.
+    OnlyInFirst(String),
+    /// The key is only in the second store.
+    OnlyInSecond(String),
+    /// The key has different values in the two stores.
+    Differs(String),
+}
+
+/// Makes a single call to the persistence.
+fn call<P: mqtt::ClientPersistence>(p: &mut P, call: &TraceCall) -> TraceResult {
+    let unit = |res: mqtt::Result<()>| match res {
+        Ok(()) => TraceResult::Ok,
+        Err(e) => TraceResult::Err(e.to_string()),
+    };
+
+    match call {
+        TraceCall::Open {
+            client_id,
+            server_uri,
+        } => unit(p.open(client_id, server_uri)),
+        TraceCall::Close => unit(p.close()),
+        TraceCall::Put { key, sizes, data } => {
+            // Without the recorded value, put zeros of the same sizes
+            let zeros;
+            let buffers: Vec<&[u8]> = match data {
+                Some(data) => {
+                    let mut bufs = Vec::with_capacity(sizes.len());
+                    let mut rest = data.as_slice();
+                    for &n in sizes {
+                        let (buf, tail) = rest.split_at(n.min(rest.len()));
+                        bufs.push(buf);
+                        rest = tail;
+                    }
+                    bufs
+                }
+                None => {
+                    zeros = vec![0u8; sizes.iter().copied().max().unwrap_or(0)];
+                    sizes.iter().map(|&n| &zeros[..n]).collect()
+                }
+            };
+            unit(p.put(key, buffers))
+        }
+        TraceCall::Get { key } => match p.get(key) {
+            Ok(v) => TraceResult::Value {
+                len: v.len(),
+                data: Some(v),
+            },
+            Err(e) => TraceResult::Err(e.to_string()),
+        },
+        TraceCall::Remove { key } => unit(p.remove(key)),
+        TraceCall::Keys => match p.keys() {
+            Ok(keys) => TraceResult::Keys(keys),
+            Err(e) => TraceResult::Err(e.to_string()),
+        },
+        TraceCall::Clear => unit(p.clear()),
+        TraceCall::ContainsKey { key } => TraceResult::Bool(p.contains_key(key)),
+    }
+}
+
+/// Reads the full contents of an open store.
+fn read_state<P: mqtt::ClientPersistence>(p: &mut P) -> mqtt::Result<BTreeMap<String, Vec<u8>>> {
+    let mut state = BTreeMap::new();
+    for key in p.keys()? {
+        let v = p.get(&key)?;
+        state.insert(key, v);
+    }
+    Ok(state)
+}
+
+/// Replays the recorded events into the persistence, checking each
+/// result against the recording, then reads back the final state of the
+/// store.
+///
+/// If `client_id` is given, it replaces the client ID in the recorded
+/// `open()` calls, so that a replay against a live server doesn't touch
+/// the real client's store. If the trace leaves the store closed, it is
+/// reopened, as it was last opened, to read the final state, then closed
+/// again.
+pub fn replay<P>(events: &[TraceEvent], p: &mut P, client_id: Option<&str>) -> mqtt::Result<Replay>
+where
+    P: mqtt::ClientPersistence,
+{
+    let mut replay = Replay::default();
+    let mut last_open = None;
+    let mut is_open = false;
+
+    for ev in events {
+        let call_made = match (&ev.call, client_id) {
+            (TraceCall::Open { server_uri, .. }, Some(id)) => TraceCall::Open {
+                client_id: id.to_string(),
+                server_uri: server_uri.clone(),
+            },
+            (c, _) => c.clone(),
+        };
+        let actual = call(p, &call_made);
+
+        match (&call_made, &actual) {
+            (TraceCall::Open { .. }, TraceResult::Ok) => {
+                last_open = Some(call_made.clone());
+                is_open = true;
+            }
+            (TraceCall::Close, _) => is_open = false,
+            _ => {}
+        }
+
+        if !ev.result.matches(&actual) {
+            replay.mismatches.push(Mismatch {
+                seq: ev.seq,
+                call: ev.call.clone(),
+                recorded: ev.result.clone(),
+                actual,
+            });
+        }
+    }
+
+    if is_open {
+        replay.state = read_state(p)?;
+    } else if let Some(open) = last_open {
+        if let TraceResult::Err(e) = call(p, &open) {
+            warn!("Couldn't reopen the store after the replay: {}", e);
+            return Ok(replay);
+        }
+        let state = read_state(p);
+        p.close()?;
+        replay.state = state?;
+    }
+    Ok(replay)
+}
+
+/// Compares the final states of two replays, or any two stores.
+pub fn diff_states(
+    first: &BTreeMap<String, Vec<u8>>,
+    second: &BTreeMap<String, Vec<u8>>,
+) -> Vec<StateDiff> {
+    let mut diffs = Vec::new();
+    for (key, v) in first {
+        match second.get(key) {
+            None => diffs.push(StateDiff::OnlyInFirst(key.clone())),
+            Some(v2) if v2 != v => diffs.push(StateDiff::Differs(key.clone())),
+            _ => {}
+        }
+    }
+    for key in second.keys() {
+        if !first.contains_key(key) {
+            diffs.push(StateDiff::OnlyInSecond(key.clone()));
+        }
+    }
+    diffs
+}
@@ -0,0 +1,198 @@
+// mqtt.rust.redis/src/segment.rs
+//
+// Values stored as separate segments, one hash field per buffer.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Values stored as separate segments, one hash field per buffer.
+//!
+//! The Paho library hands a put its value as a list of buffers, like the
+//! packet header and the payload. Rather than copy them into one buffer,
+//! a segmented put writes each one to its own field of the store's hash,
+//! named `<key>:0`, `<key>:1`, and so on. The field for the key itself
+//! then holds a small header with the number of segments, which a read
+//! recognizes, to gather them back into the value.
+//!
+//! A value that's larger than the store's maximum value size is put the
+//! same way, in chunks of that size, whether or not puts are segmented.
+
+use crate::{Error, Result};
+use std::collections::{HashMap, HashSet};
+
+/// The start of the header that marks a segmented value, which is
+/// followed by the number of segments, in decimal. The scripts that put
+/// and remove the segments, in the adapter, write and match it too.
+const HEADER: &[u8] = b"\0seg:";
+
+/// Gets the number of segments, if the value is a segment header.
+pub fn count(value: &[u8]) -> Option<usize> {
+    let n = value.strip_prefix(HEADER)?;
+    std::str::from_utf8(n).ok()?.parse().ok()
+}
+
+/// Creates the name of the hash field for segment `i` of the key.
+pub fn field(key: &str, i: usize) -> String {
+    format!("{}:{}", key, i)
+}
+
+/// Determines if the field is a segment of one of the keys.
+fn is_segment_of(field: &str, keys: &HashSet<&str>) -> bool {
+    match field.rsplit_once(':') {
+        Some((key, i)) => {
+            !i.is_empty() && i.bytes().all(|b| b.is_ascii_digit()) && keys.contains(key)
+        }
+        None => false,
+    }
+}
+
+/// Removes the fields for the segments from a list of the hash's fields,
+/// leaving the keys.
+pub fn strip(fields: Vec<String>) -> Vec<String> {
+    let keys: HashSet<&str> = fields.iter().map(String::as_str).collect();
+    let segments: HashSet<String> = fields
+        .iter()
+        .filter(|field| is_segment_of(field, &keys))
+        .cloned()
+        .collect();
+    fields
+        .into_iter()
+        .filter(|field| !segments.contains(field))
+        .collect()
+}
+
+/// Concatenates the segments of the value of a key, in order, failing
+/// with `Error::MissingSegment` if any of them wasn't found.
+pub fn concat<I>(key: &str, segments: I) -> Result<Vec<u8>>
+where
+    I: IntoIterator<Item = Option<Vec<u8>>>,
+{
+    let mut value = Vec::new();
+    for (i, segment) in segments.into_iter().enumerate() {
+        match segment {
+            Some(segment) => value.extend_from_slice(&segment),
+            None => return Err(Error::MissingSegment(key.to_string(), i)),
+        }
+    }
+    Ok(value)
+}
+
+/// Gathers the segments of each segmented value in a list of the hash's
+/// entries, leaving just the keys and their whole values.
+/// Fails with `Error::MissingSegment` if any value is missing one of its
+/// segments, rather than returning part of it.
+pub fn join(entries: Vec<(String, Vec<u8>)>) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut keys = HashSet::new();
+    for (key, value) in entries.iter() {
+        if count(value).is_some() {
+            keys.insert(key.as_str());
+        }
+    }
+    if keys.is_empty() {
+        return Ok(entries);
+    }
+    let segments: HashMap<String, Vec<u8>> = entries
+        .iter()
+        .filter(|(field, _)| is_segment_of(field, &keys))
+        .cloned()
+        .collect();
+    entries
+        .iter()
+        .filter(|(field, _)| !segments.contains_key(field))
+        .map(|(key, value)| match count(value) {
+            Some(n) => {
+                let value = concat(key, (0..n).map(|i| segments.get(&field(key, i)).cloned()))?;
+                Ok((key.clone(), value))
+            }
+            None => Ok((key.clone(), value.clone())),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(field: &str, value: &[u8]) -> (String, Vec<u8>) {
+        (field.to_string(), value.to_vec())
+    }
+
+    #[test]
+    fn test_join() {
+        let entries = vec![
+            entry("a", b"\0seg:3"),
+            entry("a:0", b"one,"),
+            entry("a:1", b"two,"),
+            entry("a:2", b"three"),
+            entry("b", b"plain"),
+        ];
+        let mut joined = join(entries).unwrap();
+        joined.sort();
+        assert_eq!(
+            joined,
+            vec![entry("a", b"one,two,three"), entry("b", b"plain")]
+        );
+    }
+
+    #[test]
+    fn test_join_empty_value() {
+        let joined = join(vec![entry("a", b"\0seg:0")]).unwrap();
+        assert_eq!(joined, vec![entry("a", b"")]);
+    }
+
+    #[test]
+    fn test_join_missing_segment() {
+        let entries = vec![
+            entry("a", b"\0seg:3"),
+            entry("a:0", b"one,"),
+            entry("a:2", b"three"),
+        ];
+        match join(entries) {
+            Err(Error::MissingSegment(key, i)) => {
+                assert_eq!(key, "a");
+                assert_eq!(i, 1);
+            }
+            other => panic!("expected a missing segment, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_concat_missing_segment() {
+        let segments = vec![Some(b"one,".to_vec()), Some(b"two".to_vec()), None];
+        assert!(matches!(
+            concat("a", segments),
+            Err(Error::MissingSegment(_, 2))
+        ));
+        let segments = vec![Some(b"one,".to_vec()), Some(b"two".to_vec())];
+        assert_eq!(concat("a", segments).unwrap(), b"one,two");
+    }
+}
@@ -0,0 +1,139 @@
+// mqtt.rust.redis/src/sentinel.rs
+//
+// Finding the Redis primary with Sentinel.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Finding the Redis primary with Sentinel.
+//!
+//! This is in the `sentinel` feature. For a Redis primary and replicas
+//! that are managed by Sentinel, the store can be given the addresses of
+//! the sentinels and the name of the primary, instead of the URL of a
+//! server. It asks the sentinels for the primary's address when it opens,
+//! and asks again whenever it loses the connection, or has a write
+//! refused by a replica, so that it follows the primary after a failover
+//! and runs the failed operation again there.
+//!
+//! The sentinels are only asked for the address. The connection to the
+//! primary uses the database, user, password, and TLS options of the
+//! store, as with any other server.
+
+use crate::{adapter, support, Error, Result};
+use std::time::Duration;
+
+/// The default timeout to connect to a sentinel.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The sentinels to ask for the address of the primary.
+#[derive(Debug, Clone)]
+pub struct SentinelConfig {
+    /// The URLs of the sentinels, like "redis://host:26379".
+    sentinels: Vec<String>,
+    /// The name the sentinels monitor the primary by.
+    master: String,
+    /// The time to wait to connect to each sentinel.
+    timeout: Duration,
+}
+
+impl SentinelConfig {
+    /// Creates the configuration for the primary that the sentinels, at
+    /// the URLs, monitor by the name `master`.
+    pub fn new<S, T>(sentinels: &[S], master: T) -> Self
+    where
+        S: AsRef<str>,
+        T: Into<String>,
+    {
+        Self {
+            sentinels: sentinels.iter().map(|s| s.as_ref().to_string()).collect(),
+            master: master.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets the time to wait to connect to each sentinel.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Gets the URLs of the sentinels.
+    pub fn sentinels(&self) -> &[String] {
+        &self.sentinels
+    }
+
+    /// Gets the name the sentinels monitor the primary by.
+    pub fn master(&self) -> &str {
+        &self.master
+    }
+
+    /// Asks the sentinels, in order, for the address of the primary, and
+    /// makes a URL for it, with the scheme of the store's URL, so that a
+    /// `rediss://` store stays on TLS.
+    ///
+    /// This fails with [`Error::NoPrimary`] if none of the sentinels can
+    /// be reached, or knows the primary.
+    pub(crate) fn resolve(&self, store_url: &str) -> Result<String> {
+        let scheme = if store_url.starts_with("rediss://") {
+            "rediss"
+        } else {
+            "redis"
+        };
+        for sentinel in &self.sentinels {
+            let res = adapter::open_client(sentinel)
+                .and_then(|client| adapter::connect_timeout(&client, self.timeout))
+                .and_then(|mut conn| adapter::sentinel_master_addr(&mut conn, &self.master));
+            match res {
+                Ok(Some((host, port))) => {
+                    let host = if host.contains(':') {
+                        format!("[{}]", host)
+                    } else {
+                        host
+                    };
+                    return Ok(format!("{}://{}:{}/", scheme, host, port));
+                }
+                Ok(None) => debug!(
+                    "Sentinel {} doesn't know '{}'",
+                    support::redact_url(sentinel),
+                    self.master
+                ),
+                Err(e) => debug!(
+                    "Couldn't ask sentinel {}: {:?}",
+                    support::redact_url(sentinel),
+                    e
+                ),
+            }
+        }
+        error!("No sentinel knows the Redis primary '{}'", self.master);
+        Err(Error::NoPrimary)
+    }
+}
@@ -0,0 +1,192 @@
+// mqtt.rust.redis/src/session.rs
+//
+// Checking the store against the broker's session.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Sessions of a store, and how they line up with the broker's.
+//!
+//! When the MQTT client connects with a persistent session, the broker
+//! says whether it still had the session, and the store says whether it
+//! had any messages left over from one. If only one side has the session,
+//! the client either resends messages that the broker has forgotten, or
+//! misses the ones the broker redelivers, which shows up later as
+//! confusing duplicate or lost QoS 2 messages.
+//!
+//! When a store is closed, it also leaves a summary of the session in a
+//! `<name>:last_session` hash, so that after a restart, it's possible to
+//! see how the persistence behaved the last time around.
+
+use std::{collections::HashMap, fmt};
+
+/// The result of checking the leftover state in the store against the
+/// broker's session-present flag after a connect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionCheck {
+    /// The store and the broker agree on whether there's a session.
+    Consistent,
+    /// The broker had no session, but the store was opened with this many
+    /// entries left over from one.
+    BrokerLost(usize),
+    /// The broker had a session, but the store was opened empty.
+    /// This is normal if no messages were in flight when the client last
+    /// disconnected.
+    StoreEmpty,
+}
+
+impl SessionCheck {
+    /// Compares the broker's session-present flag to the number of entries
+    /// left in the store when it was opened.
+    pub fn new(session_present: bool, leftovers: usize) -> Self {
+        match (session_present, leftovers) {
+            (false, n) if n > 0 => SessionCheck::BrokerLost(n),
+            (true, 0) => SessionCheck::StoreEmpty,
+            _ => SessionCheck::Consistent,
+        }
+    }
+
+    /// Determines if the store and broker agree on the session.
+    pub fn is_consistent(&self) -> bool {
+        *self == SessionCheck::Consistent
+    }
+}
+
+impl fmt::Display for SessionCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionCheck::Consistent => write!(f, "consistent"),
+            SessionCheck::BrokerLost(n) => write!(
+                f,
+                "the broker has no session, but the store has {} entries from one",
+                n
+            ),
+            SessionCheck::StoreEmpty => {
+                write!(f, "the broker has a session, but the store was empty")
+            }
+        }
+    }
+}
+
+/// A summary of a session of a store, from open to close.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionSummary {
+    /// The time the store was opened, in seconds since the Unix epoch.
+    pub opened: u64,
+    /// How long the store was open, in seconds.
+    pub duration: u64,
+    /// The number of entries left from the previous session, at open.
+    pub leftovers: usize,
+    /// The number of values put into the store.
+    pub puts: u64,
+    /// The number of entries removed from the store.
+    pub removes: u64,
+    /// The largest number of entries that were in the store at once.
+    pub peak_backlog: usize,
+    /// The number of entries in the store when it was closed.
+    pub final_backlog: usize,
+    /// The number of operations that failed.
+    pub errors: u64,
+    /// The number of operations that timed out, as by running out of the
+    /// latency budget. These are counted in the errors too.
+    pub timeouts: u64,
+}
+
+impl SessionSummary {
+    /// Gets the summary as the fields of a Redis hash.
+    pub fn to_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("opened", self.opened.to_string()),
+            ("duration", self.duration.to_string()),
+            ("leftovers", self.leftovers.to_string()),
+            ("puts", self.puts.to_string()),
+            ("removes", self.removes.to_string()),
+            ("peak_backlog", self.peak_backlog.to_string()),
+            ("final_backlog", self.final_backlog.to_string()),
+            ("errors", self.errors.to_string()),
+            ("timeouts", self.timeouts.to_string()),
+        ]
+    }
+
+    /// Reads the summary from the fields of a Redis hash.
+    /// Any missing or bad field is taken as zero, so that a summary from
+    /// an older or newer version can still be read.
+    pub fn from_fields<I>(fields: I) -> Self
+    where
+        I: IntoIterator<Item = (String, Vec<u8>)>,
+    {
+        let fields: HashMap<String, Vec<u8>> = fields.into_iter().collect();
+        let get = |name: &str| {
+            fields
+                .get(name)
+                .and_then(|v| std::str::from_utf8(v).ok())
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or_default()
+        };
+        Self {
+            opened: get("opened"),
+            duration: get("duration"),
+            leftovers: get("leftovers") as usize,
+            puts: get("puts"),
+            removes: get("removes"),
+            peak_backlog: get("peak_backlog") as usize,
+            final_backlog: get("final_backlog") as usize,
+            errors: get("errors"),
+            timeouts: get("timeouts"),
+        }
+    }
+
+    /// Notes a change in the number of entries in the store.
+    pub(crate) fn backlog_changed(&mut self, added: usize, removed: usize) {
+        self.final_backlog = (self.final_backlog + added).saturating_sub(removed);
+        self.peak_backlog = self.peak_backlog.max(self.final_backlog);
+    }
+}
+
+impl fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "opened at {}, for {}s: {} leftovers, {} puts, {} removes, \
+             peak backlog {}, final backlog {}, {} errors, {} timeouts",
+            self.opened,
+            self.duration,
+            self.leftovers,
+            self.puts,
+            self.removes,
+            self.peak_backlog,
+            self.final_backlog,
+            self.errors,
+            self.timeouts
+        )
+    }
+}
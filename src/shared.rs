@@ -0,0 +1,111 @@
+// mqtt.rust.redis/src/shared.rs
+//
+// A Redis connection shared by the persistence stores of several clients.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! A Redis connection shared by the persistence stores of several clients.
+//!
+//! Each [`RedisPersistence`] normally has a connection of its own, so a
+//! process with twenty MQTT clients keeps twenty connections open to the
+//! local Redis server. A [`SharedRedisPersistence`] holds one connection,
+//! and hands out persistence objects that all use it, each with its own
+//! store, named for its own client ID and server URI, as usual.
+//!
+//! The stores take turns on the connection, one command at a time, so a
+//! slow command in one store holds up the others. If the connection is
+//! dropped, the next store to use it makes a new one for all of them.
+//! The stores should share the same timeouts, since they're applied to
+//! the shared socket.
+
+use crate::{
+    adapter,
+    link::SharedSlot,
+    state::StateCell,
+    store::{Store, DEFAULT_URL},
+    write_behind::WriteBehind,
+    RedisPersistence, Result,
+};
+use redis::Client;
+use std::sync::{Arc, Mutex};
+
+/// A source of persistence objects that share a single connection to the
+/// Redis server.
+#[derive(Clone)]
+pub struct SharedRedisPersistence {
+    /// The client, to make a new connection when the shared one is lost.
+    client: Client,
+    /// The connection shared by the stores, if it's open.
+    slot: SharedSlot,
+}
+
+impl SharedRedisPersistence {
+    /// Creates a shared connection to the Redis server at the URL, like
+    /// `redis://localhost:6380/2`. The connection is made when the first
+    /// store is opened.
+    pub fn from_url(url: &str) -> Result<Self> {
+        Ok(Self::with_client(adapter::open_client(url)?))
+    }
+
+    /// Creates a shared connection with the Redis client.
+    pub fn with_client(client: Client) -> Self {
+        Self {
+            client,
+            slot: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Creates a shared connection to the Redis server on localhost.
+    pub fn local() -> Result<Self> {
+        Self::from_url(DEFAULT_URL)
+    }
+
+    /// Creates a persistence object, for one MQTT client, that uses the
+    /// shared connection.
+    pub fn persistence(&self) -> RedisPersistence {
+        let state = StateCell::default();
+        let store = Store::from_shared(self.client.clone(), self.slot.clone(), state.clone());
+        RedisPersistence {
+            writer: WriteBehind::default(),
+            store: Arc::new(Mutex::new(store)),
+            state,
+        }
+    }
+
+    /// Determines if the shared connection is open.
+    ///
+    /// This waits for any command that's in progress on the connection.
+    pub fn is_open(&self) -> bool {
+        self.slot.lock().map(|conn| conn.is_some()).unwrap_or(false)
+    }
+}
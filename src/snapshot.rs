@@ -0,0 +1,85 @@
+// mqtt.rust.redis/src/snapshot.rs
+//
+// Binary snapshots of a persistence store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Binary snapshots of a persistence store.
+//!
+//! A snapshot is the serialized form of the store's hash, as produced by
+//! the Redis `DUMP` command. It preserves the server's encoding of the
+//! hash, and is written back with a single `RESTORE`, which is much
+//! faster than copying the store field-by-field.
+//!
+//! Note that the format is specific to the version of Redis. A snapshot
+//! can be restored to the same or a newer version of the server, but not
+//! to an older one.
+
+/// A binary snapshot of a single store, in the Redis `DUMP` format.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The dumped hash, or `None` if the store was empty.
+    data: Option<Vec<u8>>,
+}
+
+impl Snapshot {
+    /// Creates a snapshot from the dumped data.
+    /// An empty buffer is taken to be a snapshot of an empty store, since
+    /// a dump is never empty.
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self {
+            data: if data.is_empty() { None } else { Some(data) },
+        }
+    }
+
+    /// Determines if this is a snapshot of an empty store.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_none()
+    }
+
+    /// Gets the dumped data, or an empty buffer for an empty store.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.data.as_deref().unwrap_or_default()
+    }
+
+    /// Gets the dumped data, or an empty buffer for an empty store.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data.unwrap_or_default()
+    }
+}
+
+impl From<Option<Vec<u8>>> for Snapshot {
+    fn from(data: Option<Vec<u8>>) -> Self {
+        Self::from_bytes(data.unwrap_or_default())
+    }
+}
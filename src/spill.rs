@@ -0,0 +1,272 @@
+// mqtt.rust.redis/src/spill.rs
+//
+// Spilling of the oldest entries of a store to disk.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Spilling of the oldest entries of a store to disk.
+//!
+//! During a long broker outage, an application that keeps publishing can
+//! fill the store beyond the memory budget of a small, local, Redis
+//! server. With spilling, the store keeps only the newest entries in
+//! Redis, up to a cap, and moves the oldest ones out to files on disk.
+//! Nothing is written to disk until the cap is reached, so there are no
+//! routine flash writes in normal operation.
+//!
+//! Each spilled entry is a file, named for the escaped key, in a directory
+//! named for the escaped store.
+
+use crate::{key_kind::PahoKey, name};
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The spill of a store to disk.
+#[derive(Debug)]
+pub struct Spill {
+    /// The base directory for all the spilled stores.
+    base: PathBuf,
+    /// The directory for this store.
+    dir: PathBuf,
+    /// The maximum number of entries to keep in Redis.
+    cap: usize,
+    /// The keys of the entries in Redis, oldest first.
+    order: VecDeque<String>,
+}
+
+impl Spill {
+    /// Creates a spill into the base directory, keeping up to `cap`
+    /// entries in Redis.
+    pub fn new<P: AsRef<Path>>(base: P, cap: usize) -> Self {
+        Self {
+            base: base.as_ref().to_path_buf(),
+            dir: PathBuf::new(),
+            cap: cap.max(1),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Binds the spill to a newly opened store, with the keys that are
+    /// already in Redis.
+    ///
+    /// The order that the keys were written isn't known, so they are
+    /// taken to be in the order of their Paho sequence numbers and packet
+    /// ID's, which is close enough to decide which to spill first.
+    pub fn bind(&mut self, store: &str, mut keys: Vec<String>) -> io::Result<()> {
+        self.dir = self.base.join(name::escape(store, &['/', '\\']));
+        fs::create_dir_all(&self.dir)?;
+        keys.sort_by_key(|key| PahoKey::parse(key).map(|k| k.id));
+        self.order = keys.into();
+        Ok(())
+    }
+
+    /// Gets the path of the file for the key.
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(name::escape(key, &['/', '\\', '.']))
+    }
+
+    /// Notes that the key was written to Redis, returning the oldest keys
+    /// that should now be spilled to disk, if the store is over the cap.
+    /// Any older copy of the key that was spilled is removed, as the one
+    /// in Redis replaces it.
+    pub fn wrote(&mut self, key: &str) -> io::Result<Vec<String>> {
+        if !self.order.iter().any(|k| k == key) {
+            if self.contains(key) {
+                fs::remove_file(self.path(key))?;
+            }
+            self.order.push_back(key.to_string());
+        }
+        let n = self.order.len().saturating_sub(self.cap);
+        Ok(self.order.drain(..n).collect())
+    }
+
+    /// Writes a spilled entry to disk.
+    pub fn write(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        fs::write(self.path(key), value)
+    }
+
+    /// Reads a spilled entry, if there is one.
+    pub fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Gets the size of a spilled entry, if there is one.
+    pub fn size_of(&self, key: &str) -> io::Result<Option<usize>> {
+        match fs::metadata(self.path(key)) {
+            Ok(md) => Ok(Some(md.len() as usize)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Determines if the key was spilled to disk.
+    pub fn contains(&self, key: &str) -> bool {
+        self.path(key).is_file()
+    }
+
+    /// Removes the key, whether it's in Redis or on disk.
+    /// Returns whether there was a copy of it on disk.
+    pub fn remove(&mut self, key: &str) -> io::Result<bool> {
+        if let Some(i) = self.order.iter().position(|k| k == key) {
+            self.order.remove(i);
+        }
+        match fs::remove_file(self.path(key)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Gets the keys of all the spilled entries.
+    pub fn keys(&self) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let file_name = entry?.file_name();
+            if let Some(key) = file_name.to_str().and_then(name::unescape) {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Removes all the entries, from the order and the disk.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.order.clear();
+        for key in self.keys()? {
+            let _ = self.remove(&key)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fake_server::{FakeServer, Value},
+        state::StateCell,
+        store::Store,
+        Error,
+    };
+
+    // A scratch directory for a test's spill, removed when it's dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(test: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "mqtt-redis-spill-{}-{}",
+                test,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_wrote_spills_oldest() {
+        let tmp = TempDir::new("wrote");
+        let mut spill = Spill::new(&tmp.0, 2);
+        spill.bind("client:tcp://localhost:1883", vec![]).unwrap();
+
+        assert!(spill.wrote("a").unwrap().is_empty());
+        assert!(spill.wrote("b").unwrap().is_empty());
+        assert_eq!(spill.wrote("c").unwrap(), ["a"]);
+        spill.write("a", b"1").unwrap();
+        assert!(spill.contains("a"));
+        assert_eq!(spill.read("a").unwrap().unwrap(), b"1");
+        assert_eq!(spill.size_of("a").unwrap(), Some(1));
+
+        // A new copy in Redis replaces the one on disk
+        assert_eq!(spill.wrote("a").unwrap(), ["b"]);
+        assert!(!spill.contains("a"));
+        assert_eq!(spill.read("a").unwrap(), None);
+    }
+
+    #[test]
+    fn test_store_round_trip() {
+        let tmp = TempDir::new("round-trip");
+        let server = FakeServer::start();
+        let mut store = Store::from_url(&server.url(), StateCell::default()).unwrap();
+        store.set_spill(Some(tmp.0.clone()), 2);
+        store.open("client", "tcp://localhost:1883").unwrap();
+
+        store.put("a", &[b"1"]).unwrap();
+        store.put("b", &[b"2"]).unwrap();
+        store.put("c", &[b"3"]).unwrap();
+
+        let hash = crate::name::store_name("client", "tcp://localhost:1883");
+        match server.db().get(&hash) {
+            Some(Value::Hash(h)) => assert!(!h.contains_key(&b"a"[..])),
+            v => panic!("not a hash: {:?}", v),
+        }
+        assert_eq!(store.get("a").unwrap(), b"1");
+        let mut keys = store.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["a", "b", "c"]);
+
+        store.remove("a").unwrap();
+        assert!(matches!(store.get("a"), Err(Error::NotFound)));
+        assert_eq!(store.keys().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_corrupt_dir() {
+        let tmp = TempDir::new("corrupt");
+        let mut spill = Spill::new(&tmp.0, 1);
+        spill.bind("client:tcp://localhost:1883", vec![]).unwrap();
+        spill.write("a", b"1").unwrap();
+
+        // A stray file that isn't the name of a key is left alone
+        fs::write(spill.dir.join("bad%zz"), b"junk").unwrap();
+        assert_eq!(spill.keys().unwrap(), ["a"]);
+
+        // An entry that can't be read is an error, not a missing entry
+        fs::create_dir(spill.path("b")).unwrap();
+        assert!(!spill.contains("b"));
+        assert!(spill.read("b").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+// mqtt.rust.redis/src/stamp.rs
+//
+// Write stamps to detect concurrent writers to a persistence store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Write stamps to detect concurrent writers to a persistence store.
+//!
+//! Each persistence object gets a unique instance ID when it is opened,
+//! and stamps every write into the store's metadata with that ID. If a
+//! read later finds that the stamp belongs to someone else, then another
+//! process wrote to the same store. That is nearly always the sign of two
+//! MQTT clients running with the same client ID, and is reported as such,
+//! rather than letting the two clients silently corrupt each other's
+//! in-flight messages.
+
+use std::{
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The field in the metadata hash that holds the ID of the last writer.
+pub const WRITER_FIELD: &str = "writer";
+
+/// The field in the metadata hash with the time of the last compaction.
+pub const COMPACTED_FIELD: &str = "compacted";
+
+/// The field in the metadata hash with the sequence number of the last
+/// sequenced write applied to the store.
+pub const SEQ_FIELD: &str = "seq";
+
+/// The field in the metadata hash that's set before the first compressed
+/// value is put into the store, so that whatever reads the store knows to
+/// decompress its values.
+pub const COMPRESSED_FIELD: &str = "compressed";
+
+/// The field in the metadata hash that's set before the first value is put
+/// into the store as segments, or in chunks, so that whatever reads the
+/// store knows to gather them.
+pub const SEGMENTED_FIELD: &str = "segmented";
+
+/// The field in the metadata hash with a checksum of the legacy names that
+/// the store was migrated from, so that it's only done once.
+pub const MIGRATED_FIELD: &str = "migrated";
+
+/// Gets the current time as the number of seconds since the UNIX epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Creates an ID that is unique to this instance of a persistence object.
+/// This combines the process ID, the time, and a counter for objects in
+/// the same process.
+fn new_instance_id() -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+
+    format!(
+        "{}-{:x}-{}",
+        process::id(),
+        nanos,
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// The write stamp for a single, open, persistence store.
+#[derive(Debug)]
+pub struct WriteStamp {
+    /// The unique ID for this instance
+    id: String,
+    /// Whether we've written to the store since it was opened.
+    /// Until then, a foreign stamp is just left over from a previous
+    /// session.
+    written: bool,
+    /// The last foreign writer that we reported, to avoid flooding the log.
+    foreign: Option<String>,
+}
+
+impl WriteStamp {
+    /// Creates a new stamp with a unique instance ID.
+    pub fn new() -> Self {
+        Self {
+            id: new_instance_id(),
+            written: false,
+            foreign: None,
+        }
+    }
+
+    /// Gets the unique ID of this instance.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Records that we wrote to the store.
+    /// The `prev` writer is the stamp that was in the store just before
+    /// our write.
+    pub fn wrote(&mut self, store: &str, prev: Option<String>) {
+        self.check(store, prev);
+        self.written = true;
+    }
+
+    /// Checks the writer stamp that was read back from the store.
+    /// Returns `true` if it shows evidence of another writer.
+    pub fn check(&mut self, store: &str, writer: Option<String>) -> bool {
+        let writer = match writer {
+            Some(w) if self.written && w != self.id => w,
+            _ => return false,
+        };
+
+        if self.foreign.as_ref() != Some(&writer) {
+            warn!(
+                "Redis persistence [{}]: the store was modified by another writer ({}) \
+                 while this client ({}) has it open. Is there another MQTT client \
+                 running with the same client ID?",
+                store, writer, self.id
+            );
+            self.foreign = Some(writer);
+        }
+        true
+    }
+}
+
+impl Default for WriteStamp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,186 @@
+// mqtt.rust.redis/src/state.rs
+//
+// The state of the connection to the Redis server.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! The state of the connection to the Redis server.
+//!
+//! The persistence object tracks whether it is connected to the server,
+//! and makes the state available to the application, both as a simple
+//! query and as a watch that can wait for it to change. This lets an
+//! application dashboard show the state of the persistence layer directly
+//! rather than inferring it from the errors in the logs.
+//!
+//! The state is kept apart from the store itself, so reading it never
+//! waits on an operation that is stuck talking to the server.
+
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+/// The state of the connection to the Redis server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Connected to the server.
+    Connected,
+    /// The connection was lost, and we're trying to re-establish it.
+    Reconnecting {
+        /// The number of the current attempt, starting at one.
+        attempt: u32,
+    },
+    /// Not connected to the server, either because the store isn't open,
+    /// or because the connection failed.
+    Down {
+        /// The time the connection went down.
+        since: SystemTime,
+    },
+}
+
+impl ConnectionState {
+    /// Creates a `Down` state as of now.
+    pub fn down() -> Self {
+        ConnectionState::Down {
+            since: SystemTime::now(),
+        }
+    }
+
+    /// Determines if this is the `Connected` state.
+    pub fn is_connected(&self) -> bool {
+        *self == ConnectionState::Connected
+    }
+}
+
+impl Default for ConnectionState {
+    fn default() -> Self {
+        Self::down()
+    }
+}
+
+/// The current state, and a count of the number of times it changed.
+type Versioned = (u64, ConnectionState);
+
+/// The shared cell holding the connection state.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StateCell {
+    inner: Arc<(Mutex<Versioned>, Condvar)>,
+}
+
+impl StateCell {
+    /// Locks the state, ignoring any poison since it's just a value.
+    fn lock(&self) -> MutexGuard<'_, Versioned> {
+        self.inner.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Gets the current state.
+    pub fn get(&self) -> ConnectionState {
+        self.lock().1
+    }
+
+    /// Sets a new state, waking any watchers if it changed.
+    /// A `Down` state doesn't replace an earlier `Down`, so that it keeps
+    /// the time that the connection was first lost.
+    pub fn set(&self, state: ConnectionState) {
+        let mut cur = self.lock();
+        let changed = match (cur.1, state) {
+            (ConnectionState::Down { .. }, ConnectionState::Down { .. }) => false,
+            (prev, state) => prev != state,
+        };
+        if changed {
+            debug!("Redis persistence connection state: {:?}", state);
+            *cur = (cur.0 + 1, state);
+            self.inner.1.notify_all();
+        }
+    }
+
+    /// Creates a watcher for the state.
+    pub fn watch(&self) -> StateWatcher {
+        StateWatcher {
+            cell: self.clone(),
+            seen: self.lock().0,
+        }
+    }
+}
+
+/// A watch on the connection state of a persistence store.
+///
+/// This can be used to wait for the state to change, from any thread.
+#[derive(Debug, Clone)]
+pub struct StateWatcher {
+    /// The shared state
+    cell: StateCell,
+    /// The version of the state that this watcher last saw.
+    seen: u64,
+}
+
+impl StateWatcher {
+    /// Gets the current state.
+    pub fn get(&self) -> ConnectionState {
+        self.cell.get()
+    }
+
+    /// Blocks until the state changes from the last one seen by this
+    /// watcher, and returns the new state.
+    pub fn changed(&mut self) -> ConnectionState {
+        let guard = self.cell.lock();
+        let seen = self.seen;
+        let guard = self
+            .cell
+            .inner
+            .1
+            .wait_while(guard, |cur| cur.0 == seen)
+            .unwrap_or_else(|e| e.into_inner());
+        self.seen = guard.0;
+        guard.1
+    }
+
+    /// Blocks until the state changes from the last one seen by this
+    /// watcher, or the timeout expires.
+    /// Returns the new state, or `None` on a timeout.
+    pub fn changed_timeout(&mut self, timeout: Duration) -> Option<ConnectionState> {
+        let guard = self.cell.lock();
+        let seen = self.seen;
+        let (guard, res) = self
+            .cell
+            .inner
+            .1
+            .wait_timeout_while(guard, timeout, |cur| cur.0 == seen)
+            .unwrap_or_else(|e| e.into_inner());
+        if res.timed_out() {
+            return None;
+        }
+        self.seen = guard.0;
+        Some(guard.1)
+    }
+}
@@ -0,0 +1,294 @@
+// mqtt.rust.redis/src/stats.rs
+//
+// Statistics on the messages held in a store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Statistics on the messages held in a store.
+//!
+//! The keys tell the kind of each entry, but to see which class of message
+//! is backing up, like outbound QoS 1 messages or inbound QoS 2 ones, the
+//! QoS has to be decoded from the value that Paho stored under the key.
+//!
+//! The message packets in flight start with the MQTT fixed header, which
+//! holds the QoS. The async client's queued commands and messages are
+//! Paho's own records, written with native-endian integers, which are
+//! decoded here on a best-effort basis. Anything that can't be decoded
+//! is counted with an unknown QoS.
+//!
+//! The store also keeps a count of the bytes it writes to and reads from
+//! the server, as its [`Throughput`], to base capacity planning on the
+//! measured persistence traffic.
+
+use crate::key_kind::{Direction, KeyKind};
+use std::{collections::HashMap, fmt};
+
+/// The MQTT packet type for PUBLISH, as in the fixed header, and the type
+/// of an async client's publish command.
+const PUBLISH: u8 = 3;
+
+/// A class of messages in the store, by direction and QoS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MessageClass {
+    /// The direction of the messages.
+    pub direction: Direction,
+    /// The QoS of the messages, if it's known.
+    pub qos: Option<u8>,
+}
+
+impl MessageClass {
+    /// Gets the class of the entry with the key and value, if it's a Paho
+    /// persistence key.
+    pub fn of(key: &str, value: &[u8]) -> Option<MessageClass> {
+        let kind = KeyKind::of(key)?;
+        Some(MessageClass {
+            direction: kind.direction(),
+            qos: decode_qos(kind, value),
+        })
+    }
+}
+
+impl fmt::Display for MessageClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.qos {
+            Some(qos) => write!(f, "qos{} {}", qos, self.direction),
+            None => write!(f, "qos? {}", self.direction),
+        }
+    }
+}
+
+/// The number of entries, and their total size, for a class of messages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClassStats {
+    /// The number of entries.
+    pub count: usize,
+    /// The total size of the values, in bytes.
+    pub bytes: usize,
+}
+
+impl ClassStats {
+    /// Adds an entry with a value of `len` bytes.
+    fn add(&mut self, len: usize) {
+        self.count += 1;
+        self.bytes += len;
+    }
+}
+
+/// The entries of a store, broken down by direction and QoS.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct QosBreakdown {
+    /// The stats for each class of message.
+    classes: HashMap<MessageClass, ClassStats>,
+    /// The stats for the entries that aren't Paho keys.
+    other: ClassStats,
+}
+
+impl QosBreakdown {
+    /// Creates an empty breakdown.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Breaks down the entries, as key and value.
+    pub fn from_entries<'a, I>(entries: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a str, &'a [u8])>,
+    {
+        let mut breakdown = Self::new();
+        for (key, value) in entries {
+            breakdown.add(key, value);
+        }
+        breakdown
+    }
+
+    /// Adds an entry to the breakdown.
+    pub fn add(&mut self, key: &str, value: &[u8]) {
+        match MessageClass::of(key, value) {
+            Some(class) => self.classes.entry(class).or_default().add(value.len()),
+            None => self.other.add(value.len()),
+        }
+    }
+
+    /// Adds all the entries from another breakdown into this one.
+    pub fn merge(&mut self, other: &QosBreakdown) {
+        for (class, stats) in &other.classes {
+            let entry = self.classes.entry(*class).or_default();
+            entry.count += stats.count;
+            entry.bytes += stats.bytes;
+        }
+        self.other.count += other.other.count;
+        self.other.bytes += other.other.bytes;
+    }
+
+    /// Gets the stats for the class of message.
+    pub fn get(&self, class: MessageClass) -> ClassStats {
+        self.classes.get(&class).copied().unwrap_or_default()
+    }
+
+    /// Gets the stats for all the classes of message in the store, in
+    /// order by direction, then QoS.
+    pub fn classes(&self) -> Vec<(MessageClass, ClassStats)> {
+        let mut v: Vec<_> = self.classes.iter().map(|(c, s)| (*c, *s)).collect();
+        v.sort_by_key(|(c, _)| *c);
+        v
+    }
+
+    /// Gets the stats for the entries that aren't Paho keys.
+    pub fn other(&self) -> ClassStats {
+        self.other
+    }
+}
+
+impl fmt::Display for QosBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (class, stats) in self.classes() {
+            writeln!(f, "{}: {} ({} bytes)", class, stats.count, stats.bytes)?;
+        }
+        write!(
+            f,
+            "other: {} ({} bytes)",
+            self.other.count, self.other.bytes
+        )
+    }
+}
+
+/// The amount of data that a store has written to, and read from, the
+/// Redis server.
+///
+/// This counts the bytes of the values, as sent over the connection,
+/// which is most of the persistence traffic. The keys and the protocol
+/// overhead are not included.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Throughput {
+    /// The number of bytes written to the server.
+    pub bytes_written: u64,
+    /// The number of values written to the server.
+    pub writes: u64,
+    /// The number of bytes read from the server.
+    pub bytes_read: u64,
+    /// The number of values read from the server.
+    pub reads: u64,
+}
+
+impl Throughput {
+    /// Counts a value of `len` bytes written to the server.
+    pub(crate) fn wrote(&mut self, len: usize) {
+        self.writes += 1;
+        self.bytes_written += len as u64;
+    }
+
+    /// Counts a value of `len` bytes read from the server.
+    pub(crate) fn read(&mut self, len: usize) {
+        self.reads += 1;
+        self.bytes_read += len as u64;
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "written: {} bytes in {} values, read: {} bytes in {} values",
+            self.bytes_written, self.writes, self.bytes_read, self.reads
+        )
+    }
+}
+
+/// Decodes the QoS of a message from the value stored for it.
+fn decode_qos(kind: KeyKind, value: &[u8]) -> Option<u8> {
+    match kind {
+        KeyKind::Sent => header_qos(value),
+        // Only QoS 2 messages have a PUBREL, and only inbound QoS 2
+        // messages are held until they're released.
+        KeyKind::PubRel | KeyKind::Received => Some(2),
+        KeyKind::Command => command_qos(value),
+        KeyKind::Queued => queued_qos(value),
+    }
+}
+
+/// Gets the QoS from the fixed header of a PUBLISH packet.
+fn header_qos(value: &[u8]) -> Option<u8> {
+    let hdr = *value.first()?;
+    if hdr >> 4 != PUBLISH {
+        return None;
+    }
+    valid_qos((hdr >> 1) & 0x03)
+}
+
+/// Reads a native-endian C int from the front of the buffer, advancing
+/// past it.
+fn read_int(buf: &mut &[u8]) -> Option<i32> {
+    let (n, rest) = (buf.get(..4)?, buf.get(4..)?);
+    *buf = rest;
+    Some(i32::from_ne_bytes([n[0], n[1], n[2], n[3]]))
+}
+
+/// Skips `n` bytes from the front of the buffer.
+fn skip(buf: &mut &[u8], n: usize) -> Option<()> {
+    *buf = buf.get(n..)?;
+    Some(())
+}
+
+/// Gets the QoS of an async client's publish command.
+/// This is the command type and token, then the NUL-terminated topic,
+/// the payload length and payload, and the QoS.
+fn command_qos(value: &[u8]) -> Option<u8> {
+    let mut buf = value;
+    if read_int(&mut buf)? != i32::from(PUBLISH) {
+        return None;
+    }
+    read_int(&mut buf)?;
+    let nul = buf.iter().position(|&b| b == 0)?;
+    skip(&mut buf, nul + 1)?;
+    let len = usize::try_from(read_int(&mut buf)?).ok()?;
+    skip(&mut buf, len)?;
+    valid_qos(u8::try_from(read_int(&mut buf)?).ok()?)
+}
+
+/// Gets the QoS of a message queued for the application.
+/// This is the payload length and payload, then the QoS.
+fn queued_qos(value: &[u8]) -> Option<u8> {
+    let mut buf = value;
+    let len = usize::try_from(read_int(&mut buf)?).ok()?;
+    skip(&mut buf, len)?;
+    valid_qos(u8::try_from(read_int(&mut buf)?).ok()?)
+}
+
+/// Checks that the QoS is valid.
+fn valid_qos(qos: u8) -> Option<u8> {
+    if qos <= 2 {
+        Some(qos)
+    } else {
+        None
+    }
+}
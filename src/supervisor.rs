@@ -0,0 +1,148 @@
+// mqtt.rust.redis/src/supervisor.rs
+//
+// A background supervisor for the connection to the Redis server.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! A background supervisor for the connection to the Redis server.
+//!
+//! Without a supervisor, a dropped connection is found, and replaced, in
+//! the middle of the next operation, which then takes as long as the
+//! reconnect. The supervisor checks the connection with a PING, now and
+//! then, in a thread of its own, and when it's gone, makes a new one off
+//! to the side, without holding the store, and puts it in place. So the
+//! reconnect is usually done before the MQTT client needs the store.
+//!
+//! The operations still go through the shared store, as always, so they
+//! wait for a supervisor check that's in progress, but only for the PING;
+//! never for the connect.
+
+use crate::{adapter, store::Store};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The name of the supervisor thread, as seen in a debugger or profiler.
+pub const THREAD_NAME: &str = "mqtt-redis-supervisor";
+
+/// The default time between checks of the connection.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the supervisor checks if it's been stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A background supervisor of the connection to the server.
+///
+/// The supervisor runs until this object is dropped, or the store is.
+#[derive(Debug)]
+pub struct Supervisor {
+    /// Flag to stop the supervisor thread.
+    stop: Arc<AtomicBool>,
+    /// The supervisor thread.
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Supervisor {
+    /// Starts supervising the connection of the store, checking it every
+    /// `interval`, in a background thread.
+    pub(crate) fn start(store: &Arc<Mutex<Store>>, interval: Duration) -> crate::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let store = Arc::downgrade(store);
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name(THREAD_NAME.to_string())
+                .spawn(move || supervise(store, interval, stop))?
+        };
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Stops the supervisor, waiting for its thread to finish.
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The body of the supervisor thread.
+fn supervise(store: Weak<Mutex<Store>>, interval: Duration, stop: Arc<AtomicBool>) {
+    let mut next = Instant::now() + interval;
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now < next {
+            thread::sleep(POLL_INTERVAL.min(next - now));
+            continue;
+        }
+        next = now + interval;
+
+        let store = match store.upgrade() {
+            Some(store) => store,
+            None => break,
+        };
+        let needed = store.lock().unwrap_or_else(|e| e.into_inner()).supervise();
+
+        if let Some((client, timeout)) = needed {
+            let res = match timeout {
+                Some(timeout) => adapter::connect_timeout(&client, timeout),
+                None => adapter::connect(&client),
+            };
+            match res {
+                Ok(conn) => {
+                    debug!("Supervisor reconnected to the Redis server");
+                    store
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .install_connection(conn);
+                }
+                Err(e) => debug!("Supervisor can't reach the Redis server: {}", e),
+            }
+        }
+    }
+    debug!("Supervisor done");
+}
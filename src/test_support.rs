@@ -0,0 +1,378 @@
+// mqtt.rust.redis/src/test_support.rs
+//
+// Helpers for applications to test their recovery with a Redis store.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Helpers for applications to test their recovery with a Redis store.
+//!
+//! This is in the `test-support` feature, for use as a dev-dependency of
+//! an application that embeds the persistence. A [`TestStore`] is a
+//! store in a scratch database on a real Redis server, which is cleared
+//! when it's created and again when it's dropped. It can be seeded with
+//! fabricated entries, in the formats that Paho uses, to look like a
+//! client that crashed with messages in flight, and then checked after
+//! the application recovers.
+//!
+//! ```ignore
+//! let store = TestStore::builder()
+//!     .client_id("sensor-7")
+//!     .seed(test_support::sent_publish(1, 1, "data/temp", b"21.5"))
+//!     .seed(test_support::pubrel(2))
+//!     .finalize()?;
+//!
+//! run_the_client(store.persistence());
+//! store.assert_empty();
+//! ```
+
+use crate::{
+    key_kind::{KeyKind, KindCounts},
+    Error, RedisPersistence, RedisPersistenceBuilder, Result,
+};
+
+/// The database used for the test stores, unless another is chosen, to
+/// keep them away from the application's data in the default database.
+pub const DEFAULT_TEST_DB: i64 = 15;
+
+/// The client ID for the test stores, unless another is chosen.
+pub const DEFAULT_CLIENT_ID: &str = "mqtt-redis-test";
+
+/// The server URI for the test stores, unless another is chosen.
+pub const DEFAULT_SERVER_URI: &str = "tcp://localhost:1883";
+
+/// The MQTT packet type for PUBLISH, and the async client's command type
+/// for a publish.
+const PUBLISH: u8 = 3;
+
+/// The MQTT fixed header for a PUBREL, with its required flags.
+const PUBREL_HEADER: u8 = 0x62;
+
+/// A key and value to seed a store with.
+pub type Entry = (String, Vec<u8>);
+
+/// Appends the MQTT variable-length encoding of `n` to the buffer.
+fn push_remaining_len(buf: &mut Vec<u8>, mut n: usize) {
+    loop {
+        let mut b = (n % 128) as u8;
+        n /= 128;
+        if n > 0 {
+            b |= 0x80;
+        }
+        buf.push(b);
+        if n == 0 {
+            break;
+        }
+    }
+}
+
+/// Creates an MQTT v3 PUBLISH packet.
+fn publish_packet(packet_id: u16, qos: u8, topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 4);
+    body.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+    body.extend_from_slice(topic.as_bytes());
+    if qos > 0 {
+        body.extend_from_slice(&packet_id.to_be_bytes());
+    }
+    body.extend_from_slice(payload);
+
+    let mut pkt = vec![(PUBLISH << 4) | ((qos & 0x03) << 1)];
+    push_remaining_len(&mut pkt, body.len());
+    pkt.extend(body);
+    pkt
+}
+
+/// Appends a native-endian C int to the buffer, as Paho writes them.
+fn push_int(buf: &mut Vec<u8>, n: i32) {
+    buf.extend_from_slice(&n.to_ne_bytes());
+}
+
+/// Creates the entry for an outbound QoS 1 or 2 PUBLISH that's waiting
+/// for its acknowledgment, as `s-<packet_id>`.
+pub fn sent_publish(packet_id: u16, qos: u8, topic: &str, payload: &[u8]) -> Entry {
+    (
+        format!("s-{}", packet_id),
+        publish_packet(packet_id, qos, topic, payload),
+    )
+}
+
+/// Creates the entry for the PUBREL of an outbound QoS 2 message that's
+/// waiting for the PUBCOMP, as `sc-<packet_id>`.
+pub fn pubrel(packet_id: u16) -> Entry {
+    let [hi, lo] = packet_id.to_be_bytes();
+    (format!("sc-{}", packet_id), vec![PUBREL_HEADER, 2, hi, lo])
+}
+
+/// Creates the entry for an inbound QoS 2 PUBLISH that's waiting for the
+/// PUBREL, as `r-<packet_id>`.
+pub fn received_publish(packet_id: u16, topic: &str, payload: &[u8]) -> Entry {
+    (
+        format!("r-{}", packet_id),
+        publish_packet(packet_id, 2, topic, payload),
+    )
+}
+
+/// Creates the entry for a publish command, queued by the async client
+/// before it was sent, as `c-<seq>`.
+pub fn queued_command(seq: u32, qos: u8, topic: &str, payload: &[u8]) -> Entry {
+    let mut value = Vec::with_capacity(topic.len() + payload.len() + 17);
+    push_int(&mut value, i32::from(PUBLISH));
+    push_int(&mut value, 0);
+    value.extend_from_slice(topic.as_bytes());
+    value.push(0);
+    push_int(&mut value, payload.len() as i32);
+    value.extend_from_slice(payload);
+    push_int(&mut value, i32::from(qos));
+    (format!("c-{}", seq), value)
+}
+
+/// Creates the entry for an inbound message, queued for delivery to the
+/// application, as `q-<seq>`.
+pub fn queued_message(seq: u32, qos: u8, payload: &[u8]) -> Entry {
+    let mut value = Vec::with_capacity(payload.len() + 8);
+    push_int(&mut value, payload.len() as i32);
+    value.extend_from_slice(payload);
+    push_int(&mut value, i32::from(qos));
+    (format!("q-{}", seq), value)
+}
+
+/// A builder for a [`TestStore`].
+#[derive(Debug, Clone)]
+pub struct TestStoreBuilder {
+    /// The URL of the Redis server.
+    url: Option<String>,
+    /// The database for the store.
+    db: i64,
+    /// The MQTT client ID.
+    client_id: String,
+    /// The URI of the MQTT server.
+    server_uri: String,
+    /// The entries to seed the store with.
+    entries: Vec<Entry>,
+}
+
+impl TestStoreBuilder {
+    /// Creates a builder for a store in the test database on the Redis
+    /// server on localhost.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the URL of the Redis server.
+    pub fn url<S: Into<String>>(mut self, url: S) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Sets the database for the store, in place of [`DEFAULT_TEST_DB`].
+    /// Tests that run in parallel can use different client ID's in the
+    /// same database, or a database each.
+    pub fn db(mut self, db: i64) -> Self {
+        self.db = db;
+        self
+    }
+
+    /// Sets the MQTT client ID that the store is opened for.
+    pub fn client_id<S: Into<String>>(mut self, client_id: S) -> Self {
+        self.client_id = client_id.into();
+        self
+    }
+
+    /// Sets the MQTT server URI that the store is opened for.
+    pub fn server_uri<S: Into<String>>(mut self, server_uri: S) -> Self {
+        self.server_uri = server_uri.into();
+        self
+    }
+
+    /// Adds an entry to seed the store with.
+    pub fn seed(mut self, entry: Entry) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Adds entries to seed the store with.
+    pub fn seed_all<I>(mut self, entries: I) -> Self
+    where
+        I: IntoIterator<Item = Entry>,
+    {
+        self.entries.extend(entries);
+        self
+    }
+
+    /// Creates and opens the store, clears anything left in it, and
+    /// writes the seed entries.
+    ///
+    /// The store is left open, as though the client had crashed, and
+    /// the application is about to recover.
+    pub fn finalize(self) -> Result<TestStore> {
+        let mut store = TestStore {
+            url: self.url,
+            db: self.db,
+            client_id: self.client_id,
+            server_uri: self.server_uri,
+            persistence: RedisPersistence::new(),
+        };
+        store.persistence = store.open_persistence()?;
+        store.persistence.clear()?;
+        for (key, value) in &self.entries {
+            store.persistence.put(key, &[value.as_slice()])?;
+        }
+        Ok(store)
+    }
+}
+
+impl Default for TestStoreBuilder {
+    fn default() -> Self {
+        Self {
+            url: None,
+            db: DEFAULT_TEST_DB,
+            client_id: DEFAULT_CLIENT_ID.to_string(),
+            server_uri: DEFAULT_SERVER_URI.to_string(),
+            entries: Vec::new(),
+        }
+    }
+}
+
+/// A store in a scratch database, for a test.
+///
+/// The store is cleared when it's dropped. The assertions panic, like
+/// the standard ones, with a message describing the difference.
+pub struct TestStore {
+    /// The URL of the Redis server, if not the default.
+    url: Option<String>,
+    /// The database for the store.
+    db: i64,
+    /// The MQTT client ID.
+    client_id: String,
+    /// The URI of the MQTT server.
+    server_uri: String,
+    /// The persistence for the store.
+    persistence: RedisPersistence,
+}
+
+impl TestStore {
+    /// Creates a builder for a test store.
+    pub fn builder() -> TestStoreBuilder {
+        TestStoreBuilder::new()
+    }
+
+    /// Gets the persistence for the store, to give to the MQTT client.
+    /// This is a handle to the same store, so the test can keep using
+    /// the [`TestStore`] while the client runs.
+    pub fn persistence(&self) -> RedisPersistence {
+        self.persistence.clone()
+    }
+
+    /// Gets the MQTT client ID that the store is opened for.
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Gets the MQTT server URI that the store is opened for.
+    pub fn server_uri(&self) -> &str {
+        &self.server_uri
+    }
+
+    /// Creates a new persistence for the store, and opens it.
+    fn open_persistence(&self) -> Result<RedisPersistence> {
+        let mut builder = RedisPersistenceBuilder::new().db(self.db);
+        if let Some(url) = self.url.as_ref() {
+            builder = builder.url(url.as_str());
+        }
+        let persistence = builder.finalize()?;
+        persistence.open(&self.client_id, &self.server_uri)?;
+        Ok(persistence)
+    }
+
+    /// Simulates a restart of the application: closes the store, and
+    /// opens it again with a new persistence, which is returned.
+    pub fn restart(&mut self) -> Result<RedisPersistence> {
+        self.persistence.close()?;
+        self.persistence = self.open_persistence()?;
+        Ok(self.persistence())
+    }
+
+    /// Gets the keys in the store, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys = self
+            .persistence
+            .keys()
+            .unwrap_or_else(|e| panic!("Error reading the keys of the test store: {}", e));
+        keys.sort();
+        keys
+    }
+
+    /// Asserts that the store holds exactly the keys, in any order.
+    pub fn assert_keys(&self, expected: &[&str]) {
+        let mut expected: Vec<String> = expected.iter().map(|k| k.to_string()).collect();
+        expected.sort();
+        let keys = self.keys();
+        assert_eq!(keys, expected, "The test store has the wrong keys");
+    }
+
+    /// Asserts that the store is empty.
+    pub fn assert_empty(&self) {
+        self.assert_keys(&[]);
+    }
+
+    /// Asserts that the store holds the number of keys of the kind.
+    pub fn assert_kind_count(&self, kind: KeyKind, expected: usize) {
+        let counts = KindCounts::from_keys(self.keys());
+        assert_eq!(
+            counts.get(kind),
+            expected,
+            "The test store has the wrong number of {} keys: {}",
+            kind,
+            counts
+        );
+    }
+
+    /// Asserts that the store holds the value under the key.
+    pub fn assert_value(&self, key: &str, expected: &[u8]) {
+        match self.persistence.get(key) {
+            Ok(value) => assert_eq!(
+                value, expected,
+                "The test store has the wrong value for '{}'",
+                key
+            ),
+            Err(Error::NotFound) => panic!("The test store has no key '{}'", key),
+            Err(e) => panic!("Error reading '{}' from the test store: {}", key, e),
+        }
+    }
+}
+
+impl Drop for TestStore {
+    fn drop(&mut self) {
+        let _ = self.persistence.clear();
+        let _ = self.persistence.close();
+    }
+}
@@ -0,0 +1,196 @@
+// mqtt.rust.redis/src/timeline.rs
+//
+// The timeline of recent store operations.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! The timeline of recent store operations.
+//!
+//! The store keeps a small ring buffer of the last operations it ran,
+//! with the key, size, duration, and outcome of each. It's cheap enough
+//! to leave on all the time, and is dumped to the log when an operation
+//! fails for good, so a crash report shows the lead-up to the failure
+//! without needing verbose logging.
+
+use crate::Error;
+use std::{collections::VecDeque, fmt, time::Duration};
+
+/// The default number of operations kept in the timeline.
+pub const DEFAULT_CAPACITY: usize = 64;
+
+/// The outcome of a store operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The operation succeeded.
+    Ok,
+    /// The key wasn't in the store.
+    NotFound,
+    /// The operation failed, with the error message.
+    Failed(String),
+}
+
+impl Outcome {
+    /// Gets the outcome of an operation from its result.
+    pub fn of<T>(res: &Result<T, Error>) -> Self {
+        match res {
+            Ok(_) => Outcome::Ok,
+            Err(Error::NotFound) => Outcome::NotFound,
+            Err(e) => Outcome::Failed(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Ok => write!(f, "ok"),
+            Outcome::NotFound => write!(f, "not found"),
+            Outcome::Failed(msg) => write!(f, "failed: {}", msg),
+        }
+    }
+}
+
+/// The record of a single store operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpRecord {
+    /// The name of the operation, like "put" or "get".
+    pub op: &'static str,
+    /// The key of the operation, if it had one.
+    pub key: Option<String>,
+    /// The size of the value written or read, in bytes, if any.
+    pub size: Option<usize>,
+    /// How long the operation took, including any retries.
+    pub duration: Duration,
+    /// The outcome of the operation.
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for OpRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.op)?;
+        if let Some(key) = self.key.as_ref() {
+            write!(f, " '{}'", key)?;
+        }
+        if let Some(size) = self.size {
+            write!(f, " {} bytes", size)?;
+        }
+        write!(f, " in {:?}: {}", self.duration, self.outcome)
+    }
+}
+
+/// The size of the value in the result of an operation, if it has one.
+pub(crate) trait OpSize {
+    /// Gets the size of the value, in bytes, if there is one.
+    fn op_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl OpSize for () {}
+impl OpSize for bool {}
+impl OpSize for Vec<String> {}
+impl OpSize for crate::QosBreakdown {}
+impl OpSize for Option<crate::EntryInfo> {}
+impl OpSize for Vec<(String, crate::EntryInfo)> {}
+
+impl OpSize for Vec<u8> {
+    fn op_size(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl OpSize for bytes::Bytes {
+    fn op_size(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl OpSize for usize {
+    fn op_size(&self) -> Option<usize> {
+        Some(*self)
+    }
+}
+
+/// A ring buffer of the most recent store operations.
+#[derive(Debug)]
+pub(crate) struct Timeline {
+    /// The records, oldest first.
+    records: VecDeque<OpRecord>,
+    /// The maximum number of records to keep.
+    capacity: usize,
+}
+
+impl Timeline {
+    /// Creates a timeline to hold up to `capacity` records.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Changes the number of records kept, dropping the oldest ones if
+    /// there are now too many. A capacity of zero turns off the timeline.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.records.len() > capacity {
+            self.records.pop_front();
+        }
+    }
+
+    /// Adds a record, dropping the oldest one if the timeline is full.
+    pub fn push(&mut self, rec: OpRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(rec);
+    }
+
+    /// Gets a copy of the records, oldest first.
+    pub fn records(&self) -> Vec<OpRecord> {
+        self.records.iter().cloned().collect()
+    }
+}
+
+impl fmt::Display for Timeline {
+    /// Writes the records, one per line, oldest first.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for rec in &self.records {
+            writeln!(f, "  {}", rec)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+// mqtt.rust.redis/src/tls.rs
+//
+// TLS options for an encrypted link to the Redis server.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! TLS options for an encrypted link to the Redis server.
+//!
+//! This is in the `tls` feature, which turns on the rustls support in
+//! redis-rs for `rediss://` URLs. With the defaults, the server's
+//! certificate is checked against the system's trusted roots, and its
+//! hostname is verified. A [`TlsConfig`] can add a private CA, a client
+//! certificate for mutual TLS, or turn off the hostname check for a
+//! server that's reached by an address that isn't in its certificate.
+//!
+//! The certificate files are PEM-encoded, and are read each time the
+//! store makes a Redis client, so a renewed certificate is picked up the
+//! next time the store is configured.
+
+use crate::{adapter, Error, Result};
+use redis::{Client, ConnectionInfo};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The TLS options for a `rediss://` server.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// The file with the CA certificate to trust, if not the system's.
+    ca_cert: Option<PathBuf>,
+    /// The files with the client certificate and its key, if any.
+    client_cert: Option<(PathBuf, PathBuf)>,
+    /// Whether to verify that the hostname matches the certificate.
+    verify_hostname: bool,
+}
+
+impl TlsConfig {
+    /// Creates the default options, which trust the system's roots and
+    /// verify the hostname.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts the CA certificate in the PEM file, in place of the
+    /// system's roots.
+    pub fn ca_cert<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.ca_cert = Some(path.into());
+        self
+    }
+
+    /// Authenticates to the server with the client certificate and
+    /// private key in the PEM files.
+    pub fn client_cert<C, K>(mut self, cert: C, key: K) -> Self
+    where
+        C: Into<PathBuf>,
+        K: Into<PathBuf>,
+    {
+        self.client_cert = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Sets whether to verify that the server's hostname matches its
+    /// certificate. This is on by default. Turning it off still encrypts
+    /// the link, but makes it open to an impostor.
+    pub fn verify_hostname(mut self, on: bool) -> Self {
+        self.verify_hostname = on;
+        self
+    }
+
+    /// Creates a Redis client with the connection info and these options.
+    /// The URL must use the `rediss://` scheme.
+    pub(crate) fn open_client(&self, info: ConnectionInfo) -> Result<Client> {
+        let root_cert = self.ca_cert.as_deref().map(read_pem).transpose()?;
+        let client_cert = match self.client_cert.as_ref() {
+            Some((cert, key)) => Some((read_pem(cert)?, read_pem(key)?)),
+            None => None,
+        };
+        Ok(adapter::open_tls_client(
+            info,
+            root_cert,
+            client_cert,
+            !self.verify_hostname,
+        )?)
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            ca_cert: None,
+            client_cert: None,
+            verify_hostname: true,
+        }
+    }
+}
+
+/// Reads a PEM file.
+fn read_pem(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| Error::TlsFile(path.display().to_string(), e))
+}
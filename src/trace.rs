@@ -0,0 +1,551 @@
+// mqtt.rust.redis/src/trace.rs
+//
+// Tracing of the persistence calls from the MQTT client.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Tracing of the persistence calls from the MQTT client.
+//!
+//! To reproduce a problem with the persistence, in this crate or in the
+//! Paho library, it helps to know the exact sequence of calls that the
+//! client made. A [`TracePersistence`] wraps any persistence, recording
+//! each call, with its arguments and result, to a [`Recorder`], before
+//! forwarding it. Wrap a [`MemoryPersistence`](crate::MemoryPersistence)
+//! to trace the calls without touching a Redis server at all.
+//!
+//! The recorder keeps the latest events in a ring buffer, and can also
+//! append them to a file, one per line, in a plain text format that can
+//! be attached to a bug report:
+//!
+//! ```text
+//! 1 52 open client%20one tcp://localhost:1883 => ok
+//! 2 340 put s-1 [4,12] => ok
+//! 3 501 get s-1 => value 16
+//! ```
+//!
+//! Each line has the sequence number, the time since the recorder was
+//! created, in microseconds, the call, and its result. Strings are
+//! percent-escaped as in the store names, and, if the recorder is set to
+//! record them, values are shown in hex.
+
+use crate::name;
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
+};
+use thiserror::Error;
+
+/// The characters reserved in the fields of the text format, as the
+/// list delimiters.
+const RESERVED: &[char] = &[',', '[', ']'];
+
+/// A call to the persistence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceCall {
+    /// The store was opened.
+    Open {
+        /// The client ID
+        client_id: String,
+        /// The server URI
+        server_uri: String,
+    },
+    /// The store was closed.
+    Close,
+    /// A value was put into the store.
+    Put {
+        /// The key
+        key: String,
+        /// The sizes of the buffers making up the value.
+        sizes: Vec<usize>,
+        /// The value, if values are being recorded.
+        data: Option<Vec<u8>>,
+    },
+    /// A value was read from the store.
+    Get {
+        /// The key
+        key: String,
+    },
+    /// A value was removed from the store.
+    Remove {
+        /// The key
+        key: String,
+    },
+    /// The keys in the store were requested.
+    Keys,
+    /// The store was cleared.
+    Clear,
+    /// The store was checked for a key.
+    ContainsKey {
+        /// The key
+        key: String,
+    },
+}
+
+/// The result of a call to the persistence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceResult {
+    /// The call succeeded.
+    Ok,
+    /// A value was read, of the given length.
+    Value {
+        /// The length of the value.
+        len: usize,
+        /// The value, if values are being recorded.
+        data: Option<Vec<u8>>,
+    },
+    /// The keys in the store.
+    Keys(Vec<String>),
+    /// The answer to whether the store contains a key.
+    Bool(bool),
+    /// The call failed, with the error message.
+    Err(String),
+}
+
+impl TraceResult {
+    /// Determines if an actual result matches this recorded one.
+    ///
+    /// The keys are compared as a set, since the order isn't defined, and
+    /// the errors just have to both be errors, since different stores
+    /// report them differently. A value matches on its length, and its
+    /// contents if they were recorded.
+    pub fn matches(&self, actual: &TraceResult) -> bool {
+        use TraceResult::*;
+        match (self, actual) {
+            (Err(_), Err(_)) => true,
+            (Keys(a), Keys(b)) => {
+                let mut a = a.clone();
+                let mut b = b.clone();
+                a.sort();
+                b.sort();
+                a == b
+            }
+            (Value { len, data }, Value { len: n, data: d }) => {
+                len == n && (data.is_none() || d.is_none() || data == d)
+            }
+            (a, b) => a == b,
+        }
+    }
+}
+
+/// A single, recorded, call to the persistence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// The sequence number of the call, starting at one.
+    pub seq: u64,
+    /// The time of the call since the recorder was created, in
+    /// microseconds.
+    pub micros: u64,
+    /// The call.
+    pub call: TraceCall,
+    /// The result of the call.
+    pub result: TraceResult,
+}
+
+/// Writes a string field, escaped.
+fn write_str(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str(&name::escape(s, RESERVED))
+}
+
+/// Writes a binary value in hex, as `0x...`.
+fn write_hex(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    f.write_str("0x")?;
+    for b in data {
+        write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for TraceCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceCall::Open {
+                client_id,
+                server_uri,
+            } => {
+                f.write_str("open ")?;
+                write_str(f, client_id)?;
+                f.write_str(" ")?;
+                write_str(f, server_uri)
+            }
+            TraceCall::Close => f.write_str("close"),
+            TraceCall::Put { key, sizes, data } => {
+                f.write_str("put ")?;
+                write_str(f, key)?;
+                let sizes: Vec<String> = sizes.iter().map(usize::to_string).collect();
+                write!(f, " [{}]", sizes.join(","))?;
+                if let Some(data) = data {
+                    f.write_str(" ")?;
+                    write_hex(f, data)?;
+                }
+                Ok(())
+            }
+            TraceCall::Get { key } => {
+                f.write_str("get ")?;
+                write_str(f, key)
+            }
+            TraceCall::Remove { key } => {
+                f.write_str("remove ")?;
+                write_str(f, key)
+            }
+            TraceCall::Keys => f.write_str("keys"),
+            TraceCall::Clear => f.write_str("clear"),
+            TraceCall::ContainsKey { key } => {
+                f.write_str("contains ")?;
+                write_str(f, key)
+            }
+        }
+    }
+}
+
+impl fmt::Display for TraceResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceResult::Ok => f.write_str("ok"),
+            TraceResult::Value { len, data } => {
+                write!(f, "value {}", len)?;
+                if let Some(data) = data {
+                    f.write_str(" ")?;
+                    write_hex(f, data)?;
+                }
+                Ok(())
+            }
+            TraceResult::Keys(keys) => {
+                f.write_str("keys [")?;
+                for (i, key) in keys.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_str(f, key)?;
+                }
+                f.write_str("]")
+            }
+            TraceResult::Bool(b) => write!(f, "{}", b),
+            TraceResult::Err(msg) => {
+                f.write_str("err ")?;
+                write_str(f, msg)
+            }
+        }
+    }
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} => {}",
+            self.seq, self.micros, self.call, self.result
+        )
+    }
+}
+
+/// An error parsing a line of the text format of a trace.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid trace event: '{0}'")]
+pub struct ParseTraceError(String);
+
+/// Reads a string field, unescaping it.
+fn parse_str(s: &str) -> Option<String> {
+    name::unescape(s)
+}
+
+/// Reads a binary value in hex, as `0x...`.
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let hex = s.strip_prefix("0x")?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Reads a bracketed list, like `[a,b]`, parsing each item.
+fn parse_list<T, F>(s: &str, f: F) -> Option<Vec<T>>
+where
+    F: Fn(&str) -> Option<T>,
+{
+    let items = s.strip_prefix('[')?.strip_suffix(']')?;
+    if items.is_empty() {
+        return Some(Vec::new());
+    }
+    items.split(',').map(f).collect()
+}
+
+impl TraceCall {
+    /// Parses a call from the fields of its text format.
+    fn parse(fields: &[&str]) -> Option<TraceCall> {
+        let key = || fields.get(1).and_then(|s| parse_str(s));
+        let call = match *fields.first()? {
+            "open" => TraceCall::Open {
+                client_id: key()?,
+                server_uri: parse_str(fields.get(2)?)?,
+            },
+            "close" => TraceCall::Close,
+            "put" => TraceCall::Put {
+                key: key()?,
+                sizes: parse_list(fields.get(2)?, |s| s.parse().ok())?,
+                data: match fields.get(3) {
+                    Some(s) => Some(parse_hex(s)?),
+                    None => None,
+                },
+            },
+            "get" => TraceCall::Get { key: key()? },
+            "remove" => TraceCall::Remove { key: key()? },
+            "keys" => TraceCall::Keys,
+            "clear" => TraceCall::Clear,
+            "contains" => TraceCall::ContainsKey { key: key()? },
+            _ => return None,
+        };
+        Some(call)
+    }
+}
+
+impl TraceResult {
+    /// Parses a result from the fields of its text format.
+    fn parse(fields: &[&str]) -> Option<TraceResult> {
+        let res = match *fields.first()? {
+            "ok" => TraceResult::Ok,
+            "value" => TraceResult::Value {
+                len: fields.get(1)?.parse().ok()?,
+                data: match fields.get(2) {
+                    Some(s) => Some(parse_hex(s)?),
+                    None => None,
+                },
+            },
+            "keys" => TraceResult::Keys(parse_list(fields.get(1)?, parse_str)?),
+            "true" => TraceResult::Bool(true),
+            "false" => TraceResult::Bool(false),
+            "err" => TraceResult::Err(parse_str(fields.get(1).unwrap_or(&""))?),
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+impl FromStr for TraceEvent {
+    type Err = ParseTraceError;
+
+    /// Parses an event from a line of the text format.
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (call, result) = line.trim().split_once(" => ")?;
+            let call: Vec<&str> = call.split_whitespace().collect();
+            let result: Vec<&str> = result.split_whitespace().collect();
+            Some(TraceEvent {
+                seq: call.first()?.parse().ok()?,
+                micros: call.get(1)?.parse().ok()?,
+                call: TraceCall::parse(call.get(2..)?)?,
+                result: TraceResult::parse(&result)?,
+            })
+        };
+        parse().ok_or_else(|| ParseTraceError(line.to_string()))
+    }
+}
+
+/// Reads the events from a trace in the text format, skipping any blank
+/// lines.
+pub fn read_trace<R: io::BufRead>(rdr: R) -> io::Result<Vec<TraceEvent>> {
+    let mut events = Vec::new();
+    for line in rdr.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let ev = line
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        events.push(ev);
+    }
+    Ok(events)
+}
+
+/// The state of a recorder.
+struct Recording {
+    /// The time the recorder was created.
+    start: Instant,
+    /// The sequence number of the last event.
+    seq: u64,
+    /// The latest events.
+    events: VecDeque<TraceEvent>,
+    /// The maximum number of events to keep.
+    capacity: usize,
+    /// Whether to record the values put into, and read from, the store.
+    values: bool,
+    /// The file to append the events to, if any.
+    file: Option<BufWriter<File>>,
+}
+
+/// A recorder of persistence calls.
+///
+/// This is a handle to a shared recording, so the application can keep a
+/// clone to read the events while the MQTT client owns the persistence.
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Arc<Mutex<Recording>>,
+}
+
+impl Recorder {
+    /// Creates a recorder that keeps the latest `capacity` events.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Recording {
+                start: Instant::now(),
+                seq: 0,
+                events: VecDeque::new(),
+                capacity,
+                values: false,
+                file: None,
+            })),
+        }
+    }
+
+    /// Creates a recorder that keeps the latest `capacity` events, and
+    /// also appends every event to the file at `path`.
+    pub fn with_file<P: AsRef<Path>>(capacity: usize, path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let rec = Self::new(capacity);
+        rec.lock().file = Some(BufWriter::new(file));
+        Ok(rec)
+    }
+
+    /// Sets whether to record the values put into, and read from, the
+    /// store, rather than just their sizes. This is off by default, since
+    /// the values are the application's message payloads.
+    pub fn record_values(&self, on: bool) {
+        self.lock().values = on;
+    }
+
+    /// Determines if the recorder is set to record values.
+    pub fn is_recording_values(&self) -> bool {
+        self.lock().values
+    }
+
+    /// Locks the recording, ignoring a poisoned lock.
+    fn lock(&self) -> MutexGuard<'_, Recording> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Gets the events in the ring buffer, oldest first.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.lock().events.iter().cloned().collect()
+    }
+
+    /// Clears the events from the ring buffer.
+    pub fn clear(&self) {
+        self.lock().events.clear();
+    }
+
+    /// Flushes the events written to the file, if any.
+    pub fn flush(&self) -> io::Result<()> {
+        match self.lock().file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Records a call and its result.
+    pub fn record(&self, call: TraceCall, result: TraceResult) {
+        let mut rec = self.lock();
+        rec.seq += 1;
+        let ev = TraceEvent {
+            seq: rec.seq,
+            micros: rec.start.elapsed().as_micros() as u64,
+            call,
+            result,
+        };
+
+        if let Some(file) = rec.file.as_mut() {
+            if let Err(e) = writeln!(file, "{}", ev) {
+                warn!("Error writing the persistence trace: {}", e);
+            }
+        }
+        if rec.capacity > 0 {
+            if rec.events.len() == rec.capacity {
+                rec.events.pop_front();
+            }
+            rec.events.push_back(ev);
+        }
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rec = self.lock();
+        f.debug_struct("Recorder")
+            .field("seq", &rec.seq)
+            .field("capacity", &rec.capacity)
+            .field("values", &rec.values)
+            .field("file", &rec.file.is_some())
+            .finish()
+    }
+}
+
+/// A persistence wrapper that records every call to a [`Recorder`], then
+/// forwards it to the inner persistence.
+#[cfg(feature = "paho-mqtt")]
+pub struct TracePersistence<P> {
+    /// The persistence that does the work.
+    pub(crate) inner: P,
+    /// The recorder for the calls.
+    pub(crate) recorder: Recorder,
+}
+
+#[cfg(feature = "paho-mqtt")]
+impl<P> TracePersistence<P> {
+    /// Creates a wrapper that records the calls to `inner`.
+    pub fn new(inner: P, recorder: Recorder) -> Self {
+        Self { inner, recorder }
+    }
+
+    /// Gets the recorder for the calls.
+    pub fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    /// Gets the inner persistence.
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Unwraps the inner persistence.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
@@ -0,0 +1,164 @@
+// mqtt.rust.redis/src/tracking.rs
+//
+// Server-assisted client-side caching of the store's keys.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Server-assisted client-side caching of the store's keys.
+//!
+//! With client tracking, the Redis server tells the client when a key
+//! that it's interested in changes, so the client can cache what it read
+//! for as long as it hasn't heard otherwise. For a store, that's the key
+//! set of its hash, which answers `keys()` and `contains_key()` without a
+//! round trip, even when another client writes to the store as well.
+//!
+//! The redis crate speaks RESP2, so the invalidations can't be pushed on
+//! the store's own connection. The tracker opens a second one, which
+//! subscribes to the invalidation channel, and the store's connection has
+//! its invalidations redirected to it, in broadcast mode, for the keys
+//! under the name of the hash. The store's own writes aren't reported
+//! back to it, since it keeps its key set up to date itself.
+
+use crate::{adapter, Result};
+use redis::Client;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The name of the tracking thread, as seen in a debugger or profiler.
+pub const THREAD_NAME: &str = "mqtt-redis-tracking";
+
+/// How often the listener checks if it's been stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The state shared between the tracker and its thread.
+#[derive(Debug, Default)]
+struct Shared {
+    /// Whether a change to the store was reported since it was last taken.
+    stale: AtomicBool,
+    /// Whether the tracker has lost its connection.
+    lost: AtomicBool,
+    /// Flag to stop the listener thread.
+    stop: AtomicBool,
+}
+
+/// A listener for the invalidations of a store's hash, in a background
+/// thread, which runs until the tracker is dropped.
+#[derive(Debug)]
+pub(crate) struct Tracker {
+    /// The client ID of the listener's connection, to redirect to.
+    id: u64,
+    /// The state shared with the listener thread.
+    shared: Arc<Shared>,
+    /// The listener thread.
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Tracker {
+    /// Opens the connection for the invalidations of the hash `name`, and
+    /// starts listening for them in a background thread.
+    pub fn start(client: &Client, name: &str) -> Result<Self> {
+        let mut conn = adapter::connect(client)?;
+        let id = adapter::client_id(&mut conn)?;
+        adapter::subscribe(&mut conn, adapter::INVALIDATE_CHANNEL)?;
+        conn.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let shared = Arc::new(Shared::default());
+        let thread = {
+            let shared = Arc::clone(&shared);
+            let name = name.to_string();
+            thread::Builder::new()
+                .name(THREAD_NAME.to_string())
+                .spawn(move || listen(conn, name, shared))?
+        };
+        debug!("Tracking the keys of {} as client {}", name, id);
+        Ok(Self {
+            id,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Gets the client ID that the invalidations are redirected to.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Determines if the tracker lost its connection, after which it
+    /// can't report any more changes.
+    pub fn is_lost(&self) -> bool {
+        self.shared.lost.load(Ordering::Relaxed)
+    }
+
+    /// Determines if a change to the store was reported since the last
+    /// time this was called.
+    pub fn take_stale(&self) -> bool {
+        self.shared.stale.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The body of the listener thread.
+fn listen(mut conn: redis::Connection, name: String, shared: Arc<Shared>) {
+    while !shared.stop.load(Ordering::Relaxed) {
+        match adapter::recv_invalidation(&mut conn) {
+            // A flush of the database comes without any keys
+            Ok(Some(keys)) if keys.is_empty() || keys.contains(&name) => {
+                trace!("The keys of {} were invalidated", name);
+                shared.stale.store(true, Ordering::Relaxed);
+            }
+            Ok(_) => (),
+            Err(e) if e.is_timeout() => (),
+            Err(e) => {
+                warn!("Redis persistence tracking error: {:?}", e);
+                break;
+            }
+        }
+    }
+    shared.lost.store(true, Ordering::Relaxed);
+    shared.stale.store(true, Ordering::Relaxed);
+    debug!("Tracking of {} done", name);
+}
@@ -0,0 +1,345 @@
+// mqtt.rust.redis/src/write_behind.rs
+//
+// Writing puts behind, from a bounded queue, in a background thread.
+//
+// --------------------------------------------------------------------------
+// Copyright (c) 2017-2023 Frank Pagliughi <fpagliughi@mindspring.com>
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright notice,
+// this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its
+// contributors may be used to endorse or promote products derived from this
+// software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+
+//! Writing puts behind, from a bounded queue, in a background thread.
+//!
+//! With write-behind, a put hands the value to a writer thread and
+//! returns right away, rather than waiting on the round trip to the
+//! server. The queue is bounded, so a put only waits if the writer falls
+//! that far behind. The price is a small window in which a put that has
+//! returned isn't on the server yet, and would be lost with the process.
+//!
+//! The other operations wait for the queue to drain before they run, so
+//! they see the store in the order the calls were made. An error from a
+//! queued put can't go back to its caller, so it's logged, and the first
+//! one is kept for the next flush.
+
+use crate::{store::Store, Error, Result};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, MutexGuard, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The name of the writer thread, as seen in a debugger or profiler.
+pub const THREAD_NAME: &str = "mqtt-redis-writer";
+
+/// How often to try a full queue, while flushing with a deadline.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A request to the writer thread.
+enum Msg {
+    /// Puts the value for the key into the store.
+    Put(String, Vec<u8>),
+    /// Acknowledges that all the puts queued before it are done.
+    Flush(mpsc::Sender<()>),
+}
+
+/// The state shared with the writer thread.
+#[derive(Default)]
+struct Shared {
+    /// The number of puts queued, but not yet done.
+    pending: AtomicUsize,
+    /// The first error from a queued put since the last flush.
+    error: Mutex<Option<Error>>,
+}
+
+impl Shared {
+    /// Keeps the error from a queued put, unless there's one already.
+    fn failed(&self, err: Error) {
+        let mut error = self.error.lock().unwrap_or_else(|e| e.into_inner());
+        if error.is_none() {
+            *error = Some(err);
+        }
+    }
+}
+
+/// The queue to a running writer thread.
+struct Writer {
+    /// The end of the queue for new requests.
+    /// This is dropped to stop the thread.
+    tx: Option<mpsc::SyncSender<Msg>>,
+    /// The writer thread.
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Writer {
+    /// Starts a writer thread for the store, with a queue of up to
+    /// `capacity` puts.
+    fn start(store: &Arc<Mutex<Store>>, capacity: usize, shared: &Arc<Shared>) -> Result<Self> {
+        let (tx, rx) = mpsc::sync_channel(capacity.max(1));
+        let store = Arc::downgrade(store);
+        let thread = {
+            let shared = Arc::clone(shared);
+            thread::Builder::new()
+                .name(THREAD_NAME.to_string())
+                .spawn(move || write(rx, store, shared))?
+        };
+        debug!("Writing puts behind, with a queue of {}", capacity);
+        Ok(Self {
+            tx: Some(tx),
+            thread: Some(thread),
+        })
+    }
+
+    /// Blocks until all the puts queued so far are done.
+    fn flush(&self) {
+        if let Some(tx) = self.tx.as_ref() {
+            let (ack_tx, ack_rx) = mpsc::channel();
+            if tx.send(Msg::Flush(ack_tx)).is_ok() {
+                let _ = ack_rx.recv();
+            }
+        }
+    }
+
+    /// Blocks until all the puts queued so far are done, or fails with
+    /// `Error::Timeout` at the deadline.
+    fn flush_until(&self, deadline: Instant) -> Result<()> {
+        let tx = match self.tx.as_ref() {
+            Some(tx) => tx,
+            None => return Ok(()),
+        };
+        let (ack_tx, ack_rx) = mpsc::channel();
+        let mut msg = Msg::Flush(ack_tx);
+        loop {
+            match tx.try_send(msg) {
+                Ok(()) => break,
+                Err(mpsc::TrySendError::Full(m)) if Instant::now() < deadline => {
+                    msg = m;
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Err(mpsc::TrySendError::Full(_)) => return Err(Error::Timeout),
+                Err(mpsc::TrySendError::Disconnected(_)) => return Ok(()),
+            }
+        }
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match ack_rx.recv_timeout(timeout) {
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Writer {
+    /// Closes the queue, and waits for the thread to finish the puts that
+    /// are still in it.
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The write-behind for a store, shared by all the handles to it.
+///
+/// This is kept out of the store's lock, so a put can be queued while the
+/// writer, or anyone else, holds it.
+#[derive(Clone, Default)]
+pub(crate) struct WriteBehind {
+    /// The writer, if write-behind is on.
+    writer: Arc<Mutex<Option<Writer>>>,
+    /// The state shared with the writer thread.
+    shared: Arc<Shared>,
+}
+
+impl WriteBehind {
+    /// Locks the writer, ignoring any poison since it's just the queue.
+    fn lock(&self) -> MutexGuard<'_, Option<Writer>> {
+        self.writer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Turns on write-behind for the store, with a queue of up to
+    /// `capacity` puts, or turns it off if `None`. Any puts queued with
+    /// the old setting are done first.
+    pub fn set(&self, store: &Arc<Mutex<Store>>, capacity: Option<usize>) -> Result<()> {
+        let mut writer = self.lock();
+        *writer = None;
+        if let Some(capacity) = capacity {
+            *writer = Some(Writer::start(store, capacity, &self.shared)?);
+        }
+        Ok(())
+    }
+
+    /// Determines if write-behind is on.
+    pub fn is_on(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    /// Gets the number of puts queued, but not yet done.
+    pub fn pending(&self) -> usize {
+        self.shared.pending.load(Ordering::SeqCst)
+    }
+
+    /// Queues the put, if write-behind is on, blocking only while the
+    /// queue is full. This returns `false` if the put wasn't queued, and
+    /// so should be done by the caller.
+    pub fn put(&self, key: &str, buffers: &[&[u8]]) -> bool {
+        let writer = self.lock();
+        let tx = match writer.as_ref().and_then(|w| w.tx.as_ref()) {
+            Some(tx) => tx,
+            None => return false,
+        };
+        self.shared.pending.fetch_add(1, Ordering::SeqCst);
+        if tx
+            .send(Msg::Put(key.to_string(), buffers.concat()))
+            .is_err()
+        {
+            self.shared.pending.fetch_sub(1, Ordering::SeqCst);
+            return false;
+        }
+        true
+    }
+
+    /// Blocks until all the puts queued so far are done, if there are any.
+    pub fn settle(&self) {
+        if self.pending() > 0 {
+            if let Some(writer) = self.lock().as_ref() {
+                writer.flush();
+            }
+        }
+    }
+
+    /// Blocks until all the puts queued so far are done, and reports the
+    /// first error from a queued put since the last flush.
+    pub fn flush(&self) -> Result<()> {
+        self.settle();
+        self.take_error()
+    }
+
+    /// Like [`flush()`](Self::flush), but fails with `Error::Timeout` if
+    /// the queued puts aren't done by the deadline.
+    pub fn flush_until(&self, deadline: Instant) -> Result<()> {
+        if self.pending() > 0 {
+            if let Some(writer) = self.lock().as_ref() {
+                writer.flush_until(deadline)?;
+            }
+        }
+        self.take_error()
+    }
+
+    /// Takes the first error from a queued put since the last flush.
+    fn take_error(&self) -> Result<()> {
+        let err = self
+            .shared
+            .error
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        match err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The body of the writer thread.
+/// This runs until the queue is closed, doing each put in turn.
+fn write(rx: mpsc::Receiver<Msg>, store: Weak<Mutex<Store>>, shared: Arc<Shared>) {
+    for msg in rx {
+        match msg {
+            Msg::Put(key, value) => {
+                let res = match store.upgrade() {
+                    Some(store) => store
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .put(&key, &[value.as_slice()]),
+                    None => Err(Error::NotOpen),
+                };
+                if let Err(e) = res {
+                    warn!(
+                        "Redis persistence write-behind put of '{}' failed: {:?}",
+                        key, e
+                    );
+                    shared.failed(e);
+                }
+                shared.pending.fetch_sub(1, Ordering::SeqCst);
+            }
+            Msg::Flush(ack) => {
+                let _ = ack.send(());
+            }
+        }
+    }
+    debug!("Write-behind thread done");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fake_server::{FakeServer, Value},
+        name, RedisPersistence,
+    };
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_last_handle_flushes() {
+        let server = FakeServer::start();
+        let persist = RedisPersistence::from_url(&server.url()).unwrap();
+        persist.open("client", "tcp://localhost:1883").unwrap();
+        persist.set_write_behind(Some(100)).unwrap();
+
+        // A slow server, so the puts are still queued when the handles go
+        server.db().set_hook(|cmd| {
+            if cmd == "HSET" {
+                thread::sleep(Duration::from_millis(5));
+            }
+            true
+        });
+
+        let keys = ["a", "b", "c", "d", "e"];
+        for key in &keys {
+            persist.put(key, &[key.as_bytes()]).unwrap();
+        }
+        assert!(persist.pending_writes() > 0);
+
+        drop(persist.clone());
+        drop(persist);
+
+        let hash = name::store_name("client", "tcp://localhost:1883");
+        let db = server.db();
+        match db.get(&hash) {
+            Some(Value::Hash(h)) => {
+                for key in &keys {
+                    assert_eq!(h.get(key.as_bytes()).unwrap(), key.as_bytes());
+                }
+            }
+            v => panic!("not a hash: {:?}", v),
+        }
+    }
+}